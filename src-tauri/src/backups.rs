@@ -0,0 +1,101 @@
+//! Copies of the ROM taken before it's saved, following the user's [`BackupPolicy`].
+//!
+//! The copies of `rom.gba` are kept in the `rom.gba.backups` folder next to it,
+//! named `rom.gba.<milliseconds since the Unix epoch>.bak`.
+
+use std::{
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{error::AppError, settings::BackupPolicy, state::AppResult};
+
+/// Copies the ROM file at the given path to its backups folder,
+/// deleting the oldest copies past the policy's limit.
+pub fn back_up_rom(path: &str, policy: &BackupPolicy) -> AppResult<()> {
+    let rom_path = Path::new(path);
+    let Some(file_name) = rom_path.file_name().and_then(|name| name.to_str()) else {
+        return Err(AppError::file(path, "The ROM path has no file name"));
+    };
+
+    let folder = format!("{}.backups", path);
+    std::fs::create_dir_all(&folder).map_err(|e| {
+        AppError::file(
+            &folder,
+            format!("Could not create the backups folder: {}", e),
+        )
+    })?;
+
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis())
+        .unwrap_or(0);
+    let backup_path = Path::new(&folder).join(format!("{}.{:013}.bak", file_name, time));
+    std::fs::copy(rom_path, &backup_path).map_err(|e| {
+        AppError::file(
+            backup_path.display().to_string(),
+            format!("Could not back up the ROM: {}", e),
+        )
+    })?;
+
+    prune_backups(Path::new(&folder), file_name, policy.max_backups as usize);
+    Ok(())
+}
+
+/// Deletes the oldest backups of the file, keeping `max` of them.
+fn prune_backups(folder: &Path, file_name: &str, max: usize) {
+    let Ok(entries) = std::fs::read_dir(folder) else {
+        return;
+    };
+
+    // The names only differ in the fixed width time, so they sort by it
+    let prefix = format!("{}.", file_name);
+    let mut backups: Vec<_> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let name = path.file_name().and_then(|name| name.to_str());
+            name.is_some_and(|name| name.starts_with(&prefix) && name.ends_with(".bak"))
+        })
+        .collect();
+    backups.sort();
+
+    let excess = backups.len().saturating_sub(max);
+    for path in &backups[..excess] {
+        if let Err(err) = std::fs::remove_file(path) {
+            println!("Could not delete backup {}: {}", path.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn old_backups_are_deleted() {
+        let folder = std::env::temp_dir().join(format!("polythree-backups-{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        let rom_path = folder.join("rom.gba");
+        std::fs::write(&rom_path, [1, 2, 3]).unwrap();
+
+        let policy = BackupPolicy {
+            enabled: true,
+            max_backups: 2,
+        };
+        let path = rom_path.to_str().unwrap();
+        for _ in 0..3 {
+            back_up_rom(path, &policy).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+
+        let backups: Vec<_> = std::fs::read_dir(format!("{}.backups", path))
+            .unwrap()
+            .flatten()
+            .map(|entry| std::fs::read(entry.path()).unwrap())
+            .collect();
+        assert_eq!(backups, [vec![1, 2, 3], vec![1, 2, 3]]);
+
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+}
@@ -0,0 +1,219 @@
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::AppError, state::AppResult};
+
+/// Metatile of the blocks a brush does not paint.
+pub const NULL_METATILE: u16 = 0xFFFF;
+
+/// A brush as saved by the map editor's `brush_serialization.ts`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "SerializedBrush", into = "SerializedBrush")]
+pub struct Brush {
+    /// Identifies the brush in its tileset's list, assigned when it is added
+    pub id: u32,
+    pub name: String,
+    pub pinned: bool,
+    /// The primary tileset offset
+    pub primary: u32,
+    /// The secondary tileset offset, if the brush uses its metatiles
+    pub secondary: Option<u32>,
+    pub kind: BrushKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BrushKind {
+    /// Paints the same blocks every time
+    Simple(BlocksData),
+    /// Paints paths of any shape with the corners, sides and center.
+    /// The blocks are the ones shown in the nine-patch editor, in order.
+    NinePatch {
+        metatiles: Vec<u16>,
+        permissions: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlocksData {
+    pub width: usize,
+    pub height: usize,
+    /// Where [`NULL_METATILE`] is a block the brush does not paint
+    pub metatiles: Vec<u16>,
+    pub permissions: Vec<u8>,
+}
+
+/// The number of blocks of a nine-patch brush, the ones in the editor's
+/// template from `NINEPATCH_DEFAULT_BLOCKS` in `brushes.ts`.
+const NINE_PATCH_BLOCKS: usize = 38;
+
+/// The `type` of a brush in `brushes.ts`
+const SIMPLE_BRUSH: u8 = 0;
+const NINE_PATCH_BRUSH: u8 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct SerializedBrush {
+    #[serde(default)]
+    id: u32,
+    #[serde(rename = "type")]
+    brush_type: u8,
+    name: String,
+    #[serde(default)]
+    pinned: bool,
+    primary: u32,
+    #[serde(default)]
+    secondary: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    blocks: Option<BlocksData>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metatiles: Option<Vec<u16>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    permissions: Option<Vec<u8>>,
+}
+
+impl TryFrom<SerializedBrush> for Brush {
+    type Error = AppError;
+
+    fn try_from(brush: SerializedBrush) -> AppResult<Self> {
+        let kind = match brush.brush_type {
+            SIMPLE_BRUSH => BrushKind::Simple(brush.blocks.ok_or_else(|| {
+                AppError::invalid_input(format!("Simple brush \"{}\" has no blocks", brush.name))
+            })?),
+            NINE_PATCH_BRUSH => BrushKind::NinePatch {
+                metatiles: brush.metatiles.unwrap_or_default(),
+                permissions: brush.permissions.unwrap_or_default(),
+            },
+            other => {
+                return Err(AppError::invalid_input(format!(
+                    "Invalid brush type {}",
+                    other
+                )))
+            }
+        };
+
+        let brush = Brush {
+            id: brush.id,
+            name: brush.name,
+            pinned: brush.pinned,
+            primary: brush.primary,
+            secondary: brush.secondary,
+            kind,
+        };
+        brush.check_shape()?;
+        Ok(brush)
+    }
+}
+
+impl From<Brush> for SerializedBrush {
+    fn from(brush: Brush) -> Self {
+        let (brush_type, blocks, metatiles, permissions) = match brush.kind {
+            BrushKind::Simple(blocks) => (SIMPLE_BRUSH, Some(blocks), None, None),
+            BrushKind::NinePatch {
+                metatiles,
+                permissions,
+            } => (NINE_PATCH_BRUSH, None, Some(metatiles), Some(permissions)),
+        };
+
+        SerializedBrush {
+            id: brush.id,
+            brush_type,
+            name: brush.name,
+            pinned: brush.pinned,
+            primary: brush.primary,
+            secondary: brush.secondary,
+            blocks,
+            metatiles,
+            permissions,
+        }
+    }
+}
+
+impl Brush {
+    /// Returns the metatiles the brush paints.
+    pub fn metatiles(&self) -> impl Iterator<Item = u16> + '_ {
+        let metatiles = match &self.kind {
+            BrushKind::Simple(blocks) => &blocks.metatiles,
+            BrushKind::NinePatch { metatiles, .. } => metatiles,
+        };
+        metatiles
+            .iter()
+            .copied()
+            .filter(|&metatile| metatile != NULL_METATILE)
+    }
+
+    /// Checks that the blocks have the right size for the brush.
+    fn check_shape(&self) -> AppResult<()> {
+        let (metatiles, permissions, expected) = match &self.kind {
+            BrushKind::Simple(blocks) => (
+                blocks.metatiles.len(),
+                blocks.permissions.len(),
+                blocks.width * blocks.height,
+            ),
+            BrushKind::NinePatch {
+                metatiles,
+                permissions,
+            } => (metatiles.len(), permissions.len(), NINE_PATCH_BLOCKS),
+        };
+
+        if metatiles != expected || permissions != expected {
+            return Err(AppError::invalid_input(format!(
+                "Brush \"{}\" has {} metatiles and {} permissions instead of {}",
+                self.name, metatiles, permissions, expected
+            )));
+        }
+        Ok(())
+    }
+
+    /// Checks that every metatile of the brush is in its tilesets, where the
+    /// secondary tileset's metatiles are the given range (empty if there is none).
+    pub fn validate(&self, primary_length: u16, secondary: Range<u16>) -> AppResult<()> {
+        self.check_shape()?;
+
+        match self
+            .metatiles()
+            .find(|m| *m >= primary_length && !secondary.contains(m))
+        {
+            Some(metatile) => Err(AppError::invalid_input(format!(
+                "Brush \"{}\" uses metatile {:#X}, which is not in its tilesets",
+                self.name, metatile
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nine_patch(blocks: usize) -> serde_json::Value {
+        serde_json::json!({
+            "type": NINE_PATCH_BRUSH,
+            "name": "Path",
+            "primary": 0x3DF2EC,
+            "metatiles": vec![1; blocks],
+            "permissions": vec![0; blocks],
+        })
+    }
+
+    #[test]
+    fn nine_patch_brushes_have_the_template_blocks() {
+        let brush: Brush = serde_json::from_value(nine_patch(NINE_PATCH_BLOCKS)).unwrap();
+        assert_eq!(brush.metatiles().count(), NINE_PATCH_BLOCKS);
+
+        for blocks in [0, 1, NINE_PATCH_BLOCKS + 1] {
+            assert!(serde_json::from_value::<Brush>(nine_patch(blocks)).is_err());
+        }
+    }
+
+    #[test]
+    fn simple_brushes_match_their_size() {
+        let brush = serde_json::json!({
+            "type": SIMPLE_BRUSH,
+            "name": "Tree",
+            "primary": 0x3DF2EC,
+            "blocks": { "width": 2, "height": 2, "metatiles": [1, 2, 3], "permissions": [0, 0, 0] },
+        });
+        assert!(serde_json::from_value::<Brush>(brush).is_err());
+    }
+}
@@ -1,23 +1,48 @@
 use std::{
     collections::{BTreeMap, HashMap},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
-use poly3lib::rom::RomType;
+use poly3lib::rom::{Rom, RomType};
 use serde::{Deserialize, Serialize, Serializer};
 use serde_json::Value;
 use tauri::AppHandle;
 
-use crate::state::{get_rom_path, AppResult, AppState};
+use crate::{
+    brushes::Brush,
+    error::{AppError, ErrorKind},
+    events::RomEvent,
+    state::{
+        get_config_path, get_rom_path, set_config_path, AppResult, AppState, AppStateFunctions,
+        RomHandle, RomState,
+    },
+};
+
+/// The current version of the config format, increased every time
+/// a change needs the old configs to be migrated.
+pub const CONFIG_VERSION: u32 = 3;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// The functions that upgrade a config to the next version,
+/// indexed by the version they upgrade from.
+const MIGRATIONS: [fn(&mut Value) -> AppResult<()>; CONFIG_VERSION as usize] =
+    [migrate_from_v0, migrate_from_v1, migrate_from_v2];
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct PrimaryBrushStore {
-    brushes: Vec<Value>,
-    secondary: HashMap<u32, Vec<Value>>,
+    /// The brushes that only use the primary tileset
+    pub brushes: Vec<Brush>,
+    /// The brushes that also use a secondary tileset, by its offset
+    pub secondary: HashMap<u32, Vec<Brush>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct RomConfig {
+    /// The version of the format the config was saved with.
+    pub version: u32,
+    /// The ROM the config was made for.
+    pub identity: Option<RomIdentity>,
     /// Associations of layout IDs to layout names.
     #[serde(serialize_with = "ordered_map")]
     pub layout_names: HashMap<u16, String>,
@@ -37,19 +62,126 @@ where
     ordered.serialize(serializer)
 }
 
+/// What identifies a ROM, to tell if a config was made for it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RomIdentity {
+    pub rom_type: String,
+    /// The 4 letters code in the ROM header
+    pub game_code: String,
+    /// Where the first map header and layout header are, as `"$HHHHHHH/$LLLLLLL"`.
+    ///
+    /// The contents of the tables change with every edit, and the tables
+    /// themselves are moved when they grow, but the structures of the maps
+    /// and layouts that already exist stay where they are, while hacks
+    /// usually have them somewhere else than the base game
+    #[serde(alias = "tables_hash")]
+    pub tables: String,
+}
+
+impl RomIdentity {
+    pub fn of(rom: &mut Rom) -> Self {
+        let game_code = rom
+            .data
+            .get(0xAC..0xB0)
+            .map(|code| String::from_utf8_lossy(code).into_owned())
+            .unwrap_or_default();
+
+        let describe = |offset: Option<usize>| match offset {
+            Some(offset) => format!("${:07X}", offset),
+            None => "-".to_owned(),
+        };
+        let headers = describe(rom.map_headers().get_header_offset(0, 0).ok());
+        let layouts = describe(rom.map_layouts().get_header_offset(1).ok());
+
+        Self {
+            rom_type: rom.rom_type.to_string(),
+            game_code,
+            tables: format!("{}/{}", headers, layouts),
+        }
+    }
+
+    /// Whether the identity comes from a config that only kept a hash
+    /// of the tables, which can't be compared with their offsets.
+    fn is_legacy(&self) -> bool {
+        !self.tables.contains('/')
+    }
+}
+
+/// A config that was made for a different ROM than the one it was opened with.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigMismatch {
+    /// The identity of the ROM the config was made for
+    pub config: RomIdentity,
+    /// The identity of the open ROM
+    pub rom: RomIdentity,
+}
+
+/// A file left in place of a config moved to a project folder.
+#[derive(Serialize, Deserialize)]
+struct ConfigLink {
+    project_config: String,
+}
+
+/// Returns the path of the config next to the ROM.
+fn default_config_path(rom_path: &str) -> String {
+    format!("{}.config.json", rom_path)
+}
+
+/// Returns the path of the config of a ROM, following the link
+/// to the project folder if the config was moved.
+fn locate_config(rom_path: &str) -> String {
+    let config_path = default_config_path(rom_path);
+
+    std::fs::read_to_string(&config_path)
+        .ok()
+        .and_then(|text| serde_json::from_str::<ConfigLink>(&text).ok())
+        .map(|link| link.project_config)
+        .unwrap_or(config_path)
+}
+
 impl RomConfig {
-    pub fn init(handle: AppHandle, rom_path: &str, rom_type: &RomType) -> AppResult<Self> {
-        let config_path = format!("{}.config.json", rom_path);
+    /// Loads the config of the ROM, or creates it if it does not exist,
+    /// returning it with its path and whether it was made for another ROM.
+    pub fn init(
+        handle: AppHandle,
+        rom_path: &str,
+        rom_type: &RomType,
+        identity: &RomIdentity,
+    ) -> AppResult<(Self, String, Option<ConfigMismatch>)> {
+        let config_path = locate_config(rom_path);
 
-        let config = if !Path::new(&config_path).exists() {
+        let mut config = if !Path::new(&config_path).exists() {
             RomConfig::default_for(handle, rom_type)
         } else {
             RomConfig::load(&config_path)?
         };
+
+        // Configs without an identity are bound to the first ROM they are opened with
+        let mismatch = match config.identity.as_ref() {
+            None => {
+                config.identity = Some(identity.clone());
+                None
+            }
+            Some(other) if other == identity => None,
+            // The older configs are bound again to the same game
+            Some(other)
+                if other.is_legacy()
+                    && other.rom_type == identity.rom_type
+                    && other.game_code == identity.game_code =>
+            {
+                config.identity = Some(identity.clone());
+                None
+            }
+            Some(other) => Some(ConfigMismatch {
+                config: other.clone(),
+                rom: identity.clone(),
+            }),
+        };
+
         // Write the config to file
         config.save(config_path.clone())?;
 
-        Ok(config)
+        Ok((config, config_path, mismatch))
     }
 
     pub fn default_for(handle: AppHandle, rom_type: &RomType) -> Self {
@@ -69,8 +201,16 @@ impl RomConfig {
             println!("Loaded config template from {}.", template_path_str);
 
             match serde_json::from_reader(template_file)
-                .map_err(|e| format!("Could not read config file: {}", e))
-            {
+                .map_err(|e| {
+                    AppError::file(
+                        template_path_str,
+                        format!("Could not read config file: {}", e),
+                    )
+                })
+                .and_then(|mut value| {
+                    migrate(&mut value)?;
+                    RomConfig::from_value(value)
+                }) {
                 Ok(config) => return config,
                 Err(e) => println!("Could not parse config file: {}", e),
             }
@@ -79,131 +219,382 @@ impl RomConfig {
         // Read the template
         println!("Could not load config template from {}", template_path_str);
         Self {
-            layout_names: HashMap::new(),
-            tileset_names: HashMap::new(),
-            tileset_levels: HashMap::new(),
-            brushes: HashMap::new(),
+            version: CONFIG_VERSION,
+            ..Default::default()
         }
     }
 
     pub fn save(&self, config_path: String) -> AppResult<()> {
-        let config_file = std::fs::File::create(config_path)
-            .map_err(|e| format!("Could create config file: {}", e))?;
-        serde_json::to_writer(config_file, self)
-            .map_err(|e| format!("Could not write to config file: {}", e))?;
+        let config_file = std::fs::File::create(&config_path).map_err(|e| {
+            AppError::file(&config_path, format!("Could create config file: {}", e))
+        })?;
+        serde_json::to_writer(config_file, self).map_err(|e| {
+            AppError::file(
+                &config_path,
+                format!("Could not write to config file: {}", e),
+            )
+        })?;
         Ok(())
     }
 
+    /// Loads the config, migrating it to the current version if it is older.
+    ///
+    /// Before a config is migrated, a copy of the old one is saved
+    /// next to it as `<config>.v<version>.bak`.
     pub fn load(config_path: &str) -> AppResult<Self> {
-        let config_file = std::fs::File::open(config_path)
-            .map_err(|e| format!("Could not open config file: {}", e))?;
-        serde_json::from_reader(config_file)
-            .map_err(|e| format!("Could not read config file: {}", e))
+        let config_file = std::fs::File::open(config_path).map_err(|e| {
+            AppError::file(config_path, format!("Could not open config file: {}", e))
+        })?;
+        let mut value: Value = serde_json::from_reader(config_file).map_err(|e| {
+            AppError::file(config_path, format!("Could not read config file: {}", e))
+        })?;
+
+        let version = config_version(&value)?;
+        if version < CONFIG_VERSION {
+            let backup_path = format!("{}.v{}.bak", config_path, version);
+            std::fs::copy(config_path, &backup_path).map_err(|e| {
+                AppError::file(
+                    &backup_path,
+                    format!("Could not back up the config file: {}", e),
+                )
+            })?;
+            println!(
+                "Migrating config from version {} to {}, the old one was saved to {}",
+                version, CONFIG_VERSION, backup_path
+            );
+
+            migrate(&mut value)?;
+        }
+
+        RomConfig::from_value(value)
+    }
+
+    /// Reads a config of the current version.
+    fn from_value(value: Value) -> AppResult<Self> {
+        let mut config: RomConfig = serde_json::from_value(value).map_err(|e| {
+            AppError::new(
+                ErrorKind::File,
+                format!("Could not read config file: {}", e),
+            )
+        })?;
+        config.version = CONFIG_VERSION;
+        Ok(config)
     }
 }
 
+/// Returns the version of a config, where configs without one are version 0.
+fn config_version(value: &Value) -> AppResult<u32> {
+    let version = value.get("version").and_then(Value::as_u64).unwrap_or(0) as u32;
+
+    if version > CONFIG_VERSION {
+        return Err(AppError::invalid_input(format!(
+            "The config was saved by a newer version of Polythree (config version {}, supported up to {})",
+            version, CONFIG_VERSION
+        )));
+    }
+    Ok(version)
+}
+
+/// Upgrades a config to the current version.
+fn migrate(value: &mut Value) -> AppResult<()> {
+    for version in config_version(value)?..CONFIG_VERSION {
+        MIGRATIONS[version as usize](value)?;
+        value["version"] = Value::from(version + 1);
+    }
+    Ok(())
+}
+
+/// Version 0 configs have the same fields as version 1, which only adds the version.
+fn migrate_from_v0(value: &mut Value) -> AppResult<()> {
+    if !value.is_object() {
+        return Err(AppError::invalid_input(
+            "The config file is not a JSON object",
+        ));
+    }
+    Ok(())
+}
+
+/// Version 2 adds the ROM identity, which is left empty so that
+/// the config is bound to the ROM the next time it is opened.
+fn migrate_from_v1(_value: &mut Value) -> AppResult<()> {
+    Ok(())
+}
+
+/// Version 3 stores typed brushes with an id, so every brush gets one
+/// and the ones that can't be read are dropped.
+fn migrate_from_v2(value: &mut Value) -> AppResult<()> {
+    let Some(stores) = value.get_mut("brushes").and_then(Value::as_object_mut) else {
+        return Ok(());
+    };
+
+    let mut next_id = 1;
+    for store in stores.values_mut() {
+        let Some(store) = store.as_object_mut() else {
+            continue;
+        };
+
+        let mut lists: Vec<&mut Value> = vec![];
+        for (key, field) in store.iter_mut() {
+            match (key.as_str(), field) {
+                ("brushes", brushes) => lists.push(brushes),
+                ("secondary", Value::Object(secondary)) => lists.extend(secondary.values_mut()),
+                _ => {}
+            }
+        }
+
+        for list in lists {
+            let Some(brushes) = list.as_array_mut() else {
+                *list = Value::Array(vec![]);
+                continue;
+            };
+
+            brushes.retain_mut(|brush| {
+                if brush.is_object() {
+                    brush["id"] = Value::from(next_id);
+                }
+                match serde_json::from_value::<Brush>(brush.clone()) {
+                    Ok(_) => {
+                        next_id += 1;
+                        true
+                    }
+                    Err(err) => {
+                        println!("Dropping a brush that could not be read: {}", err);
+                        false
+                    }
+                }
+            });
+        }
+    }
+    Ok(())
+}
+
 #[tauri::command]
-pub fn get_config(state: AppState) -> AppResult<RomConfig> {
-    // Unlock the ROM data
-    let config_data = state
+pub fn get_config(state: AppState, rom: RomHandle) -> AppResult<RomConfig> {
+    let state = state.rom(rom)?;
+    // Unlock the config data
+    let config = state
         .config
         .lock()
-        .map_err(|_| "Failed to unlock the config data")?;
-
-    // Get a mutable reference to the ROM data
-    let config = config_data.as_ref().ok_or("No ROM is open")?;
+        .map_err(|_| AppError::lock("config data"))?;
 
     Ok(config.clone())
 }
 
 #[tauri::command]
-pub fn set_config(state: AppState) -> AppResult<()> {
-    // Get the ROM path
-    let rom_path = get_rom_path(&state)?;
+pub fn set_config(state: AppState, rom: RomHandle, handle: AppHandle) -> AppResult<()> {
+    let state = state.rom(rom)?;
+    // Get the config path
+    let config_path = get_config_path(&state)?;
 
-    // Unlock the ROM data
-    let mut config_data = state
+    // Unlock the config data
+    let config = state
         .config
         .lock()
-        .map_err(|_| "Failed to unlock the config data")?;
+        .map_err(|_| AppError::lock("config data"))?;
 
-    // Get a mutable reference to the ROM data
-    let config = config_data.as_mut().ok_or("No ROM is open")?;
-
-    // Call the callback with the ROM
-    let config_path = format!("{}.config.json", rom_path);
     config.save(config_path)?;
+    RomEvent::ConfigChanged.emit(&handle, state.handle);
 
     Ok(())
 }
 
-pub fn update_config(state: AppState, callback: impl FnOnce(&mut RomConfig)) -> AppResult<()> {
-    let rom_path = get_rom_path(&state)?;
+pub fn update_config(state: &RomState, callback: impl FnOnce(&mut RomConfig)) -> AppResult<()> {
+    try_update_config(state, |config| {
+        callback(config);
+        Ok(())
+    })
+}
 
-    // Unlock the ROM data
-    let mut config_data = state
+/// Like [`update_config`], but the changes are only saved if the callback succeeds.
+pub fn try_update_config<T>(
+    state: &RomState,
+    callback: impl FnOnce(&mut RomConfig) -> AppResult<T>,
+) -> AppResult<T> {
+    let config_path = get_config_path(state)?;
+
+    // Unlock the config data
+    let mut config = state
         .config
         .lock()
-        .map_err(|_| "Failed to unlock the config data")
-        .unwrap();
+        .map_err(|_| AppError::lock("config data"))?;
 
-    // Get a mutable reference to the ROM data
-    let config = config_data.as_mut().ok_or("No ROM is open")?;
+    // Edit a copy, so that nothing changes if the callback fails
+    let mut edited = config.clone();
+    let result = callback(&mut edited)?;
 
-    // Call the callback with the ROM
-    callback(config);
+    // Save the changes
+    edited.save(config_path)?;
+    *config = edited;
 
-    // Call the callback with the ROM
-    let config_path = format!("{}.config.json", rom_path);
-    config.save(config_path)?;
+    Ok(result)
+}
 
-    Ok(())
+/// How to fix a config made for another ROM.
+#[derive(Debug, Deserialize)]
+pub enum ConfigResolution {
+    /// Keep the config, binding it to the open ROM
+    Rebind,
+    /// Replace the config with a new one, keeping a backup of the old one
+    StartFresh,
+    /// Replace the config with a copy of another config file
+    Load { path: String },
 }
 
 #[tauri::command]
-pub fn update_tileset_level(state: AppState, tileset: u32, levels: String) -> AppResult<()> {
-    update_config(state, |config| {
-        config.tileset_levels.insert(tileset, levels);
-    })
+pub fn resolve_config_mismatch(
+    state: AppState,
+    rom: RomHandle,
+    handle: AppHandle,
+    resolution: ConfigResolution,
+) -> AppResult<RomConfig> {
+    let state = state.rom(rom)?;
+    let mut new_config = match resolution {
+        ConfigResolution::Rebind => None,
+        ConfigResolution::StartFresh => {
+            let config_path = get_config_path(&state)?;
+            let backup_path = format!("{}.mismatch.bak", config_path);
+            std::fs::copy(&config_path, &backup_path).map_err(|e| {
+                AppError::file(
+                    &backup_path,
+                    format!("Could not back up the config file: {}", e),
+                )
+            })?;
+            Some(state.read_rom(|rom| Ok(RomConfig::default_for(handle.clone(), &rom.rom_type)))?)
+        }
+        ConfigResolution::Load { path } => Some(RomConfig::load(&path)?),
+    };
+    let identity = state.with_rom(|rom| Ok(RomIdentity::of(rom)))?;
+
+    let config = try_update_config(&state, |config| {
+        if let Some(new_config) = new_config.take() {
+            *config = new_config;
+        }
+        config.identity = Some(identity);
+        Ok(config.clone())
+    })?;
+    RomEvent::ConfigChanged.emit(&handle, state.handle);
+
+    Ok(config)
+}
+
+/// Moves the config to a project folder, leaving a link to it next to the ROM.
+/// Returns the new path of the config.
+#[tauri::command]
+pub fn move_config(state: AppState, rom: RomHandle, folder: String) -> AppResult<String> {
+    let state = state.rom(rom)?;
+    let rom_path = get_rom_path(&state)?;
+    let old_path = get_config_path(&state)?;
+    let default_path = default_config_path(&rom_path);
+
+    let file_name = Path::new(&default_path)
+        .file_name()
+        .ok_or_else(|| AppError::file(&rom_path, "Invalid ROM path"))?;
+    let new_path = PathBuf::from(&folder)
+        .join(file_name)
+        .to_string_lossy()
+        .into_owned();
+    if new_path == old_path {
+        return Ok(new_path);
+    }
+
+    std::fs::create_dir_all(&folder).map_err(|e| {
+        AppError::file(
+            &folder,
+            format!("Could not create the project folder: {}", e),
+        )
+    })?;
+    // Only use the new path once the config is there, holding
+    // the config so that no change is saved to the old one meanwhile
+    {
+        let config = state
+            .config
+            .lock()
+            .map_err(|_| AppError::lock("config data"))?;
+        config.save(new_path.clone())?;
+        set_config_path(&state, new_path.clone())?;
+    }
+
+    // Leave a link next to the ROM, unless the config was moved back there
+    if new_path != default_path {
+        let link = serde_json::to_string(&ConfigLink {
+            project_config: new_path.clone(),
+        })
+        .map_err(|e| {
+            AppError::file(
+                &default_path,
+                format!("Could not write the config link: {}", e),
+            )
+        })?;
+        std::fs::write(&default_path, link).map_err(|e| {
+            AppError::file(
+                &default_path,
+                format!("Could not write the config link: {}", e),
+            )
+        })?;
+    }
+    // Remove the config from its old project folder
+    if old_path != default_path {
+        if let Err(err) = std::fs::remove_file(&old_path) {
+            println!("Could not remove the old config {}: {}", old_path, err);
+        }
+    }
+
+    Ok(new_path)
 }
 
 #[tauri::command]
-pub fn update_brushes(
+pub fn update_tileset_level(
     state: AppState,
-    tileset1: u32,
-    tileset2: u32,
-    tileset1_brushes: Vec<Value>,
-    tileset2_brushes: Vec<Value>,
+    rom: RomHandle,
+    handle: AppHandle,
+    tileset: u32,
+    levels: String,
 ) -> AppResult<()> {
-    if tileset1_brushes.len() == 0 && tileset2_brushes.len() == 0 {
-        Ok(())
-    } else {
-        update_config(state, |config| {
-            config.brushes.insert(
-                tileset1,
-                // Find if the tileset1 configs exist
-                match config.brushes.get(&tileset1) {
-                    // If it doesn't exist, create it
-                    None => {
-                        let mut secondary_store = HashMap::new();
-                        if tileset2_brushes.len() > 0 {
-                            secondary_store.insert(tileset2, tileset2_brushes);
-                        }
-                        PrimaryBrushStore {
-                            brushes: tileset1_brushes,
-                            secondary: secondary_store,
-                        }
-                    }
-                    Some(primary_brush_store) => {
-                        let mut primary_store = primary_brush_store.clone();
-                        primary_store.brushes = tileset1_brushes;
-                        if tileset2_brushes.len() > 0 {
-                            primary_store.secondary.insert(tileset2, tileset2_brushes);
-                        }
-                        primary_store.clone()
-                    }
-                },
-            );
-        })
+    let state = state.rom(rom)?;
+    update_config(&state, |config| {
+        config.tileset_levels.insert(tileset, levels);
+    })?;
+
+    RomEvent::ConfigChanged.emit(&handle, state.handle);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::layouts::duplicate_layout_data;
+
+    /// Loads the ROM at `POLYTHREE_TEST_ROM`, if set.
+    fn test_rom() -> Option<Rom> {
+        let path = std::env::var("POLYTHREE_TEST_ROM").ok()?;
+        let mut rom = Rom::load(&path).expect("Could not load the test ROM");
+        rom.init_map().expect("Could not initialize the test ROM");
+        Some(rom)
+    }
+
+    #[test]
+    fn identity_survives_new_maps_and_layouts() {
+        let Some(mut rom) = test_rom() else {
+            println!("POLYTHREE_TEST_ROM is not set, skipping");
+            return;
+        };
+        let identity = RomIdentity::of(&mut rom);
+
+        // Like `create_map` at the end of the first group
+        let index = rom
+            .map_headers()
+            .dump_headers()
+            .unwrap()
+            .iter()
+            .filter(|dump| dump.group == 0)
+            .map(|dump| dump.index + 1)
+            .max()
+            .unwrap_or(0);
+        rom.map_headers().create_header(0, index, 1).unwrap();
+        assert_eq!(RomIdentity::of(&mut rom), identity);
+
+        // Like `duplicate_layout`
+        duplicate_layout_data(&mut rom, 1).unwrap();
+        assert_eq!(RomIdentity::of(&mut rom), identity);
     }
 }
@@ -0,0 +1,124 @@
+//! The errors of the commands, sent to the frontend as objects so that it can
+//! tell what went wrong and offer a way out instead of only showing a message.
+
+use std::fmt::Display;
+
+use serde::Serialize;
+
+use crate::handlers::map_list::MapId;
+
+/// What kind of problem an error is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// The command was given the handle of a ROM that is not open
+    RomNotOpen,
+    /// A map, layout, tileset or brush that doesn't exist
+    NotFound,
+    /// A value sent to the command that can't be used
+    InvalidInput,
+    /// `poly3lib` could not read or write a structure of the ROM
+    RomData,
+    /// A file that could not be read, written or understood
+    File,
+    /// There is no free space left in the ROM for new data
+    OutOfSpace,
+    /// A lock that was poisoned by a command that crashed
+    Lock,
+    Other,
+}
+
+impl ErrorKind {
+    /// Whether the user can fix the problem and try again, unlike
+    /// the broken ROM data and locks that need Polythree to be restarted.
+    fn is_recoverable(self) -> bool {
+        !matches!(self, ErrorKind::RomData | ErrorKind::Lock)
+    }
+}
+
+/// What the error is about, when it's known.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ErrorContext {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub map: Option<MapId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layout: Option<u16>,
+    /// An offset in the ROM
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AppError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub context: ErrorContext,
+    /// Whether trying again after fixing the problem can work
+    pub recoverable: bool,
+}
+
+impl AppError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            context: ErrorContext::default(),
+            recoverable: kind.is_recoverable(),
+        }
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::NotFound, message)
+    }
+
+    pub fn invalid_input(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::InvalidInput, message)
+    }
+
+    /// An error of `poly3lib`, which only describes the problem as text.
+    pub fn rom_data(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::RomData, message)
+    }
+
+    /// An error while reading or writing the file at the given path.
+    pub fn file(path: impl Into<String>, message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::File, message).with_path(path)
+    }
+
+    pub fn out_of_space(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::OutOfSpace, message)
+    }
+
+    /// The lock of the given data could not be taken.
+    pub fn lock(what: &str) -> Self {
+        Self::new(ErrorKind::Lock, format!("Failed to unlock the {}", what))
+    }
+
+    pub fn with_map(mut self, group: u8, index: u8) -> Self {
+        self.context.map = Some(MapId { group, index });
+        self
+    }
+
+    pub fn with_layout(mut self, layout: u16) -> Self {
+        self.context.layout = Some(layout);
+        self
+    }
+
+    pub fn with_offset(mut self, offset: usize) -> Self {
+        self.context.offset = Some(offset);
+        self
+    }
+
+    pub fn with_path(mut self, path: impl Into<String>) -> Self {
+        self.context.path = Some(path.into());
+        self
+    }
+}
+
+impl Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
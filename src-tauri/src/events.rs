@@ -0,0 +1,58 @@
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+
+use crate::{handlers::map_list::MapId, state::RomHandle};
+
+/// Events sent to every window after a change to the ROM or its config,
+/// so that the open views can update without refetching everything.
+pub enum RomEvent {
+    /// A map was created
+    MapCreated(MapId),
+    /// Some maps were deleted
+    MapsDeleted(Vec<MapId>),
+    /// A map header was changed
+    HeaderUpdated(MapId),
+    /// The header or the blocks of a layout were changed
+    LayoutUpdated(u16),
+    /// The ROM config was changed
+    ConfigChanged,
+}
+
+impl RomEvent {
+    pub const MAP_CREATED: &'static str = "map-created";
+    pub const MAPS_DELETED: &'static str = "map-deleted";
+    pub const HEADER_UPDATED: &'static str = "header-updated";
+    pub const LAYOUT_UPDATED: &'static str = "layout-updated";
+    pub const CONFIG_CHANGED: &'static str = "config-changed";
+
+    /// Sends the event about the given ROM to every window.
+    pub fn emit(self, handle: &AppHandle, rom: RomHandle) {
+        let res = match self {
+            RomEvent::MapCreated(map) => emit(handle, Self::MAP_CREATED, rom, map),
+            RomEvent::MapsDeleted(maps) => emit(handle, Self::MAPS_DELETED, rom, maps),
+            RomEvent::HeaderUpdated(map) => emit(handle, Self::HEADER_UPDATED, rom, map),
+            RomEvent::LayoutUpdated(id) => emit(handle, Self::LAYOUT_UPDATED, rom, id),
+            RomEvent::ConfigChanged => emit(handle, Self::CONFIG_CHANGED, rom, ()),
+        };
+
+        if let Err(err) = res {
+            println!("Could not send event: {}", err);
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct RomEventPayload<T> {
+    /// The ROM the event is about
+    rom: RomHandle,
+    data: T,
+}
+
+fn emit<T: Serialize + Clone>(
+    handle: &AppHandle,
+    event: &str,
+    rom: RomHandle,
+    data: T,
+) -> tauri::Result<()> {
+    handle.emit_all(event, RomEventPayload { rom, data })
+}
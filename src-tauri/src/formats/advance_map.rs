@@ -0,0 +1,231 @@
+//! Advance Map's `.blk` (map blocks only) and `.map` (map, border
+//! and tilesets numbers) export formats.
+
+use crate::{
+    error::{AppError, ErrorKind},
+    formats::RawBlocks,
+    state::AppResult,
+};
+
+/// Size of the `.map` header before the border blocks.
+const MAP_HEADER_SIZE: usize = 20;
+
+/// Width and height of the border in the games that don't store its size.
+const DEFAULT_BORDER_SIZE: usize = 2;
+
+/// The content of an Advance Map `.map` file.
+pub struct AdvanceMapFile {
+    pub map: RawBlocks,
+    pub border: RawBlocks,
+    /// Advance Map's number for the primary tileset
+    pub primary_tileset: u32,
+    /// Advance Map's number for the secondary tileset
+    pub secondary_tileset: u32,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+/// Reads `width * height` blocks starting at `offset`.
+fn read_blocks(bytes: &[u8], offset: usize, width: usize, height: usize) -> AppResult<RawBlocks> {
+    // The sizes come from the file, so they can be large enough to overflow
+    let end = width
+        .checked_mul(height)
+        .and_then(|count| count.checked_mul(2))
+        .and_then(|size| size.checked_add(offset))
+        .filter(|end| *end <= bytes.len())
+        .ok_or_else(|| {
+            AppError::new(
+                ErrorKind::File,
+                format!(
+                    "The file is too short for {}x{} blocks ({} bytes)",
+                    width,
+                    height,
+                    bytes.len()
+                ),
+            )
+        })?;
+
+    Ok(RawBlocks {
+        width,
+        height,
+        blocks: (offset..end)
+            .step_by(2)
+            .map(|i| read_u16(bytes, i))
+            .collect(),
+    })
+}
+
+fn write_blocks(bytes: &mut Vec<u8>, blocks: &RawBlocks) {
+    for block in blocks.blocks.iter() {
+        bytes.extend_from_slice(&block.to_le_bytes());
+    }
+}
+
+/// Parses a `.blk` file.
+pub fn parse_blk(bytes: &[u8]) -> AppResult<RawBlocks> {
+    if bytes.len() < 4 {
+        return Err(AppError::new(
+            ErrorKind::File,
+            "The file is too short to be a .blk file",
+        ));
+    }
+
+    let width = read_u16(bytes, 0) as usize;
+    let height = read_u16(bytes, 2) as usize;
+    read_blocks(bytes, 4, width, height)
+}
+
+/// Parses a `.map` file.
+pub fn parse_map(bytes: &[u8]) -> AppResult<AdvanceMapFile> {
+    if bytes.len() < MAP_HEADER_SIZE {
+        return Err(AppError::new(
+            ErrorKind::File,
+            "The file is too short to be a .map file",
+        ));
+    }
+
+    let width = read_u32(bytes, 0) as usize;
+    let height = read_u32(bytes, 4) as usize;
+    let primary_tileset = read_u32(bytes, 8);
+    let secondary_tileset = read_u32(bytes, 12);
+    // Ruby, Sapphire and Emerald maps leave the border size at 0
+    let (border_width, border_height) = match (bytes[16] as usize, bytes[17] as usize) {
+        (0, _) | (_, 0) => (DEFAULT_BORDER_SIZE, DEFAULT_BORDER_SIZE),
+        size => size,
+    };
+
+    let border = read_blocks(bytes, MAP_HEADER_SIZE, border_width, border_height)?;
+    let map = read_blocks(
+        bytes,
+        MAP_HEADER_SIZE + border_width * border_height * 2,
+        width,
+        height,
+    )?;
+
+    Ok(AdvanceMapFile {
+        map,
+        border,
+        primary_tileset,
+        secondary_tileset,
+    })
+}
+
+/// Serializes blocks to a `.blk` file.
+pub fn write_blk(blocks: &RawBlocks) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(4 + blocks.blocks.len() * 2);
+    bytes.extend_from_slice(&(blocks.width as u16).to_le_bytes());
+    bytes.extend_from_slice(&(blocks.height as u16).to_le_bytes());
+    write_blocks(&mut bytes, blocks);
+    bytes
+}
+
+/// Serializes a layout to a `.map` file.
+pub fn write_map(file: &AdvanceMapFile) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(
+        MAP_HEADER_SIZE + (file.border.blocks.len() + file.map.blocks.len()) * 2,
+    );
+    bytes.extend_from_slice(&(file.map.width as u32).to_le_bytes());
+    bytes.extend_from_slice(&(file.map.height as u32).to_le_bytes());
+    bytes.extend_from_slice(&file.primary_tileset.to_le_bytes());
+    bytes.extend_from_slice(&file.secondary_tileset.to_le_bytes());
+    bytes.extend_from_slice(&[file.border.width as u8, file.border.height as u8, 0, 0]);
+    write_blocks(&mut bytes, &file.border);
+    write_blocks(&mut bytes, &file.map);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(width: usize, height: usize) -> RawBlocks {
+        RawBlocks {
+            width,
+            height,
+            blocks: (0..width * height).map(|i| (i * 0x401) as u16).collect(),
+        }
+    }
+
+    #[test]
+    fn blk_round_trip() {
+        let original = blocks(3, 2);
+        let parsed = parse_blk(&write_blk(&original)).unwrap();
+
+        assert_eq!((parsed.width, parsed.height), (3, 2));
+        assert_eq!(parsed.blocks, original.blocks);
+    }
+
+    #[test]
+    fn map_round_trip() {
+        let original = AdvanceMapFile {
+            map: blocks(4, 3),
+            border: blocks(1, 2),
+            primary_tileset: 1,
+            secondary_tileset: 7,
+        };
+        let parsed = parse_map(&write_map(&original)).unwrap();
+
+        assert_eq!((parsed.map.width, parsed.map.height), (4, 3));
+        assert_eq!(parsed.map.blocks, original.map.blocks);
+        assert_eq!((parsed.border.width, parsed.border.height), (1, 2));
+        assert_eq!(parsed.border.blocks, original.border.blocks);
+        assert_eq!(parsed.primary_tileset, 1);
+        assert_eq!(parsed.secondary_tileset, 7);
+    }
+
+    #[test]
+    fn map_without_border_size_uses_2x2() {
+        let mut bytes = write_map(&AdvanceMapFile {
+            map: blocks(2, 2),
+            border: blocks(2, 2),
+            primary_tileset: 0,
+            secondary_tileset: 1,
+        });
+        bytes[16] = 0;
+        bytes[17] = 0;
+        let parsed = parse_map(&bytes).unwrap();
+
+        assert_eq!((parsed.border.width, parsed.border.height), (2, 2));
+        assert_eq!(parsed.map.blocks, blocks(2, 2).blocks);
+    }
+
+    #[test]
+    fn truncated_files_are_rejected() {
+        let blk = write_blk(&blocks(3, 2));
+        for len in 0..blk.len() {
+            let err = parse_blk(&blk[..len]).unwrap_err();
+            assert_eq!(err.kind, ErrorKind::File);
+        }
+
+        let map = write_map(&AdvanceMapFile {
+            map: blocks(3, 2),
+            border: blocks(2, 2),
+            primary_tileset: 0,
+            secondary_tileset: 1,
+        });
+        for len in 0..map.len() {
+            let err = parse_map(&map[..len]).unwrap_err();
+            assert_eq!(err.kind, ErrorKind::File);
+        }
+    }
+
+    #[test]
+    fn huge_sizes_are_rejected() {
+        let mut bytes = vec![0xFF; MAP_HEADER_SIZE];
+        bytes[16] = 2;
+        bytes[17] = 2;
+        assert!(parse_map(&bytes).is_err());
+        assert!(parse_blk(&[0xFF, 0xFF, 0xFF, 0xFF]).is_err());
+    }
+}
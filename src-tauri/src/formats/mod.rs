@@ -0,0 +1,12 @@
+pub mod advance_map;
+pub mod p3brush;
+pub mod tiled;
+
+/// A rectangle of blocks as the game stores them, with the
+/// metatile in the lower bits and the permissions in the upper ones.
+#[derive(Debug, Clone)]
+pub struct RawBlocks {
+    pub width: usize,
+    pub height: usize,
+    pub blocks: Vec<u16>,
+}
@@ -0,0 +1,59 @@
+//! Brush libraries (`.p3brush`), the brushes of a pair of tilesets saved
+//! as JSON on their own, so that they can be shared between ROMs.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{brushes::Brush, error::AppError, state::AppResult};
+
+/// The current version of the library format.
+pub const LIBRARY_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BrushLibrary {
+    /// The version of the format the library was saved with
+    pub version: u32,
+    pub primary: LibraryTileset,
+    pub secondary: Option<LibraryTileset>,
+    /// The brushes that only use the primary tileset
+    pub primary_brushes: Vec<Brush>,
+    /// The brushes that also use the secondary tileset
+    pub secondary_brushes: Vec<Brush>,
+}
+
+/// What identifies a tileset the brushes were made for,
+/// to find it in a ROM where its offset is different.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibraryTileset {
+    pub offset: u32,
+    /// The name of the tileset in the config, if it had one
+    pub name: Option<String>,
+    /// A stable hash of the tiles, palettes and metatiles of the tileset
+    pub graphics_hash: String,
+}
+
+impl BrushLibrary {
+    pub fn load(path: &str) -> AppResult<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| AppError::file(path, format!("Could not read {}: {}", path, e)))?;
+        let library: Self = serde_json::from_str(&text)
+            .map_err(|e| AppError::file(path, format!("Invalid brush library {}: {}", path, e)))?;
+
+        if library.version > LIBRARY_VERSION {
+            return Err(AppError::file(
+                path,
+                format!(
+                    "The brush library was made with a newer version of Polythree (format {})",
+                    library.version
+                ),
+            ));
+        }
+        Ok(library)
+    }
+
+    pub fn save(&self, path: &str) -> AppResult<()> {
+        let text = serde_json::to_string_pretty(self)
+            .map_err(|e| AppError::file(path, format!("Could not serialize the brushes: {}", e)))?;
+        std::fs::write(path, text)
+            .map_err(|e| AppError::file(path, format!("Could not write {}: {}", path, e)))
+    }
+}
@@ -0,0 +1,613 @@
+//! Tiled maps (`.tmx` and `.tmj`) with a layer for the bottom and top
+//! halves of the metatiles and one for the permissions, each using
+//! a tileset generated from the layout's tilesets.
+
+use std::collections::HashMap;
+
+use image::{Rgba, RgbaImage};
+use poly3lib::maps::tileset::TilesetsRenderData;
+use serde_json::{json, Value};
+
+use crate::{
+    error::{AppError, ErrorKind},
+    state::AppResult,
+};
+
+/// Number of tiles in a row of the generated tileset images.
+const SHEET_COLUMNS: usize = 16;
+/// Number of different permissions in the permissions tileset, one for
+/// each color, since the editor only shows the 4-bit level and the obstacle bit.
+pub const PERMISSIONS_COUNT: usize = 32;
+
+pub const BOTTOM_LAYER: &str = "bottom";
+pub const TOP_LAYER: &str = "top";
+pub const PERMISSIONS_LAYER: &str = "permissions";
+
+/// Bits of a Tiled gid that store the flip flags instead of the tile.
+const GID_FLAGS_MASK: u32 = 0xE0000000;
+
+/// The colors of the permissions, as `"#RRGGBB"` strings.
+/// The editor imports the same file to draw its permissions.
+const PERMISSION_COLORS: &str = include_str!("permission_colors.json");
+
+/// A tileset image written next to the Tiled map.
+pub struct TiledTileset {
+    /// The name used to find the tileset when importing
+    pub name: &'static str,
+    /// The image file name, relative to the map
+    pub image_path: String,
+    pub image: RgbaImage,
+    pub tile_count: usize,
+}
+
+/// The layers of a Tiled map, as gids.
+pub struct TiledLayers {
+    pub width: usize,
+    pub height: usize,
+    /// Layers by name
+    pub layers: HashMap<String, Vec<u32>>,
+    /// First gid of each tileset by name
+    pub first_gids: HashMap<String, u32>,
+}
+
+impl TiledLayers {
+    /// Returns the tile indices (in the tileset with the same name) of a
+    /// layer, or `None` for the empty tiles and the ones from other tilesets.
+    ///
+    /// Fails if the layer doesn't have a tile for every block of the map.
+    pub fn tiles(&self, layer: &str) -> AppResult<Vec<Option<u32>>> {
+        let gids = self.layers.get(layer).ok_or_else(|| {
+            AppError::new(
+                ErrorKind::File,
+                format!("The Tiled map has no \"{}\" layer", layer),
+            )
+        })?;
+        if self.width.checked_mul(self.height) != Some(gids.len()) {
+            return Err(AppError::new(
+                ErrorKind::File,
+                format!(
+                    "Layer \"{}\" has {} tiles, which doesn't fit a {}x{} map",
+                    layer,
+                    gids.len(),
+                    self.width,
+                    self.height
+                ),
+            ));
+        }
+        let first_gid = *self.first_gids.get(layer).ok_or_else(|| {
+            AppError::new(
+                ErrorKind::File,
+                format!("The Tiled map has no \"{}\" tileset", layer),
+            )
+        })?;
+        // The tileset ends where the next one starts
+        let last_gid = self
+            .first_gids
+            .values()
+            .filter(|&&gid| gid > first_gid)
+            .min()
+            .copied()
+            .unwrap_or(u32::MAX);
+
+        Ok(gids
+            .iter()
+            .map(|gid| {
+                let gid = gid & !GID_FLAGS_MASK;
+                match gid.checked_sub(first_gid) {
+                    Some(tile) if gid != 0 && gid < last_gid => Some(tile),
+                    _ => None,
+                }
+            })
+            .collect())
+    }
+}
+
+// ANCHOR Tileset images
+fn sheet_image(tile_count: usize) -> RgbaImage {
+    let rows = tile_count.div_ceil(SHEET_COLUMNS).max(1);
+    RgbaImage::new((SHEET_COLUMNS * 16) as u32, (rows * 16) as u32)
+}
+
+/// Converts a GBA color to RGBA.
+fn gba_to_rgba(color: u16) -> Rgba<u8> {
+    let r = ((color & 0x1F) << 3) as u8;
+    let g = (((color >> 5) & 0x1F) << 3) as u8;
+    let b = (((color >> 10) & 0x1F) << 3) as u8;
+    Rgba([r, g, b, 255])
+}
+
+/// Renders the bottom and top halves of every metatile to two images.
+pub fn render_metatile_sheets(render_data: &TilesetsRenderData) -> (RgbaImage, RgbaImage) {
+    let count = render_data.metatiles.len();
+    let mut bottom = sheet_image(count);
+    let mut top = sheet_image(count);
+
+    for (id, metatile) in render_data.metatiles.iter().enumerate() {
+        let sheet_x = (id % SHEET_COLUMNS) * 16;
+        let sheet_y = (id / SHEET_COLUMNS) * 16;
+
+        for (index, (inner_x, inner_y)) in [(0, 0), (8, 0), (0, 8), (8, 8)].iter().enumerate() {
+            for (layer, image) in [(0, &mut bottom), (4, &mut top)] {
+                let tile_info = metatile[layer + index];
+                let tile_id = (tile_info & 0x3FF) as usize;
+                let palette = ((tile_info >> 12) & 0xF) as usize;
+                let hflip = tile_info & 0x400 != 0;
+                let vflip = tile_info & 0x800 != 0;
+
+                if tile_id >= render_data.tiles.len() {
+                    continue;
+                }
+                let tile = &render_data.tiles[tile_id];
+
+                for y in 0..8 {
+                    for x in 0..8 {
+                        let color_id = tile[y][x] as usize;
+                        // Color 0 is transparent
+                        if color_id == 0 {
+                            continue;
+                        }
+
+                        let x = if hflip { 7 - x } else { x };
+                        let y = if vflip { 7 - y } else { y };
+                        let color = gba_to_rgba(render_data.palettes[palette][color_id & 0xF]);
+                        image.put_pixel(
+                            (sheet_x + inner_x + x) as u32,
+                            (sheet_y + inner_y + y) as u32,
+                            color,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    (bottom, top)
+}
+
+/// Reads every `#RRGGBB` color of a text, in order.
+fn hex_colors(text: &str) -> Vec<[u8; 3]> {
+    text.split('#')
+        .skip(1)
+        .map(|color| {
+            let channel = |i: usize| {
+                color
+                    .get(i..i + 2)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .unwrap_or(0)
+            };
+            [channel(0), channel(2), channel(4)]
+        })
+        .collect()
+}
+
+/// Renders a colored square for each permission.
+pub fn render_permissions_sheet() -> RgbaImage {
+    let mut image = sheet_image(PERMISSIONS_COUNT);
+    let colors = hex_colors(PERMISSION_COLORS);
+
+    for permission in 0..PERMISSIONS_COUNT {
+        let [r, g, b] = colors[permission];
+        let sheet_x = (permission % SHEET_COLUMNS) * 16;
+        let sheet_y = (permission / SHEET_COLUMNS) * 16;
+
+        for y in 0..16 {
+            for x in 0..16 {
+                image.put_pixel(
+                    (sheet_x + x) as u32,
+                    (sheet_y + y) as u32,
+                    Rgba([r, g, b, 0x80]),
+                );
+            }
+        }
+    }
+
+    image
+}
+
+// ANCHOR Writing
+/// Returns the first gid of each tileset, in order.
+fn first_gids(tilesets: &[TiledTileset]) -> Vec<u32> {
+    let mut next = 1;
+    tilesets
+        .iter()
+        .map(|tileset| {
+            let first_gid = next;
+            next += tileset.tile_count as u32;
+            first_gid
+        })
+        .collect()
+}
+
+/// Converts the tile indices of each layer (one layer per tileset, with
+/// the same name) to gids, leaving 0 for the missing tiles.
+fn layers_gids(tilesets: &[TiledTileset], layers: &[Vec<Option<u32>>]) -> Vec<Vec<u32>> {
+    first_gids(tilesets)
+        .into_iter()
+        .zip(layers.iter())
+        .map(|(first_gid, tiles)| {
+            tiles
+                .iter()
+                .map(|tile| tile.map(|tile| first_gid + tile).unwrap_or(0))
+                .collect()
+        })
+        .collect()
+}
+
+/// Writes a `.tmj` (JSON) map.
+pub fn write_tmj(
+    width: usize,
+    height: usize,
+    tilesets: &[TiledTileset],
+    layers: &[Vec<Option<u32>>],
+) -> String {
+    let tilesets_json: Vec<Value> = tilesets
+        .iter()
+        .zip(first_gids(tilesets))
+        .map(|(tileset, first_gid)| {
+            json!({
+                "firstgid": first_gid,
+                "name": tileset.name,
+                "image": tileset.image_path,
+                "imagewidth": tileset.image.width(),
+                "imageheight": tileset.image.height(),
+                "tilewidth": 16,
+                "tileheight": 16,
+                "tilecount": tileset.tile_count,
+                "columns": SHEET_COLUMNS,
+                "margin": 0,
+                "spacing": 0,
+            })
+        })
+        .collect();
+
+    let layers_json: Vec<Value> = tilesets
+        .iter()
+        .zip(layers_gids(tilesets, layers))
+        .enumerate()
+        .map(|(i, (tileset, data))| {
+            json!({
+                "id": i + 1,
+                "name": tileset.name,
+                "type": "tilelayer",
+                "width": width,
+                "height": height,
+                "x": 0,
+                "y": 0,
+                "opacity": 1,
+                "visible": true,
+                "data": data,
+            })
+        })
+        .collect();
+
+    json!({
+        "type": "map",
+        "version": "1.10",
+        "orientation": "orthogonal",
+        "renderorder": "right-down",
+        "infinite": false,
+        "width": width,
+        "height": height,
+        "tilewidth": 16,
+        "tileheight": 16,
+        "nextlayerid": layers_json.len() + 1,
+        "nextobjectid": 1,
+        "tilesets": tilesets_json,
+        "layers": layers_json,
+    })
+    .to_string()
+}
+
+/// Escapes the characters that can't appear as is in an XML attribute.
+fn escape_xml(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Writes a `.tmx` (XML) map with CSV layers.
+pub fn write_tmx(
+    width: usize,
+    height: usize,
+    tilesets: &[TiledTileset],
+    layers: &[Vec<Option<u32>>],
+) -> String {
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<map version=\"1.10\" orientation=\"orthogonal\" renderorder=\"right-down\" width=\"{}\" height=\"{}\" tilewidth=\"16\" tileheight=\"16\" infinite=\"0\" nextlayerid=\"{}\" nextobjectid=\"1\">\n",
+        width,
+        height,
+        tilesets.len() + 1
+    ));
+
+    for (tileset, first_gid) in tilesets.iter().zip(first_gids(tilesets)) {
+        xml.push_str(&format!(
+            " <tileset firstgid=\"{}\" name=\"{}\" tilewidth=\"16\" tileheight=\"16\" tilecount=\"{}\" columns=\"{}\">\n  <image source=\"{}\" width=\"{}\" height=\"{}\"/>\n </tileset>\n",
+            first_gid,
+            escape_xml(&tileset.name),
+            tileset.tile_count,
+            SHEET_COLUMNS,
+            escape_xml(&tileset.image_path),
+            tileset.image.width(),
+            tileset.image.height()
+        ));
+    }
+
+    for (i, (tileset, data)) in tilesets
+        .iter()
+        .zip(layers_gids(tilesets, layers))
+        .enumerate()
+    {
+        let rows: Vec<String> = data
+            .chunks(width)
+            .map(|row| {
+                row.iter()
+                    .map(|gid| gid.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .collect();
+
+        xml.push_str(&format!(
+            " <layer id=\"{}\" name=\"{}\" width=\"{}\" height=\"{}\">\n  <data encoding=\"csv\">\n{}\n</data>\n </layer>\n",
+            i + 1,
+            escape_xml(&tileset.name),
+            width,
+            height,
+            rows.join(",\n")
+        ));
+    }
+
+    xml.push_str("</map>\n");
+    xml
+}
+
+// ANCHOR Reading
+fn json_usize(value: &Value, key: &str) -> AppResult<usize> {
+    value
+        .get(key)
+        .and_then(Value::as_u64)
+        .map(|value| value as usize)
+        .ok_or_else(|| {
+            AppError::new(
+                ErrorKind::File,
+                format!("Missing \"{}\" in the Tiled map", key),
+            )
+        })
+}
+
+/// Reads the layers of a `.tmj` map.
+pub fn parse_tmj(text: &str) -> AppResult<TiledLayers> {
+    let map: Value = serde_json::from_str(text).map_err(|e| {
+        AppError::new(
+            ErrorKind::File,
+            format!("Could not read the Tiled map: {}", e),
+        )
+    })?;
+
+    let mut first_gids = HashMap::new();
+    for tileset in map["tilesets"].as_array().into_iter().flatten() {
+        if let (Some(name), Some(first_gid)) =
+            (tileset["name"].as_str(), tileset["firstgid"].as_u64())
+        {
+            first_gids.insert(name.to_owned(), first_gid as u32);
+        }
+    }
+
+    let mut layers = HashMap::new();
+    for layer in map["layers"].as_array().into_iter().flatten() {
+        let (Some(name), Some(data)) = (layer["name"].as_str(), layer["data"].as_array()) else {
+            continue;
+        };
+        let data = data
+            .iter()
+            .map(|gid| gid.as_u64().map(|gid| gid as u32))
+            .collect::<Option<Vec<u32>>>()
+            .ok_or_else(|| {
+                AppError::new(
+                    ErrorKind::File,
+                    format!("Layer \"{}\" must be saved with the CSV layer format", name),
+                )
+            })?;
+        layers.insert(name.to_owned(), data);
+    }
+
+    Ok(TiledLayers {
+        width: json_usize(&map, "width")?,
+        height: json_usize(&map, "height")?,
+        layers,
+        first_gids,
+    })
+}
+
+/// Returns the value of an attribute in an XML tag.
+fn xml_attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!(" {}=\"", name))? + name.len() + 3;
+    let end = start + tag[start..].find('"')?;
+    Some(&tag[start..end])
+}
+
+/// Returns the opening tags with the given name, with the text after them.
+fn xml_tags<'a>(xml: &'a str, name: &str) -> Vec<(&'a str, &'a str)> {
+    xml.split(&format!("<{} ", name))
+        .skip(1)
+        .map(|rest| {
+            let end = rest.find('>').unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        })
+        .collect()
+}
+
+/// Reads the layers of a `.tmx` map.
+pub fn parse_tmx(text: &str) -> AppResult<TiledLayers> {
+    let (map_tag, _) = *xml_tags(text, "map").first().ok_or_else(|| {
+        AppError::new(ErrorKind::File, "Could not find the map in the Tiled file")
+    })?;
+    let map_size = |name: &str| -> AppResult<usize> {
+        xml_attribute(map_tag, name)
+            .and_then(|value| value.parse().ok())
+            .ok_or_else(|| {
+                AppError::new(
+                    ErrorKind::File,
+                    format!("Missing \"{}\" in the Tiled map", name),
+                )
+            })
+    };
+
+    let mut first_gids = HashMap::new();
+    for (tag, _) in xml_tags(text, "tileset") {
+        if let (Some(name), Some(first_gid)) = (
+            xml_attribute(tag, "name"),
+            xml_attribute(tag, "firstgid").and_then(|gid| gid.parse().ok()),
+        ) {
+            first_gids.insert(name.to_owned(), first_gid);
+        }
+    }
+
+    let mut layers = HashMap::new();
+    for (tag, rest) in xml_tags(text, "layer") {
+        let Some(name) = xml_attribute(tag, "name") else {
+            continue;
+        };
+        let (data_tag, data) = *xml_tags(rest, "data").first().ok_or_else(|| {
+            AppError::new(ErrorKind::File, format!("Layer \"{}\" has no data", name))
+        })?;
+        if xml_attribute(data_tag, "encoding") != Some("csv") {
+            return Err(AppError::new(
+                ErrorKind::File,
+                format!("Layer \"{}\" must be saved with the CSV layer format", name),
+            ));
+        }
+
+        let data = data.strip_prefix('>').unwrap_or(data);
+        let data = &data[..data.find("</data>").ok_or_else(|| {
+            AppError::new(
+                ErrorKind::File,
+                format!("Layer \"{}\" is not complete", name),
+            )
+        })?];
+        let data = data
+            .split(',')
+            .map(|gid| gid.trim().parse::<u32>())
+            .collect::<Result<Vec<u32>, _>>()
+            .map_err(|e| {
+                AppError::new(
+                    ErrorKind::File,
+                    format!("Invalid tile in layer \"{}\": {}", name, e),
+                )
+            })?;
+        layers.insert(name.to_owned(), data);
+    }
+
+    Ok(TiledLayers {
+        width: map_size("width")?,
+        height: map_size("height")?,
+        layers,
+        first_gids,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 3;
+    const HEIGHT: usize = 2;
+
+    fn tilesets() -> Vec<TiledTileset> {
+        [
+            (BOTTOM_LAYER, 20),
+            (TOP_LAYER, 20),
+            (PERMISSIONS_LAYER, PERMISSIONS_COUNT),
+        ]
+        .into_iter()
+        .map(|(name, tile_count)| TiledTileset {
+            name,
+            image_path: format!("Route 1 & 2 <{}>.png", name),
+            image: sheet_image(tile_count),
+            tile_count,
+        })
+        .collect()
+    }
+
+    fn layers() -> Vec<Vec<Option<u32>>> {
+        vec![
+            vec![Some(0), Some(1), Some(19), None, Some(4), Some(5)],
+            vec![None, None, Some(2), Some(3), None, Some(0)],
+            vec![Some(1), Some(0), Some(31), Some(12), Some(13), Some(1)],
+        ]
+    }
+
+    fn assert_layers(parsed: &TiledLayers) {
+        assert_eq!((parsed.width, parsed.height), (WIDTH, HEIGHT));
+        for (name, layer) in [BOTTOM_LAYER, TOP_LAYER, PERMISSIONS_LAYER]
+            .into_iter()
+            .zip(layers())
+        {
+            assert_eq!(parsed.tiles(name).unwrap(), layer);
+        }
+    }
+
+    #[test]
+    fn tmx_round_trip() {
+        let xml = write_tmx(WIDTH, HEIGHT, &tilesets(), &layers());
+
+        assert!(xml.contains("source=\"Route 1 &amp; 2 &lt;bottom&gt;.png\""));
+        assert_layers(&parse_tmx(&xml).unwrap());
+    }
+
+    #[test]
+    fn tmj_round_trip() {
+        let json = write_tmj(WIDTH, HEIGHT, &tilesets(), &layers());
+        assert_layers(&parse_tmj(&json).unwrap());
+    }
+
+    #[test]
+    fn truncated_maps_are_rejected() {
+        let xml = write_tmx(WIDTH, HEIGHT, &tilesets(), &layers());
+        for len in 0..xml.len() {
+            // A file cut after the last layer can still be read, but never with other tiles
+            if let Ok(parsed) = parse_tmx(&xml[..len]) {
+                for (name, layer) in [BOTTOM_LAYER, TOP_LAYER, PERMISSIONS_LAYER]
+                    .into_iter()
+                    .zip(layers())
+                {
+                    if let Ok(tiles) = parsed.tiles(name) {
+                        assert_eq!(tiles, layer);
+                    }
+                }
+            }
+        }
+
+        let json = write_tmj(WIDTH, HEIGHT, &tilesets(), &layers());
+        for len in 0..json.len() {
+            assert!(parse_tmj(&json[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn layers_of_the_wrong_size_are_rejected() {
+        let mut parsed = parse_tmj(&write_tmj(WIDTH, HEIGHT, &tilesets(), &layers())).unwrap();
+        parsed.layers.get_mut(TOP_LAYER).unwrap().pop();
+        assert_eq!(parsed.tiles(TOP_LAYER).unwrap_err().kind, ErrorKind::File);
+
+        parsed.width = usize::MAX;
+        assert!(parsed.tiles(BOTTOM_LAYER).is_err());
+    }
+
+    #[test]
+    fn permission_colors_are_read() {
+        let colors = hex_colors(PERMISSION_COLORS);
+        assert_eq!(colors.len(), PERMISSIONS_COUNT);
+        assert_eq!(colors[0], [0x20, 0x76, 0xDF]);
+    }
+}
@@ -0,0 +1,386 @@
+use std::collections::HashMap;
+
+use poly3lib::rom::Rom;
+use serde::Serialize;
+use tauri::AppHandle;
+
+use crate::{
+    brushes::Brush,
+    config::{try_update_config, RomConfig},
+    error::AppError,
+    events::RomEvent,
+    formats::p3brush::{BrushLibrary, LibraryTileset, LIBRARY_VERSION},
+    handlers::layouts::primary_metatiles_limit,
+    raw,
+    stable_hash::StableHasher,
+    state::{AppResult, AppState, AppStateFunctions, RomHandle, RomState},
+};
+
+// ANCHOR Brush lists
+/// Returns the brushes of a primary tileset, or the ones of a pair
+/// of tilesets if `tileset2` is set, creating the list if needed.
+fn brush_list(config: &mut RomConfig, tileset1: u32, tileset2: Option<u32>) -> &mut Vec<Brush> {
+    let store = config.brushes.entry(tileset1).or_default();
+    match tileset2 {
+        Some(tileset2) => store.secondary.entry(tileset2).or_default(),
+        None => &mut store.brushes,
+    }
+}
+
+/// Returns the position of the brush with the given id in the list.
+fn find_brush(list: &[Brush], id: u32) -> AppResult<usize> {
+    list.iter()
+        .position(|brush| brush.id == id)
+        .ok_or_else(|| AppError::not_found(format!("Brush {} not found", id)))
+}
+
+/// Returns an id that no brush of the config has.
+fn next_brush_id(config: &RomConfig) -> u32 {
+    config
+        .brushes
+        .values()
+        .flat_map(|store| {
+            store
+                .brushes
+                .iter()
+                .chain(store.secondary.values().flatten())
+        })
+        .map(|brush| brush.id)
+        .max()
+        .unwrap_or(0)
+        + 1
+}
+
+/// Checks that the brush only uses metatiles of the tilesets of its list.
+fn validate_brush(
+    state: &RomState,
+    brush: &Brush,
+    tileset1: u32,
+    tileset2: Option<u32>,
+) -> AppResult<()> {
+    state.read_rom(|rom| {
+        let table = rom
+            .refs
+            .tilesets_table
+            .as_ref()
+            .ok_or_else(|| AppError::rom_data("Tilesets table not found"))?;
+        let length = |tileset: u32| table.get(&(tileset as usize)).map(|x| x.0).unwrap_or(0) as u16;
+
+        let limit = primary_metatiles_limit(&rom.rom_type);
+        let secondary_length = tileset2.map(length).unwrap_or(0);
+        brush.validate(length(tileset1), limit..limit + secondary_length)
+    })
+}
+
+// ANCHOR Brush commands
+/// Adds a brush at the end of its list, returning it with its new id.
+#[tauri::command]
+pub fn add_brush(
+    state: AppState,
+    rom: RomHandle,
+    handle: AppHandle,
+    tileset1: u32,
+    tileset2: Option<u32>,
+    mut brush: Brush,
+) -> AppResult<Brush> {
+    let state = state.rom(rom)?;
+    validate_brush(&state, &brush, tileset1, tileset2)?;
+
+    let brush = try_update_config(&state, |config| {
+        brush.id = next_brush_id(config);
+        brush_list(config, tileset1, tileset2).push(brush.clone());
+        Ok(brush)
+    })?;
+
+    RomEvent::ConfigChanged.emit(&handle, state.handle);
+    Ok(brush)
+}
+
+/// Replaces the brush that has the same id.
+#[tauri::command]
+pub fn update_brush(
+    state: AppState,
+    rom: RomHandle,
+    handle: AppHandle,
+    tileset1: u32,
+    tileset2: Option<u32>,
+    brush: Brush,
+) -> AppResult<()> {
+    let state = state.rom(rom)?;
+    validate_brush(&state, &brush, tileset1, tileset2)?;
+
+    try_update_config(&state, |config| {
+        let list = brush_list(config, tileset1, tileset2);
+        let position = find_brush(list, brush.id)?;
+        list[position] = brush;
+        Ok(())
+    })?;
+
+    RomEvent::ConfigChanged.emit(&handle, state.handle);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn delete_brush(
+    state: AppState,
+    rom: RomHandle,
+    handle: AppHandle,
+    tileset1: u32,
+    tileset2: Option<u32>,
+    id: u32,
+) -> AppResult<()> {
+    let state = state.rom(rom)?;
+    try_update_config(&state, |config| {
+        let list = brush_list(config, tileset1, tileset2);
+        let position = find_brush(list, id)?;
+        list.remove(position);
+        Ok(())
+    })?;
+
+    RomEvent::ConfigChanged.emit(&handle, state.handle);
+    Ok(())
+}
+
+/// Moves the brushes with the given ids to the start of the list, in that order.
+///
+/// The brushes that are not listed keep their order after them, so
+/// that the ones added by another window in the meantime are kept.
+#[tauri::command]
+pub fn reorder_brushes(
+    state: AppState,
+    rom: RomHandle,
+    handle: AppHandle,
+    tileset1: u32,
+    tileset2: Option<u32>,
+    ids: Vec<u32>,
+) -> AppResult<()> {
+    let state = state.rom(rom)?;
+    try_update_config(&state, |config| {
+        let list = brush_list(config, tileset1, tileset2);
+
+        let mut ordered = Vec::with_capacity(list.len());
+        for id in ids {
+            // Ignore the brushes that were deleted in the meantime
+            if let Ok(position) = find_brush(list, id) {
+                ordered.push(list.remove(position));
+            }
+        }
+        ordered.append(list);
+        *list = ordered;
+        Ok(())
+    })?;
+
+    RomEvent::ConfigChanged.emit(&handle, state.handle);
+    Ok(())
+}
+
+// ANCHOR Brush libraries
+#[derive(Debug, Serialize)]
+pub struct BrushImportReport {
+    /// The tilesets the brushes were imported into
+    tileset1: u32,
+    tileset2: Option<u32>,
+    /// How many brushes were imported
+    imported: usize,
+    /// The tilesets that were matched by name or graphics and the skipped brushes
+    warnings: Vec<String>,
+}
+
+/// Returns a hash of the tiles, palettes and metatiles of a tileset.
+///
+/// It's saved in the brush libraries, so it's a [`StableHasher`] hash that
+/// stays the same across builds and platforms.
+fn graphics_hash(rom: &Rom, tileset: u32) -> String {
+    let mut hasher = StableHasher::new();
+    for graphics in raw::tileset_graphics(rom, tileset as usize) {
+        hasher.write_option(graphics, StableHasher::write_slice);
+    }
+    format!("{:016x}", hasher.finish())
+}
+
+fn library_tileset(rom: &Rom, names: &HashMap<u32, String>, tileset: u32) -> LibraryTileset {
+    LibraryTileset {
+        offset: tileset,
+        name: names.get(&tileset).cloned(),
+        graphics_hash: graphics_hash(rom, tileset),
+    }
+}
+
+/// Finds the tileset of the ROM that a library was made for: the one with the
+/// same graphics, or else the one with the same name, or else the one at the
+/// same offset. Returns what it was found by too.
+fn find_library_tileset(
+    rom: &Rom,
+    names: &HashMap<u32, String>,
+    tileset: &LibraryTileset,
+) -> Option<(u32, &'static str)> {
+    let table = rom.refs.tilesets_table.as_ref()?;
+    let mut offsets: Vec<u32> = table.keys().map(|offset| *offset as u32).collect();
+    // When several tilesets match, prefer the one at the same offset
+    offsets.sort_by_key(|offset| (*offset != tileset.offset, *offset));
+
+    let by_graphics = offsets
+        .iter()
+        .find(|offset| graphics_hash(rom, **offset) == tileset.graphics_hash)
+        .map(|offset| (*offset, "graphics"));
+    let by_name = || {
+        let name = tileset.name.as_ref()?;
+        offsets
+            .iter()
+            .find(|offset| names.get(*offset) == Some(name))
+            .map(|offset| (*offset, "name"))
+    };
+    let by_offset = || {
+        offsets
+            .contains(&tileset.offset)
+            .then_some((tileset.offset, "offset"))
+    };
+
+    by_graphics.or_else(by_name).or_else(by_offset)
+}
+
+fn describe_library_tileset(tileset: &LibraryTileset) -> String {
+    match tileset.name.as_ref() {
+        Some(name) => format!("\"{}\" (${:07X})", name, tileset.offset),
+        None => format!("${:07X}", tileset.offset),
+    }
+}
+
+/// Saves the brushes of a primary tileset, and the ones of a pair
+/// of tilesets if `tileset2` is set, to a `.p3brush` file.
+#[tauri::command]
+pub fn export_brushes(
+    state: AppState,
+    rom: RomHandle,
+    tileset1: u32,
+    tileset2: Option<u32>,
+    path: String,
+) -> AppResult<()> {
+    let state = state.rom(rom)?;
+    let (names, store) = {
+        let config = state
+            .config
+            .lock()
+            .map_err(|_| AppError::lock("config data"))?;
+        (
+            config.tileset_names.clone(),
+            config.brushes.get(&tileset1).cloned().unwrap_or_default(),
+        )
+    };
+
+    let secondary_brushes = tileset2
+        .and_then(|tileset2| store.secondary.get(&tileset2).cloned())
+        .unwrap_or_default();
+
+    let library = state.read_rom(|rom| {
+        Ok(BrushLibrary {
+            version: LIBRARY_VERSION,
+            primary: library_tileset(rom, &names, tileset1),
+            secondary: tileset2.map(|tileset2| library_tileset(rom, &names, tileset2)),
+            primary_brushes: store.brushes,
+            secondary_brushes,
+        })
+    })?;
+
+    library.save(&path)
+}
+
+/// Adds the brushes of a `.p3brush` file to the ROM's config.
+///
+/// The brushes go to the given tilesets, or else to the ones of the ROM
+/// that match the library's by graphics, name or offset.
+#[tauri::command]
+pub fn import_brushes(
+    state: AppState,
+    rom: RomHandle,
+    handle: AppHandle,
+    path: String,
+    tileset1: Option<u32>,
+    tileset2: Option<u32>,
+) -> AppResult<BrushImportReport> {
+    let state = state.rom(rom)?;
+    let library = BrushLibrary::load(&path)?;
+    let names = {
+        let config = state
+            .config
+            .lock()
+            .map_err(|_| AppError::lock("config data"))?;
+        config.tileset_names.clone()
+    };
+
+    let mut warnings = vec![];
+    let (tileset1, tileset2) = state.read_rom(|rom| {
+        let mut find = |tileset: &LibraryTileset, target: Option<u32>| {
+            if target.is_some() {
+                return target;
+            }
+            let (offset, found_by) = find_library_tileset(rom, &names, tileset)?;
+            if offset != tileset.offset {
+                warnings.push(format!(
+                    "Tileset {} was matched to ${:07X} by its {}",
+                    describe_library_tileset(tileset),
+                    offset,
+                    found_by
+                ));
+            }
+            Some(offset)
+        };
+
+        let tileset1 = find(&library.primary, tileset1).ok_or_else(|| {
+            AppError::not_found(format!(
+                "Could not find tileset {} in this ROM",
+                describe_library_tileset(&library.primary)
+            ))
+        })?;
+        let tileset2 = library
+            .secondary
+            .as_ref()
+            .and_then(|secondary| find(secondary, tileset2));
+        Ok((tileset1, tileset2))
+    })?;
+
+    if let (Some(secondary), None) = (library.secondary.as_ref(), tileset2) {
+        warnings.push(format!(
+            "Could not find tileset {} in this ROM, so its {} brushes were skipped",
+            describe_library_tileset(secondary),
+            library.secondary_brushes.len()
+        ));
+    }
+
+    // Move the brushes to their new tilesets, skipping the ones that don't fit them
+    let mut lists: Vec<(Option<u32>, Vec<Brush>)> = vec![(None, vec![])];
+    if tileset2.is_some() {
+        lists.push((tileset2, vec![]));
+    }
+    let brushes = [library.primary_brushes, library.secondary_brushes];
+    for ((list_tileset2, imported), brushes) in lists.iter_mut().zip(brushes) {
+        for mut brush in brushes {
+            brush.primary = tileset1;
+            brush.secondary = *list_tileset2;
+            match validate_brush(&state, &brush, tileset1, *list_tileset2) {
+                Ok(()) => imported.push(brush),
+                Err(err) => warnings.push(format!("Skipped a brush: {}", err)),
+            }
+        }
+    }
+
+    let imported = try_update_config(&state, |config| {
+        let mut count = 0;
+        for (list_tileset2, brushes) in lists {
+            for mut brush in brushes {
+                brush.id = next_brush_id(config);
+                brush_list(config, tileset1, list_tileset2).push(brush);
+                count += 1;
+            }
+        }
+        Ok(count)
+    })?;
+
+    RomEvent::ConfigChanged.emit(&handle, state.handle);
+    Ok(BrushImportReport {
+        tileset1,
+        tileset2,
+        imported,
+        warnings,
+    })
+}
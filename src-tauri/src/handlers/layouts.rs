@@ -0,0 +1,1268 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use poly3lib::{
+    maps::{layout::MapLayoutData, render::TilesetsPair},
+    rom::{Rom, RomType},
+};
+use serde::{Deserialize, Serialize};
+use tauri::AppHandle;
+
+use crate::{
+    config::update_config,
+    error::AppError,
+    events::RomEvent,
+    formats::{
+        advance_map::{self, AdvanceMapFile},
+        tiled::{self, TiledTileset},
+        RawBlocks,
+    },
+    handlers::map_list::MapId,
+    raw,
+    settings::LayoutExportFormat,
+    state::{AppResult, AppState, AppStateFunctions, PolythreeState, RomHandle, RomState},
+};
+
+// ANCHOR Metatiles helpers
+/// Returns the number of metatiles reserved to the primary tileset.
+pub fn primary_metatiles_limit(rom_type: &RomType) -> u16 {
+    use RomType::*;
+    match rom_type {
+        FireRed | LeafGreen => 640,
+        Ruby | Sapphire | Emerald => 512,
+    }
+}
+
+/// Returns the offsets of the primary and secondary tilesets of a layout.
+pub fn layout_tilesets(layout: &MapLayoutData) -> Option<(usize, usize)> {
+    Some((
+        layout.header.primary_tileset.offset()?,
+        layout.header.secondary_tileset.offset()?,
+    ))
+}
+
+/// Reads the header, map and border data of a layout.
+pub fn read_layout_data(rom: &mut Rom, id: u16) -> AppResult<MapLayoutData> {
+    rom.map_layouts().read_data(id).map_err(|e| {
+        AppError::rom_data(format!("Error while loading layout {}: {}", id, e)).with_layout(id)
+    })
+}
+
+/// The error for a structure of a map that doesn't fit in the ROM.
+fn out_of_bounds(what: &str, offset: usize) -> AppError {
+    AppError::rom_data(format!("{} out of bounds", what)).with_offset(offset)
+}
+
+/// Writes the map and border data of a layout back to the ROM.
+pub fn write_layout_data(rom: &mut Rom, id: u16, data: MapLayoutData) -> AppResult<()> {
+    rom.map_layouts().write_data(id, data).map_err(|e| {
+        AppError::rom_data(format!("Error while writing layout {}: {}", id, e)).with_layout(id)
+    })
+}
+
+/// Reads the behaviors of all the metatiles in a tileset.
+///
+/// The attributes are read straight from the tileset header, since their
+/// layout only depends on the game: 16-bit attributes with an 8-bit behavior
+/// in R/S/E and 32-bit attributes with a 9-bit behavior in FR/LG.
+fn read_tileset_behaviors(rom: &Rom, tileset: usize) -> AppResult<Vec<u16>> {
+    let length = match rom.refs.tilesets_table.as_ref() {
+        Some(table) => table.get(&tileset).map(|x| x.0).unwrap_or(0),
+        None => return Err(AppError::rom_data("Tilesets table not found")),
+    };
+
+    let is_frlg = matches!(rom.rom_type, RomType::FireRed | RomType::LeafGreen);
+    let attributes_pointer = if is_frlg { tileset + 20 } else { tileset + 16 };
+    let attributes = raw::read_pointer(rom, attributes_pointer).ok_or_else(|| {
+        AppError::rom_data(format!(
+            "Invalid metatile attributes for tileset ${:07X}",
+            tileset
+        ))
+        .with_offset(tileset)
+    })?;
+
+    (0..length)
+        .map(|i| {
+            let behavior = if is_frlg {
+                raw::read_u32(rom, attributes + i * 4).map(|attr| attr & 0x1FF)
+            } else {
+                raw::read_u16(rom, attributes + i * 2).map(|attr| attr as u32 & 0xFF)
+            };
+            behavior.map(|behavior| behavior as u16).ok_or_else(|| {
+                AppError::rom_data(format!(
+                    "Metatile attributes of ${:07X} out of bounds",
+                    tileset
+                ))
+                .with_offset(tileset)
+            })
+        })
+        .collect()
+}
+
+// ANCHOR Metatile search
+/// What to look for in the layouts.
+#[derive(Debug, Deserialize)]
+pub enum MetatileQuery {
+    /// Any of the given metatiles
+    Ids(Vec<u16>),
+    /// Any metatile with the given behavior in the layout's tilesets
+    Behavior(u16),
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct BlockPosition {
+    x: usize,
+    y: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LayoutSearchResult {
+    /// The layout the metatiles were found in
+    layout: u16,
+    /// The maps that use this layout
+    maps: Vec<MapId>,
+    /// Where the metatiles were found
+    positions: Vec<BlockPosition>,
+}
+
+/// Resolves a [`MetatileQuery`] to the set of metatiles to look for,
+/// caching the ones that depend on the tilesets.
+struct MetatileMatcher<'q> {
+    query: &'q MetatileQuery,
+    cache: HashMap<(usize, usize), HashSet<u16>>,
+}
+
+impl<'q> MetatileMatcher<'q> {
+    fn new(query: &'q MetatileQuery) -> Self {
+        Self {
+            query,
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Returns the metatiles matching the query in the given layout.
+    fn metatiles_for(&mut self, rom: &Rom, layout: &MapLayoutData) -> AppResult<HashSet<u16>> {
+        let behavior = match self.query {
+            MetatileQuery::Ids(ids) => return Ok(ids.iter().copied().collect()),
+            MetatileQuery::Behavior(behavior) => *behavior,
+        };
+
+        let tilesets = match layout_tilesets(layout) {
+            Some(tilesets) => tilesets,
+            None => return Ok(HashSet::new()),
+        };
+        if let Some(metatiles) = self.cache.get(&tilesets) {
+            return Ok(metatiles.clone());
+        }
+
+        // Secondary metatiles start after the ones reserved to the primary
+        let limit = primary_metatiles_limit(&rom.rom_type);
+        let primary = read_tileset_behaviors(rom, tilesets.0)?;
+        let secondary = read_tileset_behaviors(rom, tilesets.1)?;
+
+        let metatiles: HashSet<u16> = primary
+            .iter()
+            .enumerate()
+            .map(|(i, b)| (i as u16, b))
+            .chain(
+                secondary
+                    .iter()
+                    .enumerate()
+                    .map(|(i, b)| (limit + i as u16, b)),
+            )
+            .filter(|(_, b)| **b == behavior)
+            .map(|(id, _)| id)
+            .collect();
+
+        self.cache.insert(tilesets, metatiles.clone());
+        Ok(metatiles)
+    }
+
+    /// Returns the positions of the matching blocks in the layout.
+    fn find(&mut self, rom: &Rom, layout: &MapLayoutData) -> AppResult<Vec<BlockPosition>> {
+        let metatiles = self.metatiles_for(rom, layout)?;
+        let width = layout.map_data.width as usize;
+
+        Ok(layout
+            .map_data
+            .metatiles
+            .iter()
+            .enumerate()
+            .filter(|(_, metatile)| metatiles.contains(metatile))
+            .map(|(i, _)| BlockPosition {
+                x: i % width,
+                y: i / width,
+            })
+            .collect())
+    }
+}
+
+/// Returns the maps using each layout.
+pub fn maps_by_layout(rom: &mut Rom) -> AppResult<HashMap<u16, Vec<MapId>>> {
+    let mut maps: HashMap<u16, Vec<MapId>> = HashMap::new();
+
+    for dump in rom
+        .map_headers()
+        .dump_headers()
+        .map_err(|e| AppError::rom_data(format!("Error while loading map headers: {}", e)))?
+    {
+        maps.entry(dump.header.map_layout_id)
+            .or_default()
+            .push(MapId {
+                group: dump.group,
+                index: dump.index,
+            });
+    }
+
+    Ok(maps)
+}
+
+#[tauri::command]
+pub fn find_metatiles(
+    state: AppState,
+    rom: RomHandle,
+    layout: u16,
+    query: MetatileQuery,
+) -> AppResult<Vec<BlockPosition>> {
+    let state = state.rom(rom)?;
+    state.with_rom(|rom| {
+        let layout = read_layout_data(rom, layout)?;
+
+        MetatileMatcher::new(&query).find(rom, &layout)
+    })
+}
+
+#[tauri::command]
+pub async fn find_metatiles_in_project<'r>(
+    state: tauri::State<'r, PolythreeState>,
+    rom: RomHandle,
+    query: MetatileQuery,
+) -> AppResult<Vec<LayoutSearchResult>> {
+    let state = state.rom(rom)?;
+    state.with_rom(|rom| {
+        let mut maps = maps_by_layout(rom)?;
+        let mut matcher = MetatileMatcher::new(&query);
+        let mut results = vec![];
+
+        for id in rom
+            .map_layouts()
+            .dump_valid()
+            .map_err(|e| AppError::rom_data(format!("Error while loading layout ids: {}", e)))?
+        {
+            let layout = match rom.map_layouts().read_data(id) {
+                Ok(layout) => layout,
+                // Skip the layouts that cannot be read
+                Err(_) => continue,
+            };
+
+            let positions = matcher.find(rom, &layout)?;
+            if !positions.is_empty() {
+                results.push(LayoutSearchResult {
+                    layout: id,
+                    maps: maps.remove(&id).unwrap_or_default(),
+                    positions,
+                });
+            }
+        }
+
+        Ok(results)
+    })
+}
+
+// ANCHOR Metatile replacement
+/// Which layouts to replace the metatiles in.
+#[derive(Debug, Deserialize)]
+pub enum ReplaceScope {
+    /// A single layout
+    Layout(u16),
+    /// All the layouts using this tilesets pair
+    Tilesets { tileset1: usize, tileset2: usize },
+    /// Every layout in the ROM
+    All,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MetatileReplacement {
+    /// The metatile to replace
+    from: u16,
+    /// The metatile to replace it with
+    to: u16,
+    /// The new elevation of the replaced blocks, if it should change
+    elevation: Option<u8>,
+    /// The new collision of the replaced blocks, if it should change
+    collision: Option<u8>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LayoutReplaceCount {
+    layout: u16,
+    changed: usize,
+}
+
+#[tauri::command]
+pub fn replace_metatiles(
+    state: AppState,
+    rom: RomHandle,
+    handle: AppHandle,
+    replacements: Vec<MetatileReplacement>,
+    scope: ReplaceScope,
+) -> AppResult<Vec<LayoutReplaceCount>> {
+    let state = state.rom(rom)?;
+    let mut by_from: HashMap<u16, MetatileReplacement> = HashMap::new();
+    for replacement in replacements {
+        let from = replacement.from;
+        if by_from.insert(from, replacement).is_some() {
+            return Err(AppError::invalid_input(format!(
+                "Metatile {} is replaced more than once",
+                from
+            )));
+        }
+    }
+    let replacements = by_from;
+
+    let counts = state.update_rom(|rom| {
+        let layout_ids = match scope {
+            ReplaceScope::Layout(id) => vec![id],
+            _ => rom.map_layouts().dump_valid().map_err(|e| {
+                AppError::rom_data(format!("Error while loading layout ids: {}", e))
+            })?,
+        };
+
+        let mut counts = vec![];
+        for id in layout_ids {
+            let mut layout = match scope {
+                ReplaceScope::Layout(_) => read_layout_data(rom, id)?,
+                _ => match rom.map_layouts().read_data(id) {
+                    Ok(layout) => layout,
+                    // Skip the layouts that cannot be read
+                    Err(_) => continue,
+                },
+            };
+
+            if let ReplaceScope::Tilesets { tileset1, tileset2 } = scope {
+                if layout_tilesets(&layout) != Some((tileset1, tileset2)) {
+                    continue;
+                }
+            }
+
+            let changed = replace_in_layout(&mut layout, &replacements);
+            if changed > 0 {
+                write_layout_data(rom, id, layout)?;
+                counts.push(LayoutReplaceCount {
+                    layout: id,
+                    changed,
+                });
+            }
+        }
+
+        Ok(counts)
+    })?;
+
+    for count in counts.iter() {
+        RomEvent::LayoutUpdated(count.layout).emit(&handle, state.handle);
+    }
+
+    Ok(counts)
+}
+
+/// Applies the replacements to the map data of a layout,
+/// returning the number of blocks that changed.
+fn replace_in_layout(
+    layout: &mut MapLayoutData,
+    replacements: &HashMap<u16, MetatileReplacement>,
+) -> usize {
+    let map_data = &mut layout.map_data;
+    let mut changed = 0;
+
+    for (metatile, level) in map_data
+        .metatiles
+        .iter_mut()
+        .zip(map_data.levels.iter_mut())
+    {
+        let replacement = match replacements.get(metatile) {
+            Some(replacement) => replacement,
+            None => continue,
+        };
+
+        // Levels are stored as (elevation << 1) | collision
+        let old_level = u16::from(*level);
+        let elevation = replacement
+            .elevation
+            .map(u16::from)
+            .unwrap_or(old_level >> 1);
+        let collision = replacement
+            .collision
+            .map(u16::from)
+            .unwrap_or(old_level & 1);
+        let new_level = ((elevation & 0xF) << 1) | (collision & 1);
+
+        if *metatile != replacement.to || new_level != old_level {
+            *metatile = replacement.to;
+            *level = new_level as _;
+            changed += 1;
+        }
+    }
+
+    changed
+}
+
+// ANCHOR Layout resizing
+/// The game's limit on the size of a map, borders included.
+const MAX_MAP_DATA_SIZE: usize = 0x2800;
+
+/// Size of each event type and position of its coordinates, in the
+/// order their counts and pointers appear in the map's events.
+const EVENT_TABLES: [(usize, usize); 4] = [
+    // Object events
+    (24, 4),
+    // Warps
+    (8, 0),
+    // Coord events
+    (16, 0),
+    // Background events
+    (12, 0),
+];
+
+/// Connection directions whose offset is along the x axis.
+const VERTICAL_CONNECTIONS: [u8; 2] = [1, 2];
+/// Connection directions whose offset is along the y axis.
+const HORIZONTAL_CONNECTIONS: [u8; 2] = [3, 4];
+
+/// The point of the layout that stays in place when resizing.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum ResizeAnchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl ResizeAnchor {
+    /// Returns how much the old blocks move in the resized layout.
+    fn shift(&self, old: (usize, usize), new: (usize, usize)) -> (i32, i32) {
+        use ResizeAnchor::*;
+        let dw = new.0 as i32 - old.0 as i32;
+        let dh = new.1 as i32 - old.1 as i32;
+
+        let dx = match self {
+            TopLeft | Left | BottomLeft => 0,
+            Top | Center | Bottom => dw / 2,
+            TopRight | Right | BottomRight => dw,
+        };
+        let dy = match self {
+            TopLeft | Top | TopRight => 0,
+            Left | Center | Right => dh / 2,
+            BottomLeft | Bottom | BottomRight => dh,
+        };
+
+        (dx, dy)
+    }
+}
+
+/// What to put in the blocks added by the resize.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum ResizeFill {
+    /// The same metatile everywhere
+    Metatile { metatile: u16, elevation: u8 },
+    /// The border blocks, tiled
+    Border { elevation: u8 },
+}
+
+/// The kinds of events, in the order of [`EVENT_TABLES`].
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Object,
+    Warp,
+    Coord,
+    Background,
+}
+
+const EVENT_KINDS: [EventKind; 4] = [
+    EventKind::Object,
+    EventKind::Warp,
+    EventKind::Coord,
+    EventKind::Background,
+];
+
+/// Checks that the game can load a layout of this size.
+fn check_layout_size(width: usize, height: usize) -> AppResult<()> {
+    if width == 0 || height == 0 {
+        return Err(AppError::invalid_input("The layout must be at least 1x1"));
+    }
+    if (width + 15) * (height + 14) > MAX_MAP_DATA_SIZE {
+        return Err(AppError::invalid_input(format!(
+            "A {}x{} layout is too big for the game",
+            width, height
+        )));
+    }
+    Ok(())
+}
+
+/// Checks that the game can use a border of this size.
+fn check_border_size(rom_type: &RomType, width: usize, height: usize) -> AppResult<()> {
+    if width == 0 || height == 0 || width > 0xFF || height > 0xFF {
+        return Err(AppError::invalid_input(format!(
+            "Invalid border size {}x{}",
+            width, height
+        )));
+    }
+    // Only FireRed and LeafGreen read the border size from the layout
+    if !matches!(rom_type, RomType::FireRed | RomType::LeafGreen) && (width, height) != (2, 2) {
+        return Err(AppError::invalid_input(format!(
+            "{} only supports 2x2 borders",
+            rom_type
+        )));
+    }
+    Ok(())
+}
+
+/// An event that is outside of its map after the resize.
+#[derive(Debug, Serialize)]
+pub struct OutsideEvent {
+    map: MapId,
+    kind: EventKind,
+    /// The position of the event in the list of its kind
+    index: usize,
+    x: i16,
+    y: i16,
+}
+
+#[tauri::command]
+pub fn resize_layout(
+    state: AppState,
+    rom: RomHandle,
+    handle: AppHandle,
+    id: u16,
+    width: usize,
+    height: usize,
+    anchor: ResizeAnchor,
+    fill: ResizeFill,
+) -> AppResult<Vec<OutsideEvent>> {
+    let state = state.rom(rom)?;
+    check_layout_size(width, height)?;
+
+    let outside = state.update_rom(|rom| {
+        let mut layout = read_layout_data(rom, id)?;
+
+        let old_size = (
+            layout.map_data.width as usize,
+            layout.map_data.height as usize,
+        );
+        let (dx, dy) = anchor.shift(old_size, (width, height));
+
+        // Build the new map data
+        let mut metatiles = Vec::with_capacity(width * height);
+        let mut levels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let (old_x, old_y) = (x as i32 - dx, y as i32 - dy);
+
+                if (0..old_size.0 as i32).contains(&old_x)
+                    && (0..old_size.1 as i32).contains(&old_y)
+                {
+                    let index = old_y as usize * old_size.0 + old_x as usize;
+                    metatiles.push(layout.map_data.metatiles[index]);
+                    levels.push(layout.map_data.levels[index]);
+                    continue;
+                }
+
+                let (metatile, elevation) = match fill {
+                    ResizeFill::Metatile {
+                        metatile,
+                        elevation,
+                    } => (metatile, elevation),
+                    ResizeFill::Border { elevation } => {
+                        let metatile = border_index(&layout, x, y)
+                            .map(|index| layout.border_data.metatiles[index])
+                            .unwrap_or(0);
+                        (metatile, elevation)
+                    }
+                };
+                metatiles.push(metatile);
+                levels.push(((elevation as u16 & 0xF) << 1) as _);
+            }
+        }
+
+        // Update the size and let the layout write path repoint the data
+        layout.map_data.metatiles = metatiles;
+        layout.map_data.levels = levels;
+        layout.map_data.width = width as _;
+        layout.map_data.height = height as _;
+        layout.header.width = width as _;
+        layout.header.height = height as _;
+        write_layout_data(rom, id, layout)?;
+
+        shift_maps_content(rom, id, dx, dy, (width, height))
+    })?;
+
+    RomEvent::LayoutUpdated(id).emit(&handle, state.handle);
+    Ok(outside)
+}
+
+/// Returns the index of the border block tiled at the given position,
+/// or `None` if the border is empty or shorter than its size.
+fn border_index(layout: &MapLayoutData, x: usize, y: usize) -> Option<usize> {
+    let border = &layout.border_data;
+    let (width, height) = (border.width as usize, border.height as usize);
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let index = (y % height) * width + x % width;
+    (index < border.metatiles.len() && index < border.levels.len()).then_some(index)
+}
+
+/// Moves the events and the connections of every map using the
+/// given layout, so that they stay in place after a resize.
+///
+/// Returns the events of these maps that are outside of the new size.
+fn shift_maps_content(
+    rom: &mut Rom,
+    layout: u16,
+    dx: i32,
+    dy: i32,
+    size: (usize, usize),
+) -> AppResult<Vec<OutsideEvent>> {
+    let dumps = rom
+        .map_headers()
+        .dump_headers()
+        .map_err(|e| AppError::rom_data(format!("Error while loading map headers: {}", e)))?;
+
+    let resized: HashSet<(u8, u8)> = dumps
+        .iter()
+        .filter(|dump| dump.header.map_layout_id == layout)
+        .map(|dump| (dump.group, dump.index))
+        .collect();
+
+    // Tables can be shared between maps, so make sure to shift them once
+    let mut shifted_events = HashSet::new();
+    let mut shifted_connections = HashSet::new();
+    let mut outside = vec![];
+
+    for dump in dumps.iter() {
+        let is_resized = resized.contains(&(dump.group, dump.index));
+
+        if is_resized {
+            if let Some(events) = dump.header.events.offset() {
+                if shifted_events.insert(events) {
+                    let map = MapId {
+                        group: dump.group,
+                        index: dump.index,
+                    };
+                    for (kind, index, x, y) in shift_events(rom, events, dx, dy, size)? {
+                        outside.push(OutsideEvent {
+                            map: map.clone(),
+                            kind,
+                            index,
+                            x,
+                            y,
+                        });
+                    }
+                }
+            }
+        }
+
+        let connections = match dump.header.connections.offset() {
+            Some(connections) => connections,
+            None => continue,
+        };
+        for (entry, direction, target) in read_connections(rom, connections)? {
+            // The resized maps' connections move with the blocks, while
+            // the connections to the resized maps move the other way
+            let sign = match (is_resized, resized.contains(&target)) {
+                (true, false) => 1,
+                (false, true) => -1,
+                _ => continue,
+            };
+            if !shifted_connections.insert(entry) {
+                continue;
+            }
+
+            let delta = if VERTICAL_CONNECTIONS.contains(&direction) {
+                dx
+            } else if HORIZONTAL_CONNECTIONS.contains(&direction) {
+                dy
+            } else {
+                continue;
+            };
+            let offset = raw::read_u32(rom, entry + 4)
+                .ok_or_else(|| out_of_bounds("Connection", entry))? as i32;
+            raw::write_u32(rom, entry + 4, (offset + sign * delta) as u32)
+                .ok_or_else(|| out_of_bounds("Connection", entry))?;
+        }
+    }
+
+    Ok(outside)
+}
+
+/// Moves all the events in the given map events by (`dx`, `dy`), returning
+/// the kind, index and position of the ones outside of a map of this size.
+fn shift_events(
+    rom: &mut Rom,
+    events: usize,
+    dx: i32,
+    dy: i32,
+    map_size: (usize, usize),
+) -> AppResult<Vec<(EventKind, usize, i16, i16)>> {
+    let mut outside = vec![];
+    for (i, (size, coords)) in EVENT_TABLES.iter().enumerate() {
+        let count =
+            raw::read_u8(rom, events + i).ok_or_else(|| out_of_bounds("Map events", events))?;
+        let table = match raw::read_pointer(rom, events + 4 + i * 4) {
+            Some(table) => table,
+            None => continue,
+        };
+
+        for j in 0..count as usize {
+            let x = table + j * size + coords;
+            let mut position = [0i16; 2];
+            for (k, (offset, delta)) in [(x, dx), (x + 2, dy)].into_iter().enumerate() {
+                let value = raw::read_u16(rom, offset)
+                    .ok_or_else(|| out_of_bounds("Event", offset))?
+                    as i16;
+                position[k] = (value as i32 + delta) as i16;
+                raw::write_u16(rom, offset, position[k] as u16)
+                    .ok_or_else(|| out_of_bounds("Event", offset))?;
+            }
+
+            let [x, y] = position;
+            if x < 0 || y < 0 || x as usize >= map_size.0 || y as usize >= map_size.1 {
+                outside.push((EVENT_KINDS[i], j, x, y));
+            }
+        }
+    }
+
+    Ok(outside)
+}
+
+/// Reads the connections at the given offset, returning for each one
+/// its offset, its direction and the map it connects to.
+fn read_connections(rom: &Rom, connections: usize) -> AppResult<Vec<(usize, u8, (u8, u8))>> {
+    let count = raw::read_u32(rom, connections)
+        .ok_or_else(|| out_of_bounds("Connections", connections))? as usize;
+    let table = match raw::read_pointer(rom, connections + 4) {
+        Some(table) => table,
+        None => return Ok(vec![]),
+    };
+
+    (0..count)
+        .map(|i| -> AppResult<_> {
+            let entry = table + i * 12;
+            let direction =
+                raw::read_u8(rom, entry).ok_or_else(|| out_of_bounds("Connection", entry))?;
+            let group =
+                raw::read_u8(rom, entry + 8).ok_or_else(|| out_of_bounds("Connection", entry))?;
+            let index =
+                raw::read_u8(rom, entry + 9).ok_or_else(|| out_of_bounds("Connection", entry))?;
+            Ok((entry, direction, (group, index)))
+        })
+        .collect()
+}
+
+// ANCHOR Border resizing
+#[tauri::command]
+pub fn resize_border(
+    state: AppState,
+    rom: RomHandle,
+    handle: AppHandle,
+    id: u16,
+    width: usize,
+    height: usize,
+) -> AppResult<()> {
+    let state = state.rom(rom)?;
+
+    state.update_rom(|rom| {
+        check_border_size(&rom.rom_type, width, height)?;
+
+        let mut layout = read_layout_data(rom, id)?;
+
+        // Tile the old border over the new one, or use metatile 0 if it's empty
+        let border = &layout.border_data;
+        let mut metatiles = Vec::with_capacity(width * height);
+        let mut levels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                match border_index(&layout, x, y) {
+                    Some(index) => {
+                        metatiles.push(border.metatiles[index]);
+                        levels.push(border.levels[index]);
+                    }
+                    None => {
+                        metatiles.push(0);
+                        levels.push(Default::default());
+                    }
+                }
+            }
+        }
+
+        // Update the size and let the layout write path repoint the border
+        layout.border_data.metatiles = metatiles;
+        layout.border_data.levels = levels;
+        layout.border_data.width = width as _;
+        layout.border_data.height = height as _;
+        layout.header.border_width = width as _;
+        layout.header.border_height = height as _;
+        write_layout_data(rom, id, layout)
+    })?;
+
+    RomEvent::LayoutUpdated(id).emit(&handle, state.handle);
+    Ok(())
+}
+
+// ANCHOR Layout duplication
+/// Creates a new layout with the same tilesets, map and border data as
+/// the given one, returning its id.
+pub fn duplicate_layout_data(rom: &mut Rom, id: u16) -> AppResult<u16> {
+    let source = read_layout_data(rom, id)?;
+    let (tileset1, tileset2) = layout_tilesets(&source).ok_or_else(|| {
+        AppError::rom_data(format!("Layout {} has invalid tilesets", id)).with_layout(id)
+    })?;
+
+    let new_id = rom
+        .map_layouts()
+        .create_data(
+            tileset1 as u32,
+            tileset2 as u32,
+            source.map_data.width as i32,
+            source.map_data.height as i32,
+        )
+        .map_err(|e| AppError::rom_data(format!("Error while creating new layout: {}", e)))?;
+
+    // Copy the blocks into the blank layout
+    let mut layout = read_layout_data(rom, new_id)?;
+    layout.header.border_width = source.header.border_width;
+    layout.header.border_height = source.header.border_height;
+    layout.map_data = source.map_data;
+    layout.border_data = source.border_data;
+    write_layout_data(rom, new_id, layout)?;
+
+    Ok(new_id)
+}
+
+#[tauri::command]
+pub fn duplicate_layout(
+    state: AppState,
+    rom: RomHandle,
+    handle: AppHandle,
+    id: u16,
+    name: String,
+) -> AppResult<u16> {
+    let state = state.rom(rom)?;
+    let new_id = state.update_rom(|rom| duplicate_layout_data(rom, id))?;
+    RomEvent::LayoutUpdated(new_id).emit(&handle, state.handle);
+
+    update_config(&state, |config| {
+        config.layout_names.insert(new_id, name);
+    })?;
+    RomEvent::ConfigChanged.emit(&handle, state.handle);
+
+    Ok(new_id)
+}
+
+// ANCHOR Import and export
+/// Where to put an imported layout.
+#[derive(Debug, Deserialize)]
+pub enum LayoutImportTarget {
+    /// Replace the blocks of an existing layout
+    Overwrite { layout: u16 },
+    /// Create a new layout with the given tilesets
+    New {
+        tileset1: u32,
+        tileset2: u32,
+        name: String,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct LayoutImportReport {
+    /// The layout the blocks were imported into
+    layout: u16,
+    /// Problems found in the imported blocks
+    warnings: Vec<String>,
+}
+
+/// Returns the map (or border) blocks of a layout in the game's format.
+pub fn layout_raw_blocks(layout: &MapLayoutData, border: bool) -> RawBlocks {
+    let bits = layout.bits_per_block as u32;
+    let data = if border {
+        &layout.border_data
+    } else {
+        &layout.map_data
+    };
+
+    RawBlocks {
+        width: data.width as usize,
+        height: data.height as usize,
+        blocks: data
+            .metatiles
+            .iter()
+            .zip(data.levels.iter())
+            .map(|(metatile, level)| (u16::from(*level) << bits) | *metatile)
+            .collect(),
+    }
+}
+
+/// Replaces the map (or border) blocks of a layout with the given ones.
+pub fn set_layout_raw_blocks(layout: &mut MapLayoutData, raw: &RawBlocks, border: bool) {
+    let bits = layout.bits_per_block as u32;
+    let mask = (1u16 << bits) - 1;
+
+    if border {
+        layout.header.border_width = raw.width as _;
+        layout.header.border_height = raw.height as _;
+    } else {
+        layout.header.width = raw.width as _;
+        layout.header.height = raw.height as _;
+    }
+
+    let data = if border {
+        &mut layout.border_data
+    } else {
+        &mut layout.map_data
+    };
+    data.width = raw.width as _;
+    data.height = raw.height as _;
+    data.metatiles = raw.blocks.iter().map(|block| block & mask).collect();
+    data.levels = raw
+        .blocks
+        .iter()
+        .map(|block| (block >> bits) as _)
+        .collect();
+}
+
+/// Returns a warning for each kind of block that the tilesets
+/// of the layout cannot display.
+pub fn validate_layout_metatiles(rom: &Rom, layout: &MapLayoutData) -> Vec<String> {
+    let (tileset1, tileset2) = match layout_tilesets(layout) {
+        Some(tilesets) => tilesets,
+        None => return vec!["The layout has invalid tilesets".to_owned()],
+    };
+    let (primary_length, secondary_length) = match rom.refs.tilesets_table.as_ref() {
+        Some(table) => (
+            table.get(&tileset1).map(|x| x.0).unwrap_or(0) as u16,
+            table.get(&tileset2).map(|x| x.0).unwrap_or(0) as u16,
+        ),
+        None => return vec!["Tilesets table not found".to_owned()],
+    };
+    let limit = primary_metatiles_limit(&rom.rom_type);
+    let is_valid = |metatile: u16| {
+        metatile < primary_length || (limit..limit + secondary_length).contains(&metatile)
+    };
+
+    let mut warnings = vec![];
+    for (data, name) in [(&layout.map_data, "map"), (&layout.border_data, "border")] {
+        let width = data.width as usize;
+        let invalid: Vec<(usize, u16)> = data
+            .metatiles
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|(_, metatile)| !is_valid(*metatile))
+            .collect();
+
+        if let Some((first, metatile)) = invalid.first() {
+            warnings.push(format!(
+                "{} {} blocks use metatiles outside the tilesets (0..{:#X}, {:#X}..{:#X}), the first is {:#X} at ({}, {})",
+                invalid.len(),
+                name,
+                primary_length,
+                limit,
+                limit + secondary_length,
+                metatile,
+                first % width,
+                first / width
+            ));
+        }
+    }
+
+    warnings
+}
+
+#[tauri::command]
+pub fn import_advance_map(
+    state: AppState,
+    rom: RomHandle,
+    handle: AppHandle,
+    path: String,
+    target: LayoutImportTarget,
+) -> AppResult<LayoutImportReport> {
+    let state = state.rom(rom)?;
+    let bytes = std::fs::read(&path)
+        .map_err(|e| AppError::file(&path, format!("Could not read {}: {}", path, e)))?;
+
+    // A .blk only has the map blocks, while a .map has the border too
+    let (map, border) = if is_blk_file(&path) {
+        (advance_map::parse_blk(&bytes)?, None)
+    } else {
+        let file = advance_map::parse_map(&bytes)?;
+        (file.map, Some(file.border))
+    };
+    if let Some(border) = border.as_ref() {
+        state.read_rom(|rom| check_border_size(&rom.rom_type, border.width, border.height))?;
+    }
+
+    import_layout(&state, &handle, target, map.width, map.height, |layout| {
+        set_layout_raw_blocks(layout, &map, false);
+        if let Some(border) = border.as_ref() {
+            set_layout_raw_blocks(layout, border, true);
+        }
+    })
+}
+
+/// Creates or overwrites the target layout, filling it with `set_blocks`.
+fn import_layout(
+    state: &RomState,
+    handle: &AppHandle,
+    target: LayoutImportTarget,
+    width: usize,
+    height: usize,
+    set_blocks: impl FnOnce(&mut MapLayoutData),
+) -> AppResult<LayoutImportReport> {
+    check_layout_size(width, height)?;
+
+    let report = state.update_rom(|rom| {
+        let id = match target {
+            LayoutImportTarget::Overwrite { layout } => layout,
+            LayoutImportTarget::New {
+                tileset1, tileset2, ..
+            } => rom
+                .map_layouts()
+                .create_data(tileset1, tileset2, width as i32, height as i32)
+                .map_err(|e| {
+                    AppError::rom_data(format!("Error while creating new layout: {}", e))
+                })?,
+        };
+
+        let mut layout = read_layout_data(rom, id)?;
+        set_blocks(&mut layout);
+
+        let warnings = validate_layout_metatiles(rom, &layout);
+        write_layout_data(rom, id, layout)?;
+
+        Ok(LayoutImportReport {
+            layout: id,
+            warnings,
+        })
+    })?;
+    RomEvent::LayoutUpdated(report.layout).emit(handle, state.handle);
+
+    if let LayoutImportTarget::New { name, .. } = target {
+        update_config(state, |config| {
+            config.layout_names.insert(report.layout, name);
+        })?;
+        RomEvent::ConfigChanged.emit(handle, state.handle);
+    }
+
+    Ok(report)
+}
+
+#[tauri::command]
+pub fn export_advance_map(state: AppState, rom: RomHandle, id: u16, path: String) -> AppResult<()> {
+    let state = state.rom(rom)?;
+    let bytes = state.with_rom(|rom| {
+        let layout = read_layout_data(rom, id)?;
+        let map = layout_raw_blocks(&layout, false);
+
+        if is_blk_file(&path) {
+            return Ok(advance_map::write_blk(&map));
+        }
+
+        // Advance Map numbers the tilesets in the order they appear in the ROM
+        let (tileset1, tileset2) = layout_tilesets(&layout).ok_or_else(|| {
+            AppError::rom_data(format!("Layout {} has invalid tilesets", id)).with_layout(id)
+        })?;
+        let tileset_number = |offset: usize| match rom.refs.tilesets_table.as_ref() {
+            Some(table) => table.keys().filter(|other| **other < offset).count() as u32,
+            None => 0,
+        };
+
+        Ok(advance_map::write_map(&AdvanceMapFile {
+            border: layout_raw_blocks(&layout, true),
+            map,
+            primary_tileset: tileset_number(tileset1),
+            secondary_tileset: tileset_number(tileset2),
+        }))
+    })?;
+
+    std::fs::write(&path, bytes)
+        .map_err(|e| AppError::file(&path, format!("Could not write {}: {}", path, e)))
+}
+
+fn is_blk_file(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("blk"))
+        .unwrap_or(false)
+}
+
+// ANCHOR Tiled
+#[tauri::command]
+pub fn export_layout_to_tiled(
+    state: AppState,
+    rom: RomHandle,
+    id: u16,
+    path: String,
+) -> AppResult<()> {
+    let state = state.rom(rom)?;
+    let layout = state.with_rom(|rom| read_layout_data(rom, id))?;
+    let (width, height, tilesets, layers) = state.read_rom(|rom| {
+        let (tileset1, tileset2) = layout_tilesets(&layout).ok_or_else(|| {
+            AppError::rom_data(format!("Layout {} has invalid tilesets", id)).with_layout(id)
+        })?;
+        let render_data = TilesetsPair::new(rom, tileset1, tileset2)
+            .map_err(|e| AppError::rom_data(format!("Error while loading tilesets: {}", e)))?
+            .get_render_data(rom)
+            .map_err(|e| AppError::rom_data(format!("Error getting tileset render data: {}", e)))?;
+
+        // The images are saved next to the map, named after it
+        let stem = Path::new(&path)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("layout")
+            .to_owned();
+        let metatiles_count = render_data.metatiles.len();
+        let (bottom, top) = tiled::render_metatile_sheets(&render_data);
+        let tilesets = [
+            (tiled::BOTTOM_LAYER, bottom, metatiles_count),
+            (tiled::TOP_LAYER, top, metatiles_count),
+            (
+                tiled::PERMISSIONS_LAYER,
+                tiled::render_permissions_sheet(),
+                tiled::PERMISSIONS_COUNT,
+            ),
+        ]
+        .map(|(name, image, tile_count)| TiledTileset {
+            name,
+            image_path: format!("{}_{}.png", stem, name),
+            image,
+            tile_count,
+        });
+
+        // Blocks outside the tilesets are left empty
+        let data = &layout.map_data;
+        let metatiles: Vec<Option<u32>> = data
+            .metatiles
+            .iter()
+            .map(|&metatile| Some(metatile as u32).filter(|&m| (m as usize) < metatiles_count))
+            .collect();
+        let permissions: Vec<Option<u32>> = data
+            .levels
+            .iter()
+            .map(|&level| {
+                Some(u16::from(level) as u32).filter(|&l| (l as usize) < tiled::PERMISSIONS_COUNT)
+            })
+            .collect();
+
+        Ok((
+            data.width as usize,
+            data.height as usize,
+            tilesets,
+            vec![metatiles.clone(), metatiles, permissions],
+        ))
+    })?;
+
+    let directory = Path::new(&path).parent().unwrap_or(Path::new(""));
+    for tileset in tilesets.iter() {
+        let image_path = directory.join(&tileset.image_path);
+        tileset.image.save(&image_path).map_err(|e| {
+            AppError::file(
+                image_path.display().to_string(),
+                format!("Could not write {}: {}", image_path.display(), e),
+            )
+        })?;
+    }
+
+    let text = if is_tmx_file(&path) {
+        tiled::write_tmx(width, height, &tilesets, &layers)
+    } else {
+        tiled::write_tmj(width, height, &tilesets, &layers)
+    };
+    std::fs::write(&path, text)
+        .map_err(|e| AppError::file(&path, format!("Could not write {}: {}", path, e)))
+}
+
+#[tauri::command]
+pub fn import_layout_from_tiled(
+    state: AppState,
+    rom: RomHandle,
+    handle: AppHandle,
+    path: String,
+    target: LayoutImportTarget,
+) -> AppResult<LayoutImportReport> {
+    let state = state.rom(rom)?;
+    let text = std::fs::read_to_string(&path)
+        .map_err(|e| AppError::file(&path, format!("Could not read {}: {}", path, e)))?;
+    let map = if is_tmx_file(&path) {
+        tiled::parse_tmx(&text)?
+    } else {
+        tiled::parse_tmj(&text)?
+    };
+
+    // The metatile is taken from the bottom layer, or the top one if it is empty
+    let bottom = map.tiles(tiled::BOTTOM_LAYER)?;
+    let top = map.tiles(tiled::TOP_LAYER)?;
+    let permissions = map.tiles(tiled::PERMISSIONS_LAYER)?;
+
+    let metatiles: Vec<u16> = bottom
+        .iter()
+        .zip(top.iter())
+        .map(|(bottom, top)| bottom.or(*top).unwrap_or(0) as u16)
+        .collect();
+    let levels: Vec<u16> = permissions
+        .iter()
+        .map(|permission| permission.unwrap_or(0) as u16)
+        .collect();
+
+    import_layout(&state, &handle, target, map.width, map.height, |layout| {
+        layout.header.width = map.width as _;
+        layout.header.height = map.height as _;
+        let data = &mut layout.map_data;
+        data.width = map.width as _;
+        data.height = map.height as _;
+        data.metatiles = metatiles;
+        data.levels = levels.into_iter().map(|level| level as _).collect();
+    })
+}
+
+fn is_tmx_file(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("tmx"))
+        .unwrap_or(false)
+}
+
+/// Exports a layout to the format of the path's extension. If the path has
+/// none of the formats' extensions, the user's preferred format is used and
+/// its extension is added to the path. Returns the path that was written.
+#[tauri::command]
+pub fn export_layout(state: AppState, rom: RomHandle, id: u16, path: String) -> AppResult<String> {
+    let (format, path) = match LayoutExportFormat::of_path(&path) {
+        Some(format) => (format, path),
+        None => {
+            let format = state
+                .settings
+                .lock()
+                .map_err(|_| AppError::lock("settings"))?
+                .export_formats
+                .layout;
+            (format, format!("{}.{}", path, format.extension()))
+        }
+    };
+
+    match format {
+        LayoutExportFormat::Map | LayoutExportFormat::Blk => {
+            export_advance_map(state, rom, id, path.clone())?
+        }
+        LayoutExportFormat::Tmx | LayoutExportFormat::Tmj => {
+            export_layout_to_tiled(state, rom, id, path.clone())?
+        }
+    }
+    Ok(path)
+}
@@ -1,3 +1,4 @@
+use gba_types::pointers::PointedData;
 use poly3lib::maps::{
     header::{MapHeader, MapHeaderData},
     layout::{MapLayout, MapLayoutData},
@@ -6,92 +7,320 @@ use poly3lib::maps::{
     tileset_anims::TilesetAnimationList,
 };
 
-use crate::state::{AppResult, AppState, AppStateFunctions, PolythreeState};
+use serde::{Deserialize, Serialize};
+use tauri::AppHandle;
+
+use crate::{
+    error::AppError,
+    events::RomEvent,
+    handlers::map_list::MapId,
+    state::{AppResult, AppState, AppStateFunctions, PolythreeState, RomHandle},
+    tint::PaletteTint,
+};
 
 #[tauri::command]
-pub fn get_map_header_data(state: AppState, group: u8, index: u8) -> AppResult<MapHeaderData> {
+pub async fn get_map_header_data<'r>(
+    state: tauri::State<'r, PolythreeState>,
+    rom: RomHandle,
+    group: u8,
+    index: u8,
+) -> AppResult<MapHeaderData> {
+    let state = state.rom(rom)?;
     state.with_rom(|rom| {
-        rom.map_headers()
-            .read_data(group, index)
-            .map_err(|e| format!("Error while loading map data: {}", e))
+        rom.map_headers().read_data(group, index).map_err(|e| {
+            AppError::rom_data(format!("Error while loading map data: {}", e))
+                .with_map(group, index)
+        })
     })
 }
 
 #[tauri::command]
-pub fn get_map_layout_data(state: AppState, id: u16) -> AppResult<MapLayoutData> {
+pub async fn get_map_layout_data<'r>(
+    state: tauri::State<'r, PolythreeState>,
+    rom: RomHandle,
+    id: u16,
+) -> AppResult<MapLayoutData> {
+    let state = state.rom(rom)?;
     state.with_rom(|rom| {
-        rom.map_layouts()
-            .read_data(id)
-            .map_err(|e| format!("Error while loading map data: {}", e))
+        rom.map_layouts().read_data(id).map_err(|e| {
+            AppError::rom_data(format!("Error while loading map data: {}", e)).with_layout(id)
+        })
     })
 }
 
 #[tauri::command]
-pub fn get_tilesets_rendering_data(
-    state: AppState,
+pub async fn get_tilesets_rendering_data<'r>(
+    state: tauri::State<'r, PolythreeState>,
+    rom: RomHandle,
     tileset1: usize,
     tileset2: usize,
 ) -> AppResult<TilesetsRenderData> {
-    state.with_rom(|rom| {
+    let state = state.rom(rom)?;
+    state.read_rom(|rom| {
         // Get the tileset
         let tilesets = TilesetsPair::new(rom, tileset1, tileset2)
-            .map_err(|e| format!("Error while loading tilesets: {}", e))?;
+            .map_err(|e| AppError::rom_data(format!("Error while loading tilesets: {}", e)))?;
         // Get the render data
         let render_data = tilesets
-            .get_render_data(&rom)
-            .map_err(|e| format!("Error getting tileset render data: {}", e))?;
+            .get_render_data(rom)
+            .map_err(|e| AppError::rom_data(format!("Error getting tileset render data: {}", e)))?;
 
         Ok(render_data)
     })
 }
 
+#[tauri::command]
+pub fn get_weather_tint(weather: u8) -> PaletteTint {
+    PaletteTint::for_weather(weather)
+}
+
 #[tauri::command]
 pub fn get_tilesets_lengths(
     state: AppState,
+    rom: RomHandle,
     tileset1: usize,
     tileset2: usize,
 ) -> AppResult<(usize, usize)> {
-    state.with_rom(|rom| match rom.refs.tilesets_table.as_ref() {
+    let state = state.rom(rom)?;
+    state.read_rom(|rom| match rom.refs.tilesets_table.as_ref() {
         Some(table) => Ok((
             table.get(&tileset1).map(|x| x.0).unwrap_or(0),
             table.get(&tileset2).map(|x| x.0).unwrap_or(0),
         )),
         None => {
-            return Err("Tilesets table not found".to_owned());
+            return Err(AppError::rom_data("Tilesets table not found"));
         }
     })
 }
 
 #[tauri::command]
-pub fn get_layout_offset(state: AppState, id: u16) -> AppResult<usize> {
+pub fn get_layout_offset(state: AppState, rom: RomHandle, id: u16) -> AppResult<usize> {
+    let state = state.rom(rom)?;
     state.with_rom(|rom| {
-        rom.map_layouts()
-            .get_header_offset(id)
-            .map_err(|e| format!("Error while converting layout id to offset: {}", e))
+        rom.map_layouts().get_header_offset(id).map_err(|e| {
+            AppError::not_found(format!("Error while converting layout id to offset: {}", e))
+                .with_layout(id)
+        })
     })
 }
 
 #[tauri::command]
 pub fn update_map_header(
     state: AppState,
+    rom: RomHandle,
+    handle: AppHandle,
     group: u8,
     index: u8,
     header: MapHeader,
 ) -> AppResult<()> {
+    let state = state.rom(rom)?;
     state.update_rom(|rom| {
         rom.map_headers()
             .write_header(group, index, header)
-            .map_err(|e| format!("Error while updating map header: {}", e))
-    })
+            .map_err(|e| {
+                AppError::rom_data(format!("Error while updating map header: {}", e))
+                    .with_map(group, index)
+            })
+    })?;
+
+    RomEvent::HeaderUpdated(MapId { group, index }).emit(&handle, state.handle);
+    Ok(())
 }
 
 #[tauri::command]
-pub fn update_layout_header(state: AppState, id: u16, header: MapLayout) -> AppResult<()> {
+pub fn update_layout_header(
+    state: AppState,
+    rom: RomHandle,
+    handle: AppHandle,
+    id: u16,
+    header: MapLayout,
+) -> AppResult<()> {
+    let state = state.rom(rom)?;
     state.update_rom(|rom| {
-        rom.map_layouts()
-            .write_header(id, header)
-            .map_err(|e| format!("Error while updating map layout header: {}", e))
-    })
+        rom.map_layouts().write_header(id, header).map_err(|e| {
+            AppError::rom_data(format!("Error while updating map layout header: {}", e))
+                .with_layout(id)
+        })
+    })?;
+
+    RomEvent::LayoutUpdated(id).emit(&handle, state.handle);
+    Ok(())
+}
+
+// ANCHOR Bulk header editing
+/// Which maps a bulk header edit applies to.
+///
+/// Empty lists match every map, otherwise the map must be in each of them.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct MapHeaderFilter {
+    groups: Vec<u8>,
+    map_types: Vec<u8>,
+    region_map_section_ids: Vec<u8>,
+    layouts: Vec<u16>,
+}
+
+impl MapHeaderFilter {
+    fn matches(&self, group: u8, header: &MapHeader) -> bool {
+        fn matches_list<T: PartialEq>(list: &[T], value: T) -> bool {
+            list.is_empty() || list.contains(&value)
+        }
+
+        matches_list(&self.groups, group)
+            && matches_list(&self.map_types, header.map_type as u8)
+            && matches_list(
+                &self.region_map_section_ids,
+                header.region_map_section_id as u8,
+            )
+            && matches_list(&self.layouts, header.map_layout_id as u16)
+    }
+}
+
+/// The fields of a [`MapHeader`] to change, the others are left as they are.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct MapHeaderPatch {
+    music: Option<u16>,
+    map_layout_id: Option<u16>,
+    region_map_section_id: Option<u8>,
+    cave: Option<u8>,
+    weather: Option<u8>,
+    map_type: Option<u8>,
+    biking_allowed: Option<u8>,
+    allow_escaping: Option<u8>,
+    allow_running: Option<u8>,
+    show_map_name: Option<u8>,
+    floor_num: Option<u8>,
+    battle_type: Option<u8>,
+}
+
+/// A field changed by a bulk header edit.
+#[derive(Debug, Serialize)]
+pub struct HeaderFieldChange {
+    field: &'static str,
+    old: u32,
+    new: u32,
+}
+
+/// The changes made to a single map by a bulk header edit.
+#[derive(Debug, Serialize)]
+pub struct MapHeaderChanges {
+    map: MapId,
+    changes: Vec<HeaderFieldChange>,
+}
+
+impl MapHeaderPatch {
+    /// Applies the patch to the header, returning the fields that changed.
+    fn apply(&self, header: &mut MapHeader) -> Vec<HeaderFieldChange> {
+        let mut changes = vec![];
+
+        macro_rules! patch_fields {
+            ($($field:ident),*) => {$(
+                if let Some(value) = self.$field {
+                    let old = header.$field as u32;
+                    if old != value as u32 {
+                        header.$field = value as _;
+                        changes.push(HeaderFieldChange {
+                            field: stringify!($field),
+                            old,
+                            new: value as u32,
+                        });
+                    }
+                }
+            )*};
+        }
+
+        patch_fields!(
+            music,
+            map_layout_id,
+            region_map_section_id,
+            cave,
+            weather,
+            map_type,
+            biking_allowed,
+            allow_escaping,
+            allow_running,
+            show_map_name,
+            floor_num,
+            battle_type
+        );
+
+        changes
+    }
+}
+
+/// Applies the same header changes to every map matching the filter,
+/// returning what changed in each map (maps left unchanged are omitted).
+#[tauri::command]
+pub fn update_map_headers_bulk(
+    state: AppState,
+    rom: RomHandle,
+    handle: AppHandle,
+    filter: MapHeaderFilter,
+    patch: MapHeaderPatch,
+) -> AppResult<Vec<MapHeaderChanges>> {
+    let state = state.rom(rom)?;
+    let result = state.update_rom(|rom| {
+        let maps = rom
+            .map_headers()
+            .dump_headers()
+            .map_err(|e| AppError::rom_data(format!("Error while reading map headers: {}", e)))?;
+
+        // The headers also point to their layout, which has to follow the new id
+        let layout_offset = match patch.map_layout_id {
+            Some(0) => {
+                return Err(AppError::invalid_input(
+                    "Maps can't be left without a layout",
+                ))
+            }
+            Some(layout) => Some(rom.map_layouts().get_header_offset(layout).map_err(|e| {
+                AppError::not_found(format!("Layout {} doesn't exist: {}", layout, e))
+                    .with_layout(layout)
+            })? as u32),
+            None => None,
+        };
+
+        let mut result = vec![];
+        for dump in maps {
+            if !filter.matches(dump.group, &dump.header) {
+                continue;
+            }
+
+            let mut header = dump.header;
+            let changes = patch.apply(&mut header);
+            if changes.is_empty() {
+                continue;
+            }
+            if let Some(offset) = layout_offset {
+                header.map_layout = PointedData::NoData(offset);
+            }
+
+            rom.map_headers()
+                .write_header(dump.group, dump.index, header)
+                .map_err(|e| {
+                    AppError::rom_data(format!(
+                        "Error while updating map header {}.{}: {}",
+                        dump.group, dump.index, e
+                    ))
+                    .with_map(dump.group, dump.index)
+                })?;
+            result.push(MapHeaderChanges {
+                map: MapId {
+                    group: dump.group,
+                    index: dump.index,
+                },
+                changes,
+            });
+        }
+
+        Ok(result)
+    })?;
+
+    for changes in result.iter() {
+        RomEvent::HeaderUpdated(changes.map.clone()).emit(&handle, state.handle);
+    }
+
+    Ok(result)
 }
 
 // ANCHOR Loading animations
@@ -116,12 +345,15 @@ pub struct ExportedAnimation {
 #[tauri::command]
 pub async fn get_tilesets_animations<'r>(
     state: tauri::State<'r, PolythreeState>,
+    rom: RomHandle,
     tileset1: usize,
     tileset2: usize,
 ) -> AppResult<ExportedTilesetsAnimations> {
+    let state = state.rom(rom)?;
+    // Loading the animations may need to load references
     state.with_rom(|rom| {
-        let mut tilesets = TilesetsPair::new(&rom, tileset1, tileset2)
-            .map_err(|e| format!("Error while loading tilesets: {}", e))?;
+        let mut tilesets = TilesetsPair::new(rom, tileset1, tileset2)
+            .map_err(|e| AppError::rom_data(format!("Error while loading tilesets: {}", e)))?;
 
         if let Err(anim) = tilesets.load_animations(rom) {
             println!("Error while loading animations: {}", anim);
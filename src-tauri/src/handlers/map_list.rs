@@ -1,60 +1,201 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
 
 use gba_types::pointers::PointedData;
-use poly3lib::maps::{header::MapHeaderDump, mapsec::MapSectionDump};
+use poly3lib::{
+    maps::{header::MapHeaderDump, mapsec::MapSectionDump},
+    rom::Rom,
+};
 
 use crate::{
     config::update_config,
-    state::{AppResult, AppState, AppStateFunctions, PolythreeState},
+    error::AppError,
+    events::RomEvent,
+    handlers::layouts::duplicate_layout_data,
+    preview_cache::render_map_preview,
+    preview_jobs::PreviewJob,
+    query::{MapQuery, SearchedMap},
+    state::{get_rom_path, AppResult, AppState, AppStateFunctions, PolythreeState, RomHandle},
+    tint::PaletteTint,
 };
 use serde::{Deserialize, Serialize};
+use tauri::AppHandle;
 
 #[tauri::command]
-pub fn get_map_list(state: AppState) -> AppResult<Vec<MapHeaderDump>> {
+pub fn get_map_list(state: AppState, rom: RomHandle) -> AppResult<Vec<MapHeaderDump>> {
+    let state = state.rom(rom)?;
     state.with_rom(|rom| {
         // Dump all the map headers
         rom.map_headers()
             .dump_headers()
-            .map_err(|err| err.to_string())
+            .map_err(|err| AppError::rom_data(err.to_string()))
     })
 }
 
+/// Returns the header of one map, like it's returned by [`get_map_list`].
+#[tauri::command]
+pub fn get_map_header(
+    state: AppState,
+    rom: RomHandle,
+    group: u8,
+    index: u8,
+) -> AppResult<MapHeaderDump> {
+    let state = state.rom(rom)?;
+    state.with_rom(|rom| dump_map_header(rom, group, index))
+}
+
+fn dump_map_header(rom: &mut Rom, group: u8, index: u8) -> AppResult<MapHeaderDump> {
+    let offset = rom
+        .map_headers()
+        .get_header_offset(group, index)
+        .map_err(|e| {
+            AppError::rom_data(format!("Error while getting offset for map: {}", e))
+                .with_map(group, index)
+        })?;
+
+    let map_header = rom.map_headers().read_header(group, index).map_err(|e| {
+        AppError::rom_data(format!("Error while reading map header: {}", e)).with_map(group, index)
+    })?;
+
+    rom.map_headers()
+        .dump_header(group, index, offset, map_header)
+        .ok_or_else(|| AppError::rom_data("Error while dumping map header").with_map(group, index))
+}
+
 #[tauri::command]
-pub fn get_map_names(state: AppState) -> AppResult<MapSectionDump> {
+pub fn get_map_names(state: AppState, rom: RomHandle) -> AppResult<MapSectionDump> {
+    let state = state.rom(rom)?;
     state.with_rom(|rom| {
         // Dump all the map headers
-        rom.mapsec().dump_names().map_err(|err| err.to_string())
+        rom.mapsec()
+            .dump_names()
+            .map_err(|err| AppError::rom_data(err.to_string()))
     })
 }
 
+/// Returns the ids of the maps matching the query, in the map list order.
+///
+/// See [`crate::query`] for the syntax.
+#[tauri::command]
+pub async fn search_maps<'r>(
+    state: tauri::State<'r, PolythreeState>,
+    rom: RomHandle,
+    query: String,
+) -> AppResult<Vec<MapId>> {
+    let state = state.rom(rom)?;
+    let query = MapQuery::parse(&query)?;
+
+    let layout_names = {
+        let config = state
+            .config
+            .lock()
+            .map_err(|_| AppError::lock("config data"))?;
+        config.layout_names.clone()
+    };
+
+    let (maps, names) = state.with_rom(|rom| {
+        let maps = rom
+            .map_headers()
+            .dump_headers()
+            .map_err(|err| AppError::rom_data(err.to_string()))?;
+        let names = rom
+            .mapsec()
+            .dump_names()
+            .map_err(|err| AppError::rom_data(err.to_string()))?;
+        Ok((maps, names))
+    })?;
+
+    Ok(maps
+        .iter()
+        .filter(|dump| {
+            let mapsec = dump.header.region_map_section_id as usize;
+            let name = mapsec
+                .checked_sub(names.start_index as usize)
+                .and_then(|i| names.names.get(i))
+                .and_then(|name| name.as_deref());
+
+            query.matches(&SearchedMap {
+                dump,
+                name,
+                layout_name: layout_names
+                    .get(&(dump.header.map_layout_id as u16))
+                    .map(|name| name.as_str()),
+            })
+        })
+        .map(|dump| MapId {
+            group: dump.group,
+            index: dump.index,
+        })
+        .collect())
+}
+
 #[tauri::command]
 pub async fn get_map_preview<'r>(
     state: tauri::State<'r, PolythreeState>,
+    rom: RomHandle,
     group: u8,
     index: u8,
+    tint: Option<PaletteTint>,
+    size: Option<u32>,
 ) -> AppResult<String> {
-    state.with_rom(|rom| {
-        // Read the header
-        let header = rom
-            .map_headers()
-            .read_header(group, index)
-            .map_err(|err| err.to_string())?;
-
-        // Read the layout
-        let layout = rom
-            .map_layouts()
-            .read_data(header.map_layout_id)
-            .map_err(|err| err.to_string())?;
-
-        // Render the tileset
-        let tilesets = layout
-            .read_tilesets(&rom)
-            .map_err(|_| "Error while reading the tileset")?;
-        let rendered = tilesets.render();
-
-        // Render the map
-        Ok(layout.render_to_base64(&rendered))
-    })
+    let state = state.rom(rom)?;
+    render_map_preview(&state, group, index, size, tint)
+}
+
+/// Queues the previews of the maps to be rendered in the background,
+/// each sent with a `map-preview` event when finished.
+#[tauri::command]
+pub fn queue_map_previews(
+    state: AppState,
+    rom: RomHandle,
+    handle: AppHandle,
+    maps: Vec<MapId>,
+    size: Option<u32>,
+    tint: Option<PaletteTint>,
+) -> AppResult<()> {
+    let rom_state = state.rom(rom)?;
+    let jobs = maps
+        .into_iter()
+        .map(|map| PreviewJob {
+            map,
+            size,
+            tint,
+            state: rom_state.clone(),
+        })
+        .collect();
+
+    state.preview_jobs.push(&handle, jobs);
+    Ok(())
+}
+
+/// Removes the given maps' previews of a ROM from the queue, or all of its previews if `None`.
+#[tauri::command]
+pub fn cancel_map_previews(state: AppState, rom: RomHandle, maps: Option<Vec<MapId>>) {
+    match maps {
+        Some(maps) => state
+            .preview_jobs
+            .cancel(|job| job.state.handle == rom && maps.contains(&job.map)),
+        None => state.preview_jobs.cancel(|job| job.state.handle == rom),
+    }
+}
+
+/// Also saves the map previews in a folder next to the ROM, so
+/// that they don't need to be rendered again the next time.
+#[tauri::command]
+pub fn set_preview_disk_cache(state: AppState, rom: RomHandle, enabled: bool) -> AppResult<()> {
+    let state = state.rom(rom)?;
+    let path = match enabled {
+        true => Some(PathBuf::from(format!("{}.previews", get_rom_path(&state)?))),
+        false => None,
+    };
+
+    state
+        .previews
+        .lock()
+        .map_err(|_| AppError::lock("previews cache"))?
+        .set_disk_path(path)
 }
 
 #[derive(Debug, Deserialize)]
@@ -63,10 +204,10 @@ pub struct MapIdLayout {
     index: u8,
     layout: u16,
 }
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MapId {
-    group: u8,
-    index: u8,
+    pub(crate) group: u8,
+    pub(crate) index: u8,
 }
 impl std::fmt::Debug for MapId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -92,9 +233,12 @@ pub enum LayoutActionType {
 #[tauri::command]
 pub fn delete_maps(
     state: AppState,
+    rom: RomHandle,
+    handle: AppHandle,
     maps: Vec<MapIdLayout>,
     actions: HashMap<String, LayoutAction>,
 ) -> AppResult<Vec<MapId>> {
+    let state = state.rom(rom)?;
     let mut maps_to_delete: Vec<MapId> = vec![];
     let mut maps_to_update: Vec<(u16, Vec<MapId>)> = vec![];
     let mut layouts_to_delete: HashSet<u16> = HashSet::new();
@@ -124,6 +268,11 @@ pub fn delete_maps(
         }
     }
 
+    let updated_maps: Vec<MapId> = maps_to_update
+        .iter()
+        .flat_map(|(_, maps)| maps.iter().cloned())
+        .collect();
+
     println!("Maps to Delete: \n{:?}", maps_to_delete);
     println!("Maps to Update: \n{:?}", maps_to_update);
     println!("Layouts to Delete: \n{:?}", layouts_to_delete);
@@ -134,9 +283,10 @@ pub fn delete_maps(
 
         // Delete all maps
         for MapId { group, index } in maps_to_delete.iter() {
-            let scripts = headers
-                .delete_header(*group, *index)
-                .map_err(|e| format!("Error while deleting map {}.{}: {}", group, index, e))?;
+            let scripts = headers.delete_header(*group, *index).map_err(|e| {
+                AppError::rom_data(format!("Error while deleting map: {}", e))
+                    .with_map(*group, *index)
+            })?;
 
             scripts_to_remove.extend(scripts);
         }
@@ -146,9 +296,10 @@ pub fn delete_maps(
         // Delete the layouts
         let mut layouts = rom.map_layouts();
         for layout in layouts_to_delete.iter() {
-            layouts
-                .delete_layout(*layout)
-                .map_err(|e| format!("Error while deleting layout {}: {}", layout, e))?;
+            layouts.delete_layout(*layout).map_err(|e| {
+                AppError::rom_data(format!("Error while deleting layout: {}", e))
+                    .with_layout(*layout)
+            })?;
         }
 
         // Change the requested headers
@@ -156,10 +307,11 @@ pub fn delete_maps(
         for (layout, maps) in maps_to_update {
             for MapId { group, index } in maps {
                 let mut map_header = headers.read_header(group, index).map_err(|e| {
-                    format!(
-                        "Error while updating map {}.{} to layout {}: {}",
-                        group, index, layout, e
-                    )
+                    AppError::rom_data(format!(
+                        "Error while updating the map to layout {}: {}",
+                        layout, e
+                    ))
+                    .with_map(group, index)
                 })?;
                 map_header.map_layout_id = layout;
                 if layout == 0 {
@@ -171,17 +323,19 @@ pub fn delete_maps(
                             .map_layouts()
                             .get_header_offset(layout)
                             .map_err(|e| {
-                                format!(
-                                    "Error while getting layout offset for id {}: {}",
-                                    layout, e
-                                )
+                                AppError::not_found(format!(
+                                    "Error while getting the layout offset: {}",
+                                    e
+                                ))
+                                .with_layout(layout)
                             })? as u32,
                     );
                 }
                 headers
                     .write_header(group, index, map_header)
                     .map_err(|e| {
-                        format!("Error while writing header {}.{}: {}", group, index, e)
+                        AppError::rom_data(format!("Error while writing header: {}", e))
+                            .with_map(group, index)
                     })?;
             }
         }
@@ -189,20 +343,26 @@ pub fn delete_maps(
         Ok(maps_to_delete)
     })?;
 
-    update_config(state, |config| {
+    RomEvent::MapsDeleted(res.clone()).emit(&handle, state.handle);
+    for map in updated_maps {
+        RomEvent::HeaderUpdated(map).emit(&handle, state.handle);
+    }
+
+    update_config(&state, |config| {
         for layout in layouts_to_delete.iter() {
             // Find and remove the name in the configs
             config.layout_names.remove(layout);
         }
     })?;
+    RomEvent::ConfigChanged.emit(&handle, state.handle);
 
     Ok(res)
 }
 
 fn parse_u16(number: String) -> AppResult<u16> {
-    number
-        .parse::<u16>()
-        .map_err(|e| format!("Svelte-side mishap: Failed to parse string. {}", e))
+    number.parse::<u16>().map_err(|e| {
+        AppError::invalid_input(format!("Svelte-side mishap: Failed to parse string. {}", e))
+    })
 }
 
 #[derive(Serialize)]
@@ -212,8 +372,9 @@ pub struct TilesetType {
 }
 
 #[tauri::command]
-pub fn get_tilesets(state: AppState) -> AppResult<Vec<TilesetType>> {
-    state.with_rom(|rom| match rom.refs.tilesets_table {
+pub fn get_tilesets(state: AppState, rom: RomHandle) -> AppResult<Vec<TilesetType>> {
+    let state = state.rom(rom)?;
+    state.read_rom(|rom| match rom.refs.tilesets_table {
         Some(ref table) => {
             let mut tilesets = vec![];
 
@@ -230,11 +391,12 @@ pub fn get_tilesets(state: AppState) -> AppResult<Vec<TilesetType>> {
 }
 
 #[tauri::command]
-pub fn get_layout_ids(state: AppState) -> AppResult<Vec<u16>> {
+pub fn get_layout_ids(state: AppState, rom: RomHandle) -> AppResult<Vec<u16>> {
+    let state = state.rom(rom)?;
     state.with_rom(|rom| {
         rom.map_layouts()
             .dump_valid()
-            .map_err(|e| format!("Error while loading layout ids: {}", e))
+            .map_err(|e| AppError::rom_data(format!("Error while loading layout ids: {}", e)))
     })
 }
 
@@ -250,20 +412,27 @@ pub enum MapCreationLayoutOptions {
         tileset2: u32,
         name: String,
     },
+    Duplicate {
+        layout: u16,
+        name: String,
+    },
 }
 
 #[tauri::command]
 pub fn create_map(
     state: AppState,
+    rom: RomHandle,
+    handle: AppHandle,
     group: u8,
     index: u8,
     layout_options: MapCreationLayoutOptions,
 ) -> AppResult<MapHeaderDump> {
+    let state = state.rom(rom)?;
     use MapCreationLayoutOptions::*;
 
     let mut layout_id = 0;
 
-    let res = state.with_rom(|rom| {
+    let res = state.update_rom(|rom| {
         layout_id = match layout_options {
             Use { layout } => layout,
             New {
@@ -275,39 +444,35 @@ pub fn create_map(
             } => rom
                 .map_layouts()
                 .create_data(tileset1, tileset2, width, height)
-                .map_err(|e| format!("Error while creating new layout: {}", e))?,
+                .map_err(|e| {
+                    AppError::rom_data(format!("Error while creating new layout: {}", e))
+                })?,
+            Duplicate { layout, .. } => duplicate_layout_data(rom, layout)?,
         };
 
         rom.map_headers()
             .create_header(group, index, layout_id)
-            .map_err(|e| format!("Error while creating new map: {}", e))?;
-
-        let offset = rom
-            .map_headers()
-            .get_header_offset(group, index)
             .map_err(|e| {
-                format!(
-                    "Error while getting offset for new map {}.{}: {}",
-                    group, index, e
-                )
+                AppError::rom_data(format!("Error while creating new map: {}", e))
+                    .with_map(group, index)
             })?;
 
-        let map_header = rom
-            .map_headers()
-            .read_header(group, index)
-            .map_err(|e| format!("Error while reading new map header: {}", e))?;
-
-        rom.map_headers()
-            .dump_header(group, index, offset, map_header)
-            .ok_or("Error while dumping new map header".to_string())
+        dump_map_header(rom, group, index)
     })?;
 
-    update_config(state, |config| match layout_options {
-        MapCreationLayoutOptions::New { name, .. } => {
+    RomEvent::MapCreated(MapId { group, index }).emit(&handle, state.handle);
+    if !matches!(layout_options, Use { .. }) {
+        RomEvent::LayoutUpdated(layout_id).emit(&handle, state.handle);
+    }
+
+    update_config(&state, |config| match layout_options {
+        MapCreationLayoutOptions::New { name, .. }
+        | MapCreationLayoutOptions::Duplicate { name, .. } => {
             config.layout_names.insert(layout_id, name);
         }
         _ => {}
     })?;
+    RomEvent::ConfigChanged.emit(&handle, state.handle);
 
     Ok(res)
 }
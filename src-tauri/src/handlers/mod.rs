@@ -1,3 +1,6 @@
+pub mod brushes;
+pub mod layouts;
 pub mod map_editor;
 pub mod map_list;
 pub mod rom;
+pub mod transplant;
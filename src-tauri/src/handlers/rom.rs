@@ -1,63 +1,179 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use poly3lib::rom::Rom;
 use serde::Serialize;
 use tauri::AppHandle;
 
 use crate::{
-    config::RomConfig,
-    state::{AppResult, AppState, AppStateFunctions},
+    config::{ConfigMismatch, RomConfig, RomIdentity},
+    error::AppError,
+    settings::{update_settings, RecentRom, RomSession},
+    state::{already_open, get_rom_path, AppResult, AppState, AppStateFunctions, RomHandle},
 };
 
 #[derive(Serialize)]
 pub struct OpenRom {
+    /// The handle the other commands use to refer to the ROM
+    handle: RomHandle,
     rom_type: String,
     rom_size: usize,
     rom_size_fmt: String,
+    /// Set if the config was made for a different ROM
+    config_mismatch: Option<ConfigMismatch>,
 }
 
 #[tauri::command]
 pub fn init_rom(state: AppState, handle: AppHandle, path: String) -> AppResult<OpenRom> {
     println!("Loading ROM: {}", path);
+    // A file can only be opened once, or the saves of one state would undo the other's
+    if let Some(open) = state.open_handle(&path)? {
+        return Err(already_open(open));
+    }
 
     match Rom::load(&path) {
         Ok(mut rom) => {
             // Initialize the ROM's references so that
             // later operations will be faster
             if let Err(err) = rom.init_map() {
-                return Err(format!("Failed to initialize references: {}", err));
+                return Err(AppError::rom_data(format!(
+                    "Failed to initialize references: {}",
+                    err
+                )));
             }
 
             // Save the references
             if let Err(err) = rom.save_refs(&path) {
-                return Err(format!("Failed to save references: {}", err));
+                return Err(AppError::file(
+                    &path,
+                    format!("Failed to save references: {}", err),
+                ));
             }
 
             // Check if you have the config file near the ROM.
             // If you don't, create it.
-            let config = RomConfig::init(handle, &path, &rom.rom_type)?;
+            let identity = RomIdentity::of(&mut rom);
+            let (config, config_path, config_mismatch) =
+                RomConfig::init(handle, &path, &rom.rom_type, &identity)?;
+
+            // Store the opened ROM in the state
+            let rom_type = rom.rom_type.to_string();
+            let rom_size = rom.data.len();
+            let rom_handle = state.open_rom(path.clone(), rom, config, config_path)?;
 
             // Prepare the response
             let res = OpenRom {
-                rom_type: rom.rom_type.to_string(),
-                rom_size: rom.data.len(),
+                handle: rom_handle,
+                rom_type: rom_type.clone(),
+                rom_size,
                 rom_size_fmt: {
-                    let bytes = rom.data.len() as u64;
+                    let bytes = rom_size as u64;
                     let bytes = byte_unit::Byte::from_bytes(bytes);
                     let bytes = bytes.get_appropriate_unit(true);
                     format!("{}", bytes)
                 },
+                config_mismatch,
             };
 
-            // Store the opened ROM in the state
-            // Update the rom_path in the AppState
-            state.set_rom(path, rom, config);
+            // Add the ROM to the recent ones, which is not worth failing for
+            let recent = RecentRom {
+                path,
+                rom_type,
+                size: rom_size,
+                last_opened: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|time| time.as_secs())
+                    .unwrap_or(0),
+            };
+            if let Err(err) = update_settings(&state, |settings| settings.add_recent_rom(recent)) {
+                println!("Could not save the recent ROMs: {}", err);
+            }
 
             Ok(res)
         }
-        Err(err) => Err(err.to_string()),
+        Err(err) => Err(AppError::file(&path, err.to_string())),
     }
 }
 
 #[tauri::command]
-pub fn close_rom(state: AppState) {
-    state.clear_rom();
+pub fn close_rom(state: AppState, rom: RomHandle) -> AppResult<()> {
+    state.close_rom(rom)
+}
+
+#[derive(Serialize)]
+pub struct OpenRomInfo {
+    handle: RomHandle,
+    path: String,
+}
+
+/// Returns the ROMs that are open, in the order they were opened.
+#[tauri::command]
+pub fn get_open_roms(state: AppState) -> AppResult<Vec<OpenRomInfo>> {
+    state
+        .roms()?
+        .iter()
+        .map(|rom| {
+            Ok(OpenRomInfo {
+                handle: rom.handle,
+                path: get_rom_path(rom)?,
+            })
+        })
+        .collect()
+}
+
+// ANCHOR Recent ROMs and sessions
+#[tauri::command]
+pub fn get_recent_roms(state: AppState) -> AppResult<Vec<RecentRom>> {
+    let settings = state
+        .settings
+        .lock()
+        .map_err(|_| AppError::lock("settings"))?;
+    Ok(settings.recent_roms.clone())
+}
+
+#[tauri::command]
+pub fn remove_recent_rom(state: AppState, path: String) -> AppResult<()> {
+    update_settings(&state, |settings| {
+        settings.recent_roms.retain(|recent| recent.path != path);
+    })
+}
+
+/// Saves the tabs that are open for a ROM.
+#[tauri::command]
+pub fn save_session(state: AppState, rom: RomHandle, session: RomSession) -> AppResult<()> {
+    let path = get_rom_path(&state.rom(rom)?)?;
+    update_settings(&state, |settings| {
+        settings.sessions.insert(path, session);
+    })
+}
+
+/// Returns the tabs that were open the last time a ROM was used,
+/// without the map editors of the maps that don't exist anymore.
+#[tauri::command]
+pub fn restore_session(state: AppState, rom: RomHandle) -> AppResult<RomSession> {
+    let rom_state = state.rom(rom)?;
+    let path = get_rom_path(&rom_state)?;
+    let session = {
+        let settings = state
+            .settings
+            .lock()
+            .map_err(|_| AppError::lock("settings"))?;
+        settings.sessions.get(&path).cloned().unwrap_or_default()
+    };
+
+    rom_state.with_rom(|rom| {
+        let active = session.active;
+        let mut restored = RomSession::default();
+        for (position, tab) in session.tabs.into_iter().enumerate() {
+            if let Some(map) = tab.map.as_ref() {
+                if rom.map_headers().read_header(map.group, map.index).is_err() {
+                    continue;
+                }
+            }
+            if active == Some(position) {
+                restored.active = Some(restored.tabs.len());
+            }
+            restored.tabs.push(tab);
+        }
+        Ok(restored)
+    })
 }
@@ -0,0 +1,896 @@
+use std::path::Path;
+
+use gba_types::pointers::PointedData;
+use poly3lib::{
+    maps::{header::MapHeader, layout::MapLayoutData},
+    rom::{Rom, RomType},
+};
+use serde::{Deserialize, Serialize};
+use tauri::AppHandle;
+
+use crate::{
+    config::update_config,
+    error::AppError,
+    events::RomEvent,
+    handlers::{
+        layouts::{
+            layout_tilesets, primary_metatiles_limit, read_layout_data, validate_layout_metatiles,
+            write_layout_data,
+        },
+        map_list::MapId,
+    },
+    raw::{self, FreeSpace},
+    state::{get_rom_path, AppResult, AppState, AppStateFunctions, RomHandle},
+};
+
+// ANCHOR Options and report
+/// Which tilesets the transplanted layout uses.
+#[derive(Debug, Deserialize)]
+pub enum TransplantTilesets {
+    /// Copy the tilesets of the source map, reusing the ones
+    /// of the ROM that have the same graphics
+    Copy,
+    /// Use tilesets that are already in the ROM
+    Use { tileset1: u32, tileset2: u32 },
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransplantOptions {
+    tilesets: TransplantTilesets,
+    /// Copy the object events, warps, triggers and signs
+    events: bool,
+    /// Keep the pointers to the scripts of the source ROM, which
+    /// only makes sense when both ROMs are based on the same game
+    keep_scripts: bool,
+    /// Copy the wild encounters
+    encounters: bool,
+}
+
+/// What a reference to the data of the source ROM is to.
+#[derive(Debug, Serialize)]
+pub enum ReferenceKind {
+    Flag,
+    Var,
+    Script,
+    Map,
+    Animation,
+}
+
+/// A reference that could not be brought over, to check by hand.
+#[derive(Debug, Serialize)]
+pub struct UnresolvedReference {
+    kind: ReferenceKind,
+    description: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransplantedTileset {
+    /// The offset of the tileset in the source ROM
+    source: u32,
+    /// The offset of the tileset in this ROM
+    offset: u32,
+    /// Whether the tileset was copied, or one with the same graphics was already there
+    copied: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransplantReport {
+    /// The new map and its layout
+    map: MapId,
+    layout: u16,
+    /// The tilesets of the source map, if they were copied
+    tilesets: Vec<TransplantedTileset>,
+    unresolved: Vec<UnresolvedReference>,
+    /// The parts that could not be copied and the blocks outside the tilesets
+    warnings: Vec<String>,
+}
+
+fn is_frlg(rom_type: &RomType) -> bool {
+    matches!(rom_type, RomType::FireRed | RomType::LeafGreen)
+}
+
+/// Writes the bytes to free space in the ROM, returning where they are.
+fn write_to_free_space(
+    rom: &mut Rom,
+    free_space: &mut FreeSpace,
+    bytes: &[u8],
+    what: &str,
+) -> AppResult<usize> {
+    free_space.write(rom, bytes).ok_or_else(|| {
+        AppError::out_of_space(format!(
+            "Not enough free space for the {} ({} bytes)",
+            what,
+            bytes.len()
+        ))
+    })
+}
+
+/// Writes a pointer in a struct that is going to be written to the ROM.
+fn set_pointer(bytes: &mut [u8], offset: usize, target: Option<usize>) {
+    let value = target.map(|target| target as u32 + 0x08000000).unwrap_or(0);
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Reads a pointer in a struct that was read from the ROM.
+fn get_pointer(bytes: &[u8], offset: usize) -> Option<usize> {
+    let value = u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?) as usize;
+    match value {
+        0x08000000..=0x09FFFFFF => Some(value - 0x08000000),
+        _ => None,
+    }
+}
+
+fn get_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+// ANCHOR Tilesets
+/// The size of the tileset struct.
+const TILESET_SIZE: usize = 24;
+/// The tiles that fit in VRAM, split between the tilesets like the metatiles.
+const TILES_COUNT: usize = 1024;
+
+/// A tileset with the data it points to.
+struct TilesetData {
+    /// The offset of the tileset in its ROM
+    offset: usize,
+    /// The tileset struct, whose pointers are replaced when it's written
+    header: Vec<u8>,
+    tiles: Vec<u8>,
+    palettes: Vec<u8>,
+    metatiles: Vec<u8>,
+    attributes: Vec<u8>,
+    /// Whether the tileset has animations, which are code that can't be copied
+    animated: bool,
+}
+
+/// Returns where the attributes and the animations callback
+/// are in the tileset struct, which R/S/E and FR/LG swap.
+fn tileset_pointers(frlg: bool) -> (usize, usize) {
+    match frlg {
+        true => (20, 16),
+        false => (16, 20),
+    }
+}
+
+impl TilesetData {
+    fn read(rom: &Rom, offset: usize) -> AppResult<Self> {
+        let invalid =
+            || AppError::rom_data(format!("Invalid tileset ${:07X}", offset)).with_offset(offset);
+        let frlg = is_frlg(&rom.rom_type);
+        let (attributes_pointer, callback_pointer) = tileset_pointers(frlg);
+        let metatiles_count = rom
+            .refs
+            .tilesets_table
+            .as_ref()
+            .and_then(|table| table.get(&offset))
+            .map(|x| x.0)
+            .unwrap_or(0);
+
+        let header = raw::read_bytes(rom, offset, TILESET_SIZE).ok_or_else(invalid)?;
+        let compressed = header[0] != 0;
+        let secondary = header[1] != 0;
+
+        let tiles_size = if compressed {
+            let tiles = get_pointer(header, 4).ok_or_else(invalid)?;
+            raw::lz77_size(rom, tiles).ok_or_else(invalid)?
+        } else {
+            // Without compression there is no size, so take all the room of the tileset
+            let limit = primary_metatiles_limit(&rom.rom_type) as usize;
+            32 * if secondary {
+                TILES_COUNT - limit
+            } else {
+                limit
+            }
+        };
+        let slice = |pointer_offset: usize, size: usize| -> AppResult<Vec<u8>> {
+            let start = get_pointer(header, pointer_offset).ok_or_else(invalid)?;
+            raw::read_bytes(rom, start, size)
+                .map(<[u8]>::to_vec)
+                .ok_or_else(invalid)
+        };
+
+        Ok(Self {
+            offset,
+            header: header.to_vec(),
+            tiles: slice(4, tiles_size)?,
+            palettes: slice(8, raw::TILESET_PALETTES_SIZE)?,
+            metatiles: slice(12, metatiles_count * 16)?,
+            attributes: slice(
+                attributes_pointer,
+                metatiles_count * if frlg { 4 } else { 2 },
+            )?,
+            animated: get_pointer(header, callback_pointer).is_some(),
+        })
+    }
+
+    /// Whether both tilesets look and behave the same.
+    fn same_graphics(&self, other: &Self) -> bool {
+        self.header[..2] == other.header[..2]
+            && self.tiles == other.tiles
+            && self.palettes == other.palettes
+            && self.metatiles == other.metatiles
+            && self.attributes == other.attributes
+    }
+
+    /// Writes the tileset and its data to free space, without its
+    /// animations, returning its offset.
+    fn write(&self, rom: &mut Rom, free_space: &mut FreeSpace) -> AppResult<usize> {
+        let (attributes_pointer, callback_pointer) = tileset_pointers(is_frlg(&rom.rom_type));
+        let mut header = self.header.clone();
+
+        for (pointer_offset, data, what) in [
+            (4, &self.tiles, "tileset graphics"),
+            (8, &self.palettes, "tileset palettes"),
+            (12, &self.metatiles, "metatiles"),
+            (attributes_pointer, &self.attributes, "metatile attributes"),
+        ] {
+            let offset = write_to_free_space(rom, free_space, data, what)?;
+            set_pointer(&mut header, pointer_offset, Some(offset));
+        }
+        set_pointer(&mut header, callback_pointer, None);
+
+        write_to_free_space(rom, free_space, &header, "tileset")
+    }
+}
+
+/// Returns the tileset of the ROM with the same graphics, if any.
+fn find_same_tileset(rom: &Rom, tileset: &TilesetData) -> Option<usize> {
+    let mut offsets: Vec<usize> = rom.refs.tilesets_table.as_ref()?.keys().copied().collect();
+    offsets.sort();
+
+    offsets.into_iter().find(|offset| {
+        TilesetData::read(rom, *offset)
+            .map(|candidate| candidate.same_graphics(tileset))
+            .unwrap_or(false)
+    })
+}
+
+// ANCHOR Events
+/// The size of the events struct.
+const EVENTS_SIZE: usize = 20;
+/// The size of each kind of event, in the order of the events struct.
+const EVENT_SIZES: [usize; 4] = [24, 8, 16, 12];
+const EVENT_NAMES: [&str; 4] = ["Object event", "Warp", "Trigger", "Sign"];
+
+/// Reads the object events, warps, triggers and signs of a map, listing what
+/// they use from the source ROM and clearing their scripts unless they are kept.
+fn read_events(
+    rom: &Rom,
+    offset: usize,
+    keep_scripts: bool,
+    unresolved: &mut Vec<UnresolvedReference>,
+) -> AppResult<[Vec<u8>; 4]> {
+    let invalid =
+        || AppError::rom_data(format!("Invalid map events at ${:07X}", offset)).with_offset(offset);
+    let frlg = is_frlg(&rom.rom_type);
+    let mut lists: [Vec<u8>; 4] = Default::default();
+
+    for (kind, list) in lists.iter_mut().enumerate() {
+        let count = raw::read_u8(rom, offset + kind).ok_or_else(invalid)? as usize;
+        if count == 0 {
+            continue;
+        }
+        let size = EVENT_SIZES[kind];
+        let start = raw::read_pointer(rom, offset + 4 + kind * 4).ok_or_else(invalid)?;
+        *list = raw::read_bytes(rom, start, count * size)
+            .ok_or_else(invalid)?
+            .to_vec();
+
+        for (index, event) in list.chunks_mut(size).enumerate() {
+            let context = format!("{} {}", EVENT_NAMES[kind], index + 1);
+            let mut script = |event: &mut [u8], at: usize| {
+                if let Some(script) = get_pointer(event, at) {
+                    let action = if keep_scripts { "kept" } else { "removed" };
+                    unresolved.push(UnresolvedReference {
+                        kind: ReferenceKind::Script,
+                        description: format!(
+                            "{} runs the script at ${:07X}, which was {}",
+                            context, script, action
+                        ),
+                    });
+                    if !keep_scripts {
+                        set_pointer(event, at, None);
+                    }
+                }
+            };
+
+            let reference = match kind {
+                0 => {
+                    script(event, 16);
+                    let flag = get_u16(event, 20);
+                    (flag != 0).then(|| (ReferenceKind::Flag, format!("uses flag {:#X}", flag)))
+                }
+                1 => Some((
+                    ReferenceKind::Map,
+                    format!("leads to map {}.{} of the source ROM", event[7], event[6]),
+                )),
+                2 => {
+                    script(event, 12);
+                    let var = get_u16(event, 6);
+                    (var != 0).then(|| (ReferenceKind::Var, format!("checks var {:#X}", var)))
+                }
+                _ => match event[5] {
+                    // The signs that run a script when the player faces them
+                    0..=4 => {
+                        script(event, 8);
+                        None
+                    }
+                    // Hidden items and secret bases, whose flag is next to the item
+                    _ => {
+                        let flag = get_u16(event, 10) & if frlg { 0xFF } else { 0xFFFF };
+                        Some((
+                            ReferenceKind::Flag,
+                            format!("is a hidden item or secret base using flag {:#X}", flag),
+                        ))
+                    }
+                },
+            };
+
+            if let Some((kind, description)) = reference {
+                unresolved.push(UnresolvedReference {
+                    kind,
+                    description: format!("{} {}", context, description),
+                });
+            }
+        }
+    }
+
+    Ok(lists)
+}
+
+/// Writes the events to free space, returning the offset of the events struct.
+fn write_events(
+    rom: &mut Rom,
+    free_space: &mut FreeSpace,
+    lists: &[Vec<u8>; 4],
+) -> AppResult<usize> {
+    let mut events = vec![0; EVENTS_SIZE];
+    for (kind, list) in lists.iter().enumerate() {
+        if list.is_empty() {
+            continue;
+        }
+        let offset = write_to_free_space(rom, free_space, list, EVENT_NAMES[kind])?;
+        events[kind] = (list.len() / EVENT_SIZES[kind]) as u8;
+        set_pointer(&mut events, 4 + kind * 4, Some(offset));
+    }
+    write_to_free_space(rom, free_space, &events, "map events")
+}
+
+/// Lists the connections of a map, which lead to maps of the source ROM.
+fn read_connections(rom: &Rom, offset: usize, unresolved: &mut Vec<UnresolvedReference>) {
+    const DIRECTIONS: [&str; 7] = ["", "south", "north", "west", "east", "dive", "emerge"];
+
+    let count = raw::read_u32(rom, offset).unwrap_or(0) as usize;
+    let Some(list) = raw::read_pointer(rom, offset + 4) else {
+        return;
+    };
+    for connection in (0..count).filter_map(|i| raw::read_bytes(rom, list + i * 12, 12)) {
+        unresolved.push(UnresolvedReference {
+            kind: ReferenceKind::Map,
+            description: format!(
+                "The {} connection to map {}.{} of the source ROM was not copied",
+                DIRECTIONS.get(connection[0] as usize).unwrap_or(&"unknown"),
+                connection[8],
+                connection[9]
+            ),
+        });
+    }
+}
+
+// ANCHOR Wild encounters
+/// The size of an entry of the wild encounters table.
+const WILD_HEADER_SIZE: usize = 20;
+/// How many Pokémon each kind of encounter has: land, water, rock smash and fishing.
+const WILD_MONS_COUNTS: [usize; 4] = [12, 5, 5, 10];
+/// How many entries the wild encounters table has at least, to be trusted.
+const WILD_TABLE_MIN_LENGTH: usize = 16;
+
+/// Whether there is a wild encounters entry at the offset: a map whose
+/// encounter pointers are null or point to a rate followed by a pointer.
+fn is_wild_header(rom: &Rom, offset: usize) -> bool {
+    if !matches!(raw::read_u8(rom, offset), Some(group) if group != 0xFF) {
+        return false;
+    }
+
+    let mut has_encounters = false;
+    for kind in 0..4 {
+        match raw::read_u32(rom, offset + 4 + kind * 4) {
+            Some(0) => {}
+            _ => match raw::read_pointer(rom, offset + 4 + kind * 4) {
+                Some(info) if raw::read_pointer(rom, info + 4).is_some() => has_encounters = true,
+                _ => return false,
+            },
+        }
+    }
+    has_encounters
+}
+
+/// Where the code that reads the wild encounters table keeps the pointer to it.
+fn wild_table_pointer(rom_type: &RomType) -> usize {
+    use RomType::*;
+    match rom_type {
+        FireRed | LeafGreen => 0x082990,
+        Ruby | Sapphire => 0x084C1C,
+        Emerald => 0x0B4D78,
+    }
+}
+
+/// The most entries the wild encounters table can have, one for each map.
+const WILD_TABLE_MAX_LENGTH: usize = 0x100 * 0x100;
+
+/// Finds the table of wild encounters, which `poly3lib` doesn't read, from the
+/// pointer the game uses, as long as it points to something that looks like it.
+fn find_wild_table(rom: &Rom) -> AppResult<usize> {
+    let pointer = wild_table_pointer(&rom.rom_type);
+    let not_found = || {
+        AppError::not_found(format!(
+            "Could not find the wild encounters table from the pointer at ${:07X}",
+            pointer
+        ))
+        .with_offset(pointer)
+    };
+    let table = raw::read_pointer(rom, pointer).ok_or_else(not_found)?;
+
+    let mut length = 0;
+    while length < WILD_TABLE_MAX_LENGTH && is_wild_header(rom, table + length * WILD_HEADER_SIZE) {
+        length += 1;
+    }
+    let terminated = raw::read_u8(rom, table + length * WILD_HEADER_SIZE) == Some(0xFF);
+    match terminated && length >= WILD_TABLE_MIN_LENGTH {
+        true => Ok(table),
+        false => Err(not_found()),
+    }
+}
+
+/// Returns the entries of the wild encounters table, with the map of each.
+fn wild_table_entries(rom: &Rom, table: usize) -> Vec<(usize, MapId)> {
+    let mut entries = vec![];
+    let mut offset = table;
+    while let Some(&[group, index]) = raw::read_bytes(rom, offset, 2) {
+        if group == 0xFF {
+            break;
+        }
+        entries.push((offset, MapId { group, index }));
+        offset += WILD_HEADER_SIZE;
+    }
+    entries
+}
+
+/// The encounters of a map: the rate and the Pokémon of each kind of encounter.
+type WildEncounters = [Option<(u8, Vec<u8>)>; 4];
+
+fn read_encounters(rom: &Rom, map: &MapId) -> AppResult<Option<WildEncounters>> {
+    let table = find_wild_table(rom)?;
+    let Some((entry, _)) = wild_table_entries(rom, table)
+        .into_iter()
+        .find(|(_, entry_map)| entry_map == map)
+    else {
+        return Ok(None);
+    };
+
+    let mut encounters: WildEncounters = Default::default();
+    for (kind, encounter) in encounters.iter_mut().enumerate() {
+        let Some(info) = raw::read_pointer(rom, entry + 4 + kind * 4) else {
+            continue;
+        };
+        let rate = raw::read_u8(rom, info).unwrap_or(0);
+        let mons = raw::read_pointer(rom, info + 4)
+            .and_then(|mons| raw::read_bytes(rom, mons, WILD_MONS_COUNTS[kind] * 4))
+            .ok_or_else(|| {
+                AppError::rom_data(format!("Invalid wild encounters at ${:07X}", info))
+                    .with_offset(info)
+            })?;
+        *encounter = Some((rate, mons.to_vec()));
+    }
+    Ok(Some(encounters))
+}
+
+/// Puts the encounters of the map in its entry of the wild encounters table,
+/// or else in the entry of a map that doesn't exist anymore.
+///
+/// The table is never moved, since the code that reads it would have to be
+/// changed too. If the encounters can't be written, the space taken by the
+/// ones written so far is freed.
+fn write_encounters(
+    rom: &mut Rom,
+    free_space: &mut FreeSpace,
+    map: &MapId,
+    encounters: &WildEncounters,
+) -> AppResult<()> {
+    let table = find_wild_table(rom)?;
+    let entries = wild_table_entries(rom, table);
+    let entry_offset = match entries.iter().find(|(_, entry_map)| entry_map == map) {
+        Some((offset, _)) => *offset,
+        None => entries
+            .iter()
+            .find(|(_, entry_map)| {
+                rom.map_headers()
+                    .read_header(entry_map.group, entry_map.index)
+                    .is_err()
+            })
+            .map(|(offset, _)| *offset)
+            .ok_or_else(|| {
+                AppError::out_of_space(
+                    "The wild encounters table has no room for this map, and it can't be moved",
+                )
+            })?,
+    };
+
+    let checkpoint = free_space.checkpoint();
+    let mut write_entry = || {
+        let mut entry = vec![0; WILD_HEADER_SIZE];
+        entry[0] = map.group;
+        entry[1] = map.index;
+        for (kind, encounter) in encounters.iter().enumerate() {
+            let Some((rate, mons)) = encounter else {
+                continue;
+            };
+            let mons = write_to_free_space(rom, free_space, mons, "wild Pokémon")?;
+            let mut info = vec![*rate, 0, 0, 0, 0, 0, 0, 0];
+            set_pointer(&mut info, 4, Some(mons));
+            let info = write_to_free_space(rom, free_space, &info, "wild encounters")?;
+            set_pointer(&mut entry, 4 + kind * 4, Some(info));
+        }
+        Ok(entry)
+    };
+
+    match write_entry() {
+        Ok(entry) => {
+            rom.data[entry_offset..entry_offset + WILD_HEADER_SIZE].copy_from_slice(&entry);
+            Ok(())
+        }
+        Err(err) => {
+            free_space.rollback(rom, checkpoint);
+            Err(err)
+        }
+    }
+}
+
+// ANCHOR Transplant
+/// Everything that is copied from the source ROM, read before the target is changed.
+struct SourceMap {
+    header: MapHeader,
+    layout: MapLayoutData,
+    frlg: bool,
+    metatiles_limit: u16,
+    tilesets: Vec<TilesetData>,
+    events: [Vec<u8>; 4],
+    map_scripts: Option<usize>,
+    encounters: Option<WildEncounters>,
+    unresolved: Vec<UnresolvedReference>,
+    warnings: Vec<String>,
+}
+
+fn read_source_map(
+    rom: &mut Rom,
+    map: &MapId,
+    options: &TransplantOptions,
+) -> AppResult<SourceMap> {
+    let header = rom
+        .map_headers()
+        .read_header(map.group, map.index)
+        .map_err(|e| {
+            AppError::rom_data(format!(
+                "Error while reading map {}.{} of the source ROM: {}",
+                map.group, map.index, e
+            ))
+        })?;
+    let layout = rom
+        .map_layouts()
+        .read_data(header.map_layout_id)
+        .map_err(|e| {
+            AppError::rom_data(format!(
+                "Error while loading layout {} of the source ROM: {}",
+                header.map_layout_id, e
+            ))
+        })?;
+
+    let mut unresolved = vec![];
+    let mut warnings = vec![];
+
+    let tilesets = match options.tilesets {
+        TransplantTilesets::Copy => {
+            let (tileset1, tileset2) = layout_tilesets(&layout).ok_or_else(|| {
+                AppError::rom_data(format!(
+                    "Layout {} of the source ROM has invalid tilesets",
+                    header.map_layout_id
+                ))
+            })?;
+            vec![
+                TilesetData::read(rom, tileset1)?,
+                TilesetData::read(rom, tileset2)?,
+            ]
+        }
+        TransplantTilesets::Use { .. } => vec![],
+    };
+    for tileset in tilesets.iter().filter(|tileset| tileset.animated) {
+        unresolved.push(UnresolvedReference {
+            kind: ReferenceKind::Animation,
+            description: format!(
+                "The animations of tileset ${:07X} are code and were not copied",
+                tileset.offset
+            ),
+        });
+    }
+
+    let events = match header.events.offset() {
+        Some(events) if options.events => {
+            read_events(rom, events, options.keep_scripts, &mut unresolved)?
+        }
+        _ => Default::default(),
+    };
+
+    let map_scripts = header.map_scripts.offset();
+    if let Some(map_scripts) = map_scripts {
+        let action = if options.keep_scripts {
+            "kept"
+        } else {
+            "not copied"
+        };
+        unresolved.push(UnresolvedReference {
+            kind: ReferenceKind::Script,
+            description: format!("The map scripts at ${:07X} were {}", map_scripts, action),
+        });
+    }
+
+    if let Some(connections) = header.connections.offset() {
+        read_connections(rom, connections, &mut unresolved);
+    }
+
+    let encounters = match options.encounters {
+        true => read_encounters(rom, map).unwrap_or_else(|err| {
+            warnings.push(format!("The wild encounters were not copied: {}", err));
+            None
+        }),
+        false => None,
+    };
+
+    Ok(SourceMap {
+        header,
+        layout,
+        frlg: is_frlg(&rom.rom_type),
+        metatiles_limit: primary_metatiles_limit(&rom.rom_type),
+        tilesets,
+        events,
+        map_scripts: map_scripts.filter(|_| options.keep_scripts),
+        encounters,
+        unresolved,
+        warnings,
+    })
+}
+
+/// Copies a map of another open ROM into this one as a new map, with its
+/// layout and border, and optionally its tilesets, events and encounters.
+///
+/// The flags, vars, scripts and maps the copied data refers to can't be
+/// matched to the ones of this ROM, so they are listed in the report.
+#[tauri::command]
+pub fn transplant_map(
+    state: AppState,
+    rom: RomHandle,
+    handle: AppHandle,
+    source: RomHandle,
+    map: MapId,
+    target: MapId,
+    options: TransplantOptions,
+) -> AppResult<TransplantReport> {
+    let source_state = state.rom(source)?;
+    let state = state.rom(rom)?;
+
+    let source_map = source_state.with_rom(|rom| read_source_map(rom, &map, &options))?;
+    let (layout_name, tileset_names) = {
+        let config = source_state
+            .config
+            .lock()
+            .map_err(|_| AppError::lock("config data"))?;
+        let tileset_names: Vec<Option<String>> = source_map
+            .tilesets
+            .iter()
+            .map(|tileset| config.tileset_names.get(&(tileset.offset as u32)).cloned())
+            .collect();
+        (
+            config
+                .layout_names
+                .get(&source_map.header.map_layout_id)
+                .cloned(),
+            tileset_names,
+        )
+    };
+    let layout_name = match layout_name {
+        Some(name) => name,
+        None => {
+            let path = get_rom_path(&source_state)?;
+            let file = Path::new(&path).file_name().unwrap_or_default();
+            format!(
+                "Map {}.{} of {}",
+                map.group,
+                map.index,
+                file.to_string_lossy()
+            )
+        }
+    };
+
+    let SourceMap {
+        header: source_header,
+        layout: source_layout,
+        frlg: source_frlg,
+        metatiles_limit: source_limit,
+        tilesets: source_tilesets,
+        events,
+        map_scripts,
+        encounters,
+        unresolved,
+        mut warnings,
+    } = source_map;
+    let MapId { group, index } = target;
+
+    let report = state.update_rom(|rom| {
+        let mut free_space = FreeSpace::new(rom);
+        let frlg = is_frlg(&rom.rom_type);
+        let limit = primary_metatiles_limit(&rom.rom_type);
+
+        // The tileset structs and metatile attributes differ between the games
+        if !source_tilesets.is_empty() && source_frlg != frlg {
+            let games = match source_frlg {
+                true => "FireRed and LeafGreen",
+                false => "Ruby, Sapphire and Emerald",
+            };
+            return Err(AppError::invalid_input(format!(
+                "The tilesets of {} can't be copied to {}, use tilesets of this ROM instead",
+                games, rom.rom_type
+            )));
+        }
+
+        // Copy the tilesets that are not already in the ROM
+        let mut tilesets = vec![];
+        for tileset in source_tilesets.iter() {
+            let (offset, copied) = match find_same_tileset(rom, tileset) {
+                Some(offset) => (offset, false),
+                None => (tileset.write(rom, &mut free_space)?, true),
+            };
+            tilesets.push(TransplantedTileset {
+                source: tileset.offset as u32,
+                offset: offset as u32,
+                copied,
+            });
+        }
+        let (tileset1, tileset2) = match options.tilesets {
+            TransplantTilesets::Copy => (tilesets[0].offset, tilesets[1].offset),
+            TransplantTilesets::Use { tileset1, tileset2 } => (tileset1, tileset2),
+        };
+
+        // Everything `poly3lib` writes has to stay clear of what was written to
+        // the free space, which it doesn't know about, so that space is reserved
+        let layout_id = free_space
+            .reserved(rom, |rom| {
+                rom.map_layouts().create_data(
+                    tileset1,
+                    tileset2,
+                    source_layout.map_data.width as i32,
+                    source_layout.map_data.height as i32,
+                )
+            })
+            .map_err(|e| AppError::rom_data(format!("Error while creating new layout: {}", e)))?;
+
+        // Let the references find the new tilesets through the new layout
+        if tilesets.iter().any(|tileset| tileset.copied) {
+            rom.refs.tilesets_table = None;
+            rom.init_map().map_err(|e| {
+                AppError::rom_data(format!("Failed to initialize references: {}", e))
+            })?;
+        }
+
+        let mut layout = read_layout_data(rom, layout_id)?;
+        layout.header.border_width = source_layout.header.border_width;
+        layout.header.border_height = source_layout.header.border_height;
+        layout.map_data = source_layout.map_data;
+        layout.border_data = source_layout.border_data;
+
+        // The secondary metatiles start at a different id in the other games
+        if source_limit != limit {
+            for metatile in layout
+                .map_data
+                .metatiles
+                .iter_mut()
+                .chain(layout.border_data.metatiles.iter_mut())
+                .filter(|metatile| **metatile >= source_limit)
+            {
+                *metatile = *metatile - source_limit + limit;
+            }
+        }
+        if !frlg && (layout.border_data.width, layout.border_data.height) != (2, 2) {
+            warnings.push(format!(
+                "The border is {}x{}, but {} only supports 2x2 borders",
+                layout.border_data.width, layout.border_data.height, rom.rom_type
+            ));
+        }
+        warnings.extend(validate_layout_metatiles(rom, &layout));
+        free_space.reserved(rom, |rom| write_layout_data(rom, layout_id, layout))?;
+
+        // Create the map and copy the header of the source one
+        free_space
+            .reserved(rom, |rom| {
+                rom.map_headers().create_header(group, index, layout_id)
+            })
+            .map_err(|e| {
+                AppError::rom_data(format!("Error while creating new map: {}", e))
+                    .with_map(group, index)
+            })?;
+        let mut header = rom
+            .map_headers()
+            .read_header(group, index)
+            .map_err(|e| {
+                AppError::rom_data(format!("Error while reading new map header: {}", e))
+                    .with_map(group, index)
+            })?;
+
+        header.music = source_header.music;
+        header.region_map_section_id = source_header.region_map_section_id;
+        header.cave = source_header.cave;
+        header.weather = source_header.weather;
+        header.map_type = source_header.map_type;
+        header.biking_allowed = source_header.biking_allowed;
+        header.allow_escaping = source_header.allow_escaping;
+        header.allow_running = source_header.allow_running;
+        header.show_map_name = source_header.show_map_name;
+        header.floor_num = source_header.floor_num;
+        header.battle_type = source_header.battle_type;
+        if source_frlg != frlg {
+            warnings.push(format!(
+                "The music and map section were copied as they are, but {} numbers them differently",
+                rom.rom_type
+            ));
+        }
+
+        let events = write_events(rom, &mut free_space, &events)?;
+        header.events = PointedData::NoData(events as u32);
+        // An empty list of map scripts only has the 0 that ends it
+        let map_scripts = match map_scripts {
+            Some(map_scripts) => map_scripts,
+            None => write_to_free_space(rom, &mut free_space, &[0; 4], "map scripts")?,
+        };
+        header.map_scripts = PointedData::NoData(map_scripts as u32);
+        header.connections = PointedData::Null;
+
+        free_space
+            .reserved(rom, |rom| rom.map_headers().write_header(group, index, header))
+            .map_err(|e| {
+                AppError::rom_data(format!(
+                    "Error while writing header {}.{}: {}",
+                    group, index, e
+                ))
+                .with_map(group, index)
+            })?;
+
+        if let Some(encounters) = encounters.as_ref() {
+            let map = MapId { group, index };
+            if let Err(err) = write_encounters(rom, &mut free_space, &map, encounters) {
+                warnings.push(format!("The wild encounters were not copied: {}", err));
+            }
+        }
+
+        Ok(TransplantReport {
+            map: MapId { group, index },
+            layout: layout_id,
+            tilesets,
+            unresolved,
+            warnings,
+        })
+    })?;
+
+    RomEvent::MapCreated(report.map.clone()).emit(&handle, state.handle);
+    RomEvent::LayoutUpdated(report.layout).emit(&handle, state.handle);
+
+    update_config(&state, |config| {
+        config.layout_names.insert(report.layout, layout_name);
+        for (tileset, name) in report.tilesets.iter().zip(tileset_names) {
+            if let (true, Some(name)) = (tileset.copied, name) {
+                config.tileset_names.entry(tileset.offset).or_insert(name);
+            }
+        }
+    })?;
+    RomEvent::ConfigChanged.emit(&handle, state.handle);
+
+    Ok(report)
+}
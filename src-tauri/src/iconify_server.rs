@@ -287,9 +287,9 @@ fn get_icons(prefix: &str, icons: Vec<String>, path: &PathBuf) -> String {
 }
 
 /// Spawns and runs the iconify server
-pub fn spawn_iconify_server_thread(icons_path: PathBuf) {
+pub fn spawn_iconify_server_thread(icons_path: PathBuf, port: u16) {
     std::thread::spawn(move || {
-        let server = Server::http("127.0.0.1:3000").expect("Failed to start http server");
+        let server = Server::http(("127.0.0.1", port)).expect("Failed to start http server");
 
         for request in server.incoming_requests() {
             // Parse the request
@@ -0,0 +1,44 @@
+//! Conversions between images and the base64 PNG data URLs sent to the frontend.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::{ImageOutputFormat, RgbaImage};
+
+use crate::{
+    error::{AppError, ErrorKind},
+    state::AppResult,
+};
+
+const PNG_DATA_URL_PREFIX: &str = "data:image/png;base64,";
+
+/// Encodes an image to PNG.
+pub fn encode_png(image: &RgbaImage) -> AppResult<Vec<u8>> {
+    let mut png = std::io::Cursor::new(vec![]);
+    image
+        .write_to(&mut png, ImageOutputFormat::Png)
+        .map_err(|e| {
+            AppError::new(
+                ErrorKind::Other,
+                format!("Could not encode the image: {}", e),
+            )
+        })?;
+    Ok(png.into_inner())
+}
+
+/// Reads the image in a base64-encoded PNG data URL.
+pub fn decode_data_url(data_url: &str) -> AppResult<RgbaImage> {
+    let encoded = data_url
+        .strip_prefix(PNG_DATA_URL_PREFIX)
+        .ok_or_else(|| AppError::invalid_input("The rendered image is not a PNG data URL"))?;
+    let bytes = STANDARD.decode(encoded).map_err(|e| {
+        AppError::invalid_input(format!("Could not decode the rendered image: {}", e))
+    })?;
+
+    Ok(image::load_from_memory(&bytes)
+        .map_err(|e| AppError::invalid_input(format!("Could not read the rendered image: {}", e)))?
+        .into_rgba8())
+}
+
+/// Returns the base64-encoded PNG data URL for the given PNG file contents.
+pub fn png_to_data_url(png: &[u8]) -> String {
+    format!("{}{}", PNG_DATA_URL_PREFIX, STANDARD.encode(png))
+}
@@ -1,20 +1,34 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod backups;
+mod brushes;
 mod config;
+mod error;
+mod events;
+mod formats;
 mod handlers;
 mod iconify_server;
+mod images;
+mod preview_cache;
+mod preview_jobs;
+mod query;
+mod raw;
+mod settings;
+mod stable_hash;
 mod state;
+mod tint;
 
 use std::path::PathBuf;
 
 use iconify_server::spawn_iconify_server_thread;
 use state::PolythreeState;
-use tauri::App;
+use tauri::{App, Manager};
 
 use crate::{
     config::*,
-    handlers::{map_editor::*, map_list::*, rom::*},
+    handlers::{brushes::*, layouts::*, map_editor::*, map_list::*, rom::*, transplant::*},
+    settings::*,
 };
 
 fn setup_function(app: &mut App) -> Result<(), Box<dyn std::error::Error>> {
@@ -29,8 +43,15 @@ fn setup_function(app: &mut App) -> Result<(), Box<dyn std::error::Error>> {
         PathBuf::from("./iconify-icons/")
     };
 
+    // Load the user's settings
+    let (settings, settings_path) = AppSettings::init(&app.handle());
+    let port = settings.iconify_port;
+    let state = app.state::<PolythreeState>();
+    *state.settings.lock().unwrap() = settings;
+    *state.settings_path.lock().unwrap() = settings_path;
+
     // Run an http server in a separate thread
-    spawn_iconify_server_thread(path);
+    spawn_iconify_server_thread(path, port);
 
     Ok(())
 }
@@ -42,15 +63,36 @@ fn main() {
             // ROM
             init_rom,
             close_rom,
+            get_open_roms,
+            get_recent_roms,
+            remove_recent_rom,
+            save_session,
+            restore_session,
+            // Settings
+            get_settings,
+            set_settings,
             // Config
             get_config,
             set_config,
             update_tileset_level,
-            update_brushes,
+            resolve_config_mismatch,
+            move_config,
+            // Brushes
+            add_brush,
+            update_brush,
+            delete_brush,
+            reorder_brushes,
+            export_brushes,
+            import_brushes,
             // Map list
             get_map_list,
+            get_map_header,
             get_map_names,
             get_map_preview,
+            search_maps,
+            queue_map_previews,
+            cancel_map_previews,
+            set_preview_disk_cache,
             get_tilesets,
             get_layout_ids,
             create_map,
@@ -62,8 +104,24 @@ fn main() {
             get_tilesets_animations,
             get_layout_offset,
             get_tilesets_lengths,
+            get_weather_tint,
             update_map_header,
             update_layout_header,
+            update_map_headers_bulk,
+            // Layouts
+            find_metatiles,
+            find_metatiles_in_project,
+            replace_metatiles,
+            resize_layout,
+            resize_border,
+            duplicate_layout,
+            import_advance_map,
+            export_advance_map,
+            export_layout_to_tiled,
+            export_layout,
+            import_layout_from_tiled,
+            // Transplant
+            transplant_map,
         ])
         .setup(setup_function)
         .run(tauri::generate_context!())
@@ -0,0 +1,277 @@
+use std::{collections::HashMap, path::PathBuf, sync::Mutex};
+
+use image::{imageops::FilterType, RgbaImage};
+use poly3lib::{maps::layout::MapLayoutData, rom::Rom};
+
+use crate::{
+    error::AppError,
+    images, raw,
+    stable_hash::StableHasher,
+    state::{AppResult, AppStateFunctions, RomState},
+    tint::PaletteTint,
+};
+
+/// Previews kept in memory before the cache is emptied.
+const MAX_ENTRIES: usize = 4096;
+/// Size of the previews folder above which the oldest previews are removed.
+const MAX_DISK_SIZE: u64 = 64 * 1024 * 1024;
+/// Size the previews folder is brought back to when it's too large,
+/// so that it isn't cleaned up again after every preview.
+const PRUNED_DISK_SIZE: u64 = MAX_DISK_SIZE * 3 / 4;
+
+/// Rendered map previews, keyed by a hash of everything that affects them,
+/// so that they don't need to be invalidated when the ROM changes.
+#[derive(Default)]
+pub struct PreviewCache {
+    /// The previews as PNG data URLs
+    entries: HashMap<u64, String>,
+    /// The folder where the previews are also saved, if any
+    disk_path: Option<PathBuf>,
+    /// The size of the previews in the folder
+    disk_size: u64,
+}
+
+impl PreviewCache {
+    /// Saves the previews as PNG files in the given folder, or
+    /// only keeps them in memory if `None`.
+    pub fn set_disk_path(&mut self, path: Option<PathBuf>) -> AppResult<()> {
+        if let Some(path) = path.as_ref() {
+            std::fs::create_dir_all(path).map_err(|e| {
+                AppError::file(
+                    path.display().to_string(),
+                    format!("Could not create the previews folder: {}", e),
+                )
+            })?;
+        }
+        self.disk_path = path;
+        self.prune_disk(MAX_DISK_SIZE);
+        Ok(())
+    }
+
+    /// Returns the cached preview, looking on disk if it's not in memory.
+    pub fn get(&mut self, key: u64) -> Option<String> {
+        if let Some(preview) = self.entries.get(&key) {
+            return Some(preview.clone());
+        }
+
+        let png = std::fs::read(self.file_path(key)?).ok()?;
+        let preview = images::png_to_data_url(&png);
+        self.insert_entry(key, preview.clone());
+        Some(preview)
+    }
+
+    /// Caches a preview, returning its PNG data URL.
+    pub fn insert(&mut self, key: u64, image: &RgbaImage) -> AppResult<String> {
+        let png = images::encode_png(image)?;
+
+        // Failing to write to disk only costs a render the next time
+        if let Some(path) = self.file_path(key) {
+            match std::fs::write(&path, &png) {
+                Ok(()) => self.disk_size += png.len() as u64,
+                Err(err) => println!("Could not save preview {}: {}", path.display(), err),
+            }
+            if self.disk_size > MAX_DISK_SIZE {
+                self.prune_disk(PRUNED_DISK_SIZE);
+            }
+        }
+
+        let preview = images::png_to_data_url(&png);
+        self.insert_entry(key, preview.clone());
+        Ok(preview)
+    }
+
+    fn insert_entry(&mut self, key: u64, preview: String) {
+        if self.entries.len() >= MAX_ENTRIES {
+            self.entries.clear();
+        }
+        self.entries.insert(key, preview);
+    }
+
+    /// Removes the least recently written previews from the folder
+    /// until it's no larger than `max_size`, and measures what's left.
+    fn prune_disk(&mut self, max_size: u64) {
+        self.disk_size = 0;
+        let Some(entries) = self
+            .disk_path
+            .as_ref()
+            .and_then(|path| std::fs::read_dir(path).ok())
+        else {
+            return;
+        };
+
+        let mut previews: Vec<_> = entries
+            .flatten()
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "png"))
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok()?;
+                Some((metadata.modified().ok()?, metadata.len(), entry.path()))
+            })
+            .collect();
+        previews.sort();
+
+        let mut size: u64 = previews.iter().map(|(_, len, _)| len).sum();
+        for (_, len, path) in previews {
+            if size <= max_size {
+                break;
+            }
+            if std::fs::remove_file(&path).is_ok() {
+                size -= len;
+            }
+        }
+        self.disk_size = size;
+    }
+
+    fn file_path(&self, key: u64) -> Option<PathBuf> {
+        Some(self.disk_path.as_ref()?.join(format!("{:016x}.png", key)))
+    }
+}
+
+/// The layouts of the maps, read when the ROM is opened and again after every
+/// change, so that previews can be rendered while only reading the ROM.
+#[derive(Default)]
+pub struct MapLayouts {
+    /// The layout of each map, by group and index
+    maps: HashMap<(u8, u8), u16>,
+    /// The layouts that could be read, by id
+    layouts: HashMap<u16, MapLayoutData>,
+}
+
+impl MapLayouts {
+    /// Reads the layouts of all the maps, skipping the ones that can't be read.
+    pub fn load(rom: &mut Rom) -> Self {
+        let mut res = Self::default();
+        let Ok(headers) = rom.map_headers().dump_headers() else {
+            return res;
+        };
+
+        for dump in headers {
+            let id = dump.header.map_layout_id;
+            res.maps.insert((dump.group, dump.index), id);
+            if !res.layouts.contains_key(&id) {
+                if let Ok(layout) = rom.map_layouts().read_data(id) {
+                    res.layouts.insert(id, layout);
+                }
+            }
+        }
+        res
+    }
+
+    /// Returns the layout of a map.
+    pub fn get(&self, group: u8, index: u8) -> AppResult<&MapLayoutData> {
+        let id = *self
+            .maps
+            .get(&(group, index))
+            .ok_or_else(|| AppError::not_found("The map does not exist").with_map(group, index))?;
+        self.layouts.get(&id).ok_or_else(|| {
+            AppError::rom_data("The layout of the map could not be read").with_layout(id)
+        })
+    }
+}
+
+/// Returns the key of a layout's preview with the given size and tint.
+///
+/// The key covers the blocks of the layout, its tilesets' offsets and their
+/// tiles, palettes and metatiles, so any change to them makes a different key.
+/// It's a [`StableHasher`] hash, so that the previews saved on disk are found
+/// again the next time Polythree is opened.
+fn preview_key(
+    rom: &Rom,
+    layout: &MapLayoutData,
+    size: Option<u32>,
+    tint: Option<PaletteTint>,
+) -> u64 {
+    let mut hasher = StableHasher::new();
+
+    let data = &layout.map_data;
+    hasher
+        .write_u64(data.width as u64)
+        .write_u64(data.height as u64);
+    for (metatile, level) in data.metatiles.iter().zip(data.levels.iter()) {
+        hasher
+            .write(&metatile.to_le_bytes())
+            .write(&u16::from(*level).to_le_bytes());
+    }
+
+    for tileset in [
+        layout.header.primary_tileset.offset(),
+        layout.header.secondary_tileset.offset(),
+    ] {
+        hasher.write_option(tileset, |hasher, offset| {
+            hasher.write_u64(offset as u64);
+            for graphics in raw::tileset_graphics(rom, offset) {
+                hasher.write_option(graphics, StableHasher::write_slice);
+            }
+            hasher
+        });
+    }
+
+    hasher.write_option(size, |hasher, size| hasher.write_u64(size as u64));
+    hasher.write_option(tint.filter(|tint| !tint.is_identity()), |hasher, tint| {
+        hasher
+            .write(&[tint.grayscale as u8, tint.blend_amount])
+            .write(&tint.blend_color)
+    });
+    hasher.finish()
+}
+
+/// Returns the preview of a map, rendering it only if it's not cached.
+pub fn render_map_preview(
+    state: &RomState,
+    group: u8,
+    index: u8,
+    size: Option<u32>,
+    tint: Option<PaletteTint>,
+) -> AppResult<String> {
+    let cache = &state.previews;
+    state.read_rom(|rom| {
+        let layouts = state
+            .layouts
+            .read()
+            .map_err(|_| AppError::lock("map layouts"))?;
+        let layout = layouts.get(group, index)?;
+
+        // Look for a preview of the same content
+        let key = preview_key(rom, layout, size, tint);
+        if let Some(preview) = lock_cache(cache)?.get(key) {
+            return Ok(preview);
+        }
+
+        // Render the tileset
+        let tilesets = layout.read_tilesets(rom).map_err(|err| {
+            AppError::rom_data(format!("Error while reading the tileset: {}", err))
+                .with_map(group, index)
+        })?;
+        let rendered = tilesets.render();
+
+        // Render the map
+        let mut preview = images::decode_data_url(&layout.render_to_base64(&rendered))?;
+        if let Some(size) = size {
+            preview = fit_image(preview, size);
+        }
+
+        // Apply the palette tint, if any
+        if let Some(tint) = tint {
+            tint.apply_to_image(&mut preview);
+        }
+
+        // The cache is not locked while rendering, so that other previews can use it
+        lock_cache(cache)?.insert(key, &preview)
+    })
+}
+
+fn lock_cache(cache: &Mutex<PreviewCache>) -> AppResult<std::sync::MutexGuard<PreviewCache>> {
+    cache.lock().map_err(|_| AppError::lock("previews cache"))
+}
+
+/// Scales the image down so that neither side is larger than `size`.
+fn fit_image(image: RgbaImage, size: u32) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    if width <= size && height <= size {
+        return image;
+    }
+
+    let scale = size as f32 / width.max(height) as f32;
+    let new_width = ((width as f32 * scale) as u32).max(1);
+    let new_height = ((height as f32 * scale) as u32).max(1);
+    image::imageops::resize(&image, new_width, new_height, FilterType::Triangle)
+}
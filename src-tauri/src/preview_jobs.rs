@@ -0,0 +1,127 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex, Once},
+};
+
+use serde::Serialize;
+use tauri::{AppHandle, Manager};
+
+use crate::{
+    handlers::map_list::MapId,
+    preview_cache::render_map_preview,
+    state::{RomHandle, RomState},
+    tint::PaletteTint,
+};
+
+/// The event sent for each finished preview.
+pub const MAP_PREVIEW_EVENT: &str = "map-preview";
+
+/// A map preview waiting to be rendered.
+pub struct PreviewJob {
+    pub map: MapId,
+    pub size: Option<u32>,
+    pub tint: Option<PaletteTint>,
+    /// The open ROM the preview is rendered from and whose cache it's saved to
+    pub state: Arc<RomState>,
+}
+
+#[derive(Clone, Serialize)]
+pub struct MapPreviewResult {
+    rom: RomHandle,
+    map: MapId,
+    size: Option<u32>,
+    tint: Option<PaletteTint>,
+    /// The preview as a PNG data URL
+    preview: Option<String>,
+    /// Why the preview could not be rendered
+    error: Option<String>,
+}
+
+/// Map previews rendered in the background by a pool of worker threads.
+pub struct PreviewJobs {
+    queue: Arc<(Mutex<VecDeque<PreviewJob>>, Condvar)>,
+    workers: Once,
+}
+
+impl Default for PreviewJobs {
+    fn default() -> Self {
+        Self {
+            queue: Arc::default(),
+            workers: Once::new(),
+        }
+    }
+}
+
+impl PreviewJobs {
+    /// Adds the jobs to the queue, starting the workers the first time.
+    ///
+    /// Jobs for a map already in the queue with the same options are ignored.
+    pub fn push(&self, handle: &AppHandle, jobs: Vec<PreviewJob>) {
+        self.workers.call_once(|| {
+            let count = std::thread::available_parallelism()
+                .map(|count| count.get())
+                .unwrap_or(1);
+
+            for _ in 0..count {
+                let handle = handle.clone();
+                let queue = self.queue.clone();
+                std::thread::spawn(move || worker(handle, queue));
+            }
+        });
+
+        let (queue, condvar) = &*self.queue;
+        let mut queue = queue.lock().unwrap();
+        for job in jobs {
+            let queued = queue.iter().any(|other| {
+                other.state.handle == job.state.handle
+                    && other.map == job.map
+                    && other.size == job.size
+                    && other.tint == job.tint
+            });
+            if !queued {
+                queue.push_back(job);
+            }
+        }
+        condvar.notify_all();
+    }
+
+    /// Removes the jobs that are not being rendered yet for which `filter` is true.
+    pub fn cancel(&self, filter: impl Fn(&PreviewJob) -> bool) {
+        let (queue, _) = &*self.queue;
+        queue.lock().unwrap().retain(|job| !filter(job));
+    }
+}
+
+fn worker(handle: AppHandle, queue: Arc<(Mutex<VecDeque<PreviewJob>>, Condvar)>) {
+    loop {
+        let job = {
+            let (queue, condvar) = &*queue;
+            let mut queue = queue.lock().unwrap();
+            loop {
+                match queue.pop_front() {
+                    Some(job) => break job,
+                    None => queue = condvar.wait(queue).unwrap(),
+                }
+            }
+        };
+
+        let result =
+            render_map_preview(&job.state, job.map.group, job.map.index, job.size, job.tint);
+
+        let (preview, error) = match result {
+            Ok(preview) => (Some(preview), None),
+            Err(error) => (None, Some(error.to_string())),
+        };
+        let payload = MapPreviewResult {
+            rom: job.state.handle,
+            map: job.map,
+            size: job.size,
+            tint: job.tint,
+            preview,
+            error,
+        };
+        if let Err(err) = handle.emit_all(MAP_PREVIEW_EVENT, payload) {
+            println!("Could not send map preview: {}", err);
+        }
+    }
+}
@@ -0,0 +1,387 @@
+//! A small query language for searching the map list.
+//!
+//! A query is a list of terms separated by spaces, all of which must match:
+//! - `key:value` compares a field of the map with the value, for example
+//!   `group:3`, `music:0x15A`, `weather:rain` or `layout.tileset2:0x3C0000`;
+//! - `key:a,b,c` matches any of the values;
+//! - a term starting with `-` matches the maps the term does not match;
+//! - a term without a key searches both the map and layout names;
+//! - values can be quoted to include spaces, as in `name:"Route 1*"`.
+//!
+//! Names match if they contain the value, unless it has `*` or `?` wildcards,
+//! in which case the whole name must match. Names are compared ignoring case.
+
+use poly3lib::maps::header::MapHeaderDump;
+
+use crate::{error::AppError, state::AppResult};
+
+/// Names for the `header.weather` values.
+const WEATHER_NAMES: [&str; 16] = [
+    "none",
+    "sunny_clouds",
+    "sunny",
+    "rain",
+    "snow",
+    "thunderstorm",
+    "fog",
+    "ash",
+    "sandstorm",
+    "fog_diagonal",
+    "underwater",
+    "shade",
+    "drought",
+    "downpour",
+    "bubbles",
+    "abnormal",
+];
+
+/// Names for the `header.map_type` values.
+const MAP_TYPE_NAMES: [&str; 10] = [
+    "none",
+    "town",
+    "city",
+    "route",
+    "underground",
+    "underwater",
+    "ocean_route",
+    "unknown",
+    "indoor",
+    "secret_base",
+];
+
+#[derive(Debug, Clone, Copy)]
+enum NumberField {
+    Group,
+    Index,
+    Music,
+    Weather,
+    MapType,
+    Mapsec,
+    Layout,
+    Tileset1,
+    Tileset2,
+    Cave,
+    Floor,
+    BattleType,
+    Biking,
+    Escaping,
+    Running,
+    ShowName,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum TextField {
+    /// The region map section name
+    Name,
+    /// The layout name from the config
+    LayoutName,
+    /// Either of the names
+    Any,
+}
+
+#[derive(Debug)]
+enum Condition {
+    Number(NumberField, Vec<u64>),
+    Text(TextField, Vec<String>),
+}
+
+#[derive(Debug)]
+struct Term {
+    negated: bool,
+    condition: Condition,
+}
+
+/// The data a map is searched by.
+pub struct SearchedMap<'a> {
+    pub dump: &'a MapHeaderDump,
+    /// The name of the map's region map section
+    pub name: Option<&'a str>,
+    /// The name of the map's layout
+    pub layout_name: Option<&'a str>,
+}
+
+/// A parsed map list query.
+#[derive(Debug)]
+pub struct MapQuery {
+    terms: Vec<Term>,
+}
+
+impl MapQuery {
+    pub fn parse(query: &str) -> AppResult<Self> {
+        let terms = tokenize(query)?
+            .into_iter()
+            .map(|token| parse_term(&token))
+            .collect::<AppResult<_>>()?;
+
+        Ok(Self { terms })
+    }
+
+    /// Returns true if the map matches every term of the query.
+    pub fn matches(&self, map: &SearchedMap) -> bool {
+        self.terms
+            .iter()
+            .all(|term| term.condition.matches(map) != term.negated)
+    }
+}
+
+impl Condition {
+    fn matches(&self, map: &SearchedMap) -> bool {
+        match self {
+            Condition::Number(field, values) => {
+                let value = field.value(map.dump);
+                values.contains(&value)
+            }
+            Condition::Text(field, patterns) => {
+                let names: &[Option<&str>] = match field {
+                    TextField::Name => &[map.name],
+                    TextField::LayoutName => &[map.layout_name],
+                    TextField::Any => &[map.name, map.layout_name],
+                };
+                patterns.iter().any(|pattern| {
+                    names
+                        .iter()
+                        .flatten()
+                        .any(|name| matches_name(pattern, name))
+                })
+            }
+        }
+    }
+}
+
+impl NumberField {
+    fn value(&self, dump: &MapHeaderDump) -> u64 {
+        let header = &dump.header;
+        match self {
+            NumberField::Group => dump.group as u64,
+            NumberField::Index => dump.index as u64,
+            NumberField::Music => header.music as u64,
+            NumberField::Weather => header.weather as u64,
+            NumberField::MapType => header.map_type as u64,
+            NumberField::Mapsec => header.region_map_section_id as u64,
+            NumberField::Layout => header.map_layout_id as u64,
+            NumberField::Tileset1 => dump.tileset1 as u64,
+            NumberField::Tileset2 => dump.tileset2 as u64,
+            NumberField::Cave => header.cave as u64,
+            NumberField::Floor => header.floor_num as u64,
+            NumberField::BattleType => header.battle_type as u64,
+            NumberField::Biking => header.biking_allowed as u64,
+            NumberField::Escaping => header.allow_escaping as u64,
+            NumberField::Running => header.allow_running as u64,
+            NumberField::ShowName => header.show_map_name as u64,
+        }
+    }
+
+    /// Names that can be used instead of the numbers.
+    fn names(&self) -> &'static [&'static str] {
+        match self {
+            NumberField::Weather => &WEATHER_NAMES,
+            NumberField::MapType => &MAP_TYPE_NAMES,
+            _ => &[],
+        }
+    }
+}
+
+/// Splits the query at the spaces outside of quotes, removing the quotes.
+fn tokenize(query: &str) -> AppResult<Vec<String>> {
+    let mut tokens = vec![];
+    let mut current = String::new();
+    let mut in_quotes = false;
+
+    for c in query.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            c if c.is_whitespace() && !in_quotes => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err(AppError::invalid_input(
+            "Unclosed quote in the search query",
+        ));
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+
+    Ok(tokens)
+}
+
+fn parse_term(token: &str) -> AppResult<Term> {
+    let (negated, token) = match token.strip_prefix('-') {
+        Some(rest) if !rest.is_empty() => (true, rest),
+        _ => (false, token),
+    };
+
+    let condition = match token.split_once(':') {
+        None => Condition::Text(TextField::Any, vec![token.to_lowercase()]),
+        Some((key, value)) => {
+            let values: Vec<&str> = value.split(',').filter(|v| !v.is_empty()).collect();
+            if values.is_empty() {
+                return Err(AppError::invalid_input(format!(
+                    "Missing value for \"{}\" in the search query",
+                    key
+                )));
+            }
+
+            let number_field = match key.to_lowercase().as_str() {
+                "name" | "mapsec.name" => {
+                    return Ok(Term {
+                        negated,
+                        condition: text_condition(TextField::Name, &values),
+                    })
+                }
+                "layout.name" => {
+                    return Ok(Term {
+                        negated,
+                        condition: text_condition(TextField::LayoutName, &values),
+                    })
+                }
+                "group" => NumberField::Group,
+                "index" => NumberField::Index,
+                "music" => NumberField::Music,
+                "weather" => NumberField::Weather,
+                "type" | "map_type" => NumberField::MapType,
+                "mapsec" => NumberField::Mapsec,
+                "layout" => NumberField::Layout,
+                "tileset1" | "layout.tileset1" => NumberField::Tileset1,
+                "tileset2" | "layout.tileset2" => NumberField::Tileset2,
+                "cave" => NumberField::Cave,
+                "floor" => NumberField::Floor,
+                "battle" | "battle_type" => NumberField::BattleType,
+                "bike" | "biking" => NumberField::Biking,
+                "escape" | "escaping" => NumberField::Escaping,
+                "run" | "running" => NumberField::Running,
+                "show_name" | "show_map_name" => NumberField::ShowName,
+                _ => {
+                    return Err(AppError::invalid_input(format!(
+                        "Unknown search key \"{}\"",
+                        key
+                    )))
+                }
+            };
+
+            let numbers = values
+                .iter()
+                .map(|value| parse_number(number_field, value))
+                .collect::<AppResult<_>>()?;
+            Condition::Number(number_field, numbers)
+        }
+    };
+
+    Ok(Term { negated, condition })
+}
+
+fn text_condition(field: TextField, values: &[&str]) -> Condition {
+    Condition::Text(field, values.iter().map(|v| v.to_lowercase()).collect())
+}
+
+/// Parses a decimal or hexadecimal number, or one of the field's names.
+fn parse_number(field: NumberField, value: &str) -> AppResult<u64> {
+    let value = value.to_lowercase();
+    if let Some(position) = field.names().iter().position(|name| *name == value) {
+        return Ok(position as u64);
+    }
+
+    let parsed = match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| {
+        AppError::invalid_input(format!("Invalid value \"{}\" in the search query", value))
+    })
+}
+
+/// Matches a lowercase pattern with a name.
+fn matches_name(pattern: &str, name: &str) -> bool {
+    let name = name.to_lowercase();
+    if !pattern.contains(['*', '?']) {
+        return name.contains(pattern);
+    }
+
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches_wildcards(&pattern, &name)
+}
+
+/// Matches a whole string with a pattern of `*` and `?` wildcards.
+fn matches_wildcards(pattern: &[char], text: &[char]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some(('*', rest)) => (0..=text.len()).any(|skip| matches_wildcards(rest, &text[skip..])),
+        Some((c, rest)) => match text.split_first() {
+            Some((t, text_rest)) if *c == '?' || c == t => matches_wildcards(rest, text_rest),
+            _ => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn tokens_are_split_outside_of_quotes() {
+        assert_eq!(
+            tokenize("  group:3 name:\"Route 1*\"  -weather:rain ").unwrap(),
+            ["group:3", "name:Route 1*", "-weather:rain"]
+        );
+        assert!(tokenize("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn unclosed_quotes_are_rejected() {
+        let err = tokenize("name:\"Route").unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn terms_are_parsed() {
+        let term = parse_term("-music:0x15A,12").unwrap();
+        assert!(term.negated);
+        assert!(matches!(
+            term.condition,
+            Condition::Number(NumberField::Music, ref values) if *values == [0x15A, 12]
+        ));
+
+        let term = parse_term("weather:Rain").unwrap();
+        assert!(matches!(
+            term.condition,
+            Condition::Number(NumberField::Weather, ref values) if *values == [3]
+        ));
+
+        let term = parse_term("-").unwrap();
+        assert!(!term.negated);
+        assert!(matches!(term.condition, Condition::Text(TextField::Any, _)));
+    }
+
+    #[test]
+    fn incomplete_terms_are_rejected() {
+        for query in [
+            "group:",
+            "group:,",
+            "music:0x",
+            "weather:storm",
+            "colour:red",
+        ] {
+            let err = MapQuery::parse(query).unwrap_err();
+            assert_eq!(err.kind, ErrorKind::InvalidInput, "{}", query);
+        }
+    }
+
+    #[test]
+    fn names_match_with_wildcards() {
+        assert!(matches_name("route", "Route 101"));
+        assert!(matches_name("route 1*", "Route 101"));
+        assert!(matches_name("route 1?1", "Route 101"));
+        assert!(!matches_name("route 1?", "Route 101"));
+        assert!(!matches_name("town", "Route 101"));
+        assert!(matches_name("*", ""));
+    }
+}
@@ -0,0 +1,312 @@
+//! Helpers for reading and writing values straight in the ROM data,
+//! for the structures that are not exposed by `poly3lib`.
+
+use std::ops::Range;
+
+use poly3lib::rom::{Rom, RomType};
+
+/// Reads a little-endian value of `size` bytes from the ROM.
+fn read_value(rom: &Rom, offset: usize, size: usize) -> Option<u32> {
+    let bytes = rom.data.get(offset..offset + size)?;
+    Some(
+        bytes
+            .iter()
+            .rev()
+            .fold(0u32, |acc, byte| (acc << 8) | *byte as u32),
+    )
+}
+
+/// Writes a little-endian value of `size` bytes to the ROM.
+fn write_value(rom: &mut Rom, offset: usize, size: usize, value: u32) -> Option<()> {
+    let bytes = rom.data.get_mut(offset..offset + size)?;
+    bytes.copy_from_slice(&value.to_le_bytes()[..size]);
+    Some(())
+}
+
+pub fn read_u8(rom: &Rom, offset: usize) -> Option<u8> {
+    rom.data.get(offset).copied()
+}
+
+pub fn read_u16(rom: &Rom, offset: usize) -> Option<u16> {
+    read_value(rom, offset, 2).map(|value| value as u16)
+}
+
+pub fn read_u32(rom: &Rom, offset: usize) -> Option<u32> {
+    read_value(rom, offset, 4)
+}
+
+pub fn write_u16(rom: &mut Rom, offset: usize, value: u16) -> Option<()> {
+    write_value(rom, offset, 2, value as u32)
+}
+
+pub fn write_u32(rom: &mut Rom, offset: usize, value: u32) -> Option<()> {
+    write_value(rom, offset, 4, value)
+}
+
+/// Reads a GBA pointer from the ROM, returning the offset it points to.
+pub fn read_pointer(rom: &Rom, offset: usize) -> Option<usize> {
+    match read_u32(rom, offset)? as usize {
+        pointer @ 0x08000000..=0x09FFFFFF => Some(pointer - 0x08000000),
+        _ => None,
+    }
+}
+
+/// Returns `size` bytes of the ROM starting at the given offset.
+pub fn read_bytes(rom: &Rom, offset: usize, size: usize) -> Option<&[u8]> {
+    rom.data.get(offset..offset + size)
+}
+
+/// Returns the size of the LZ77-compressed data at the given offset.
+pub fn lz77_size(rom: &Rom, offset: usize) -> Option<usize> {
+    if read_u8(rom, offset)? != 0x10 {
+        return None;
+    }
+    let size = read_u32(rom, offset)? as usize >> 8;
+
+    let mut position = offset + 4;
+    let mut written = 0;
+    while written < size {
+        let flags = read_u8(rom, position)?;
+        position += 1;
+        for bit in (0..8).rev() {
+            if written >= size {
+                break;
+            }
+            if flags & (1 << bit) != 0 {
+                // A copy of 3 to 18 bytes already written
+                written += (read_u8(rom, position)? >> 4) as usize + 3;
+                position += 2;
+            } else {
+                written += 1;
+                position += 1;
+            }
+        }
+    }
+    Some(position - offset)
+}
+
+/// Where the free space of each game starts, past the end of its original data.
+fn free_space_start(rom_type: &RomType) -> usize {
+    use RomType::*;
+    match rom_type {
+        FireRed | LeafGreen => 0x720000,
+        Ruby | Sapphire => 0x6C0000,
+        Emerald => 0xE40000,
+    }
+}
+
+/// How many free bytes are kept before written data, so that it
+/// can't be mistaken for the end of the data before it.
+const FREE_SPACE_PADDING: usize = 16;
+
+/// Finds free space for new data: runs of `0xFF` bytes past the data of
+/// the original game.
+///
+/// The first bytes of every run are left alone, since they may be the end of
+/// the data before it rather than free space. The data written through the
+/// same `FreeSpace` is kept track of, so that it's never overwritten by the
+/// next writes even if it ends with `0xFF` bytes itself.
+pub struct FreeSpace {
+    start: usize,
+    /// The ranges that were written, in order
+    written: Vec<Range<usize>>,
+}
+
+impl FreeSpace {
+    pub fn new(rom: &Rom) -> Self {
+        Self {
+            start: free_space_start(&rom.rom_type),
+            written: vec![],
+        }
+    }
+
+    /// Writes the bytes to the first free space that fits them,
+    /// aligned to 4 bytes. Returns where they were written.
+    pub fn write(&mut self, rom: &mut Rom, bytes: &[u8]) -> Option<usize> {
+        let start = self.find(&rom.data, bytes.len())?;
+        rom.data[start..start + bytes.len()].copy_from_slice(bytes);
+        self.written.push(start..start + bytes.len());
+        Some(start)
+    }
+
+    /// Returns a point that [`FreeSpace::rollback`] can go back to.
+    pub fn checkpoint(&self) -> usize {
+        self.written.len()
+    }
+
+    /// Frees the data written since the checkpoint, filling it with `0xFF` again.
+    pub fn rollback(&mut self, rom: &mut Rom, checkpoint: usize) {
+        for range in self.written.drain(checkpoint..) {
+            rom.data[range].fill(0xFF);
+        }
+    }
+
+    /// Runs `f` with the written data reserved, so that the allocator of
+    /// `poly3lib`, which looks for runs of `0xFF` bytes, can't take it.
+    ///
+    /// The `0xFF` bytes of the written data are replaced while `f` runs,
+    /// and put back after it.
+    pub fn reserved<T>(&self, rom: &mut Rom, f: impl FnOnce(&mut Rom) -> T) -> T {
+        let masked = self.mask(&mut rom.data);
+        let result = f(rom);
+        unmask(&mut rom.data, &masked);
+        result
+    }
+
+    /// Replaces the `0xFF` bytes of the written data, returning where they were.
+    fn mask(&self, data: &mut [u8]) -> Vec<usize> {
+        let mut masked = vec![];
+        for range in self.written.iter() {
+            for offset in range.clone() {
+                if data[offset] == 0xFF {
+                    data[offset] = RESERVED_BYTE;
+                    masked.push(offset);
+                }
+            }
+        }
+        masked
+    }
+
+    fn find(&self, data: &[u8], size: usize) -> Option<usize> {
+        let mut written: Vec<&Range<usize>> = self.written.iter().collect();
+        written.sort_by_key(|range| range.start);
+        let mut written = written.into_iter().peekable();
+
+        // Where the current run of free bytes started
+        let mut run_start = self.start;
+        let mut offset = self.start;
+        while offset < data.len() {
+            while written.next_if(|range| range.end <= offset).is_some() {}
+            if let Some(range) = written.peek().filter(|range| range.start <= offset) {
+                offset = range.end;
+                run_start = offset;
+                continue;
+            }
+            if data[offset] != 0xFF {
+                offset += 1;
+                run_start = offset;
+                continue;
+            }
+
+            let candidate = (run_start + FREE_SPACE_PADDING).next_multiple_of(4);
+            if offset + 1 >= candidate + size {
+                return Some(candidate);
+            }
+            offset += 1;
+        }
+        None
+    }
+}
+
+/// What the `0xFF` bytes of the reserved data are replaced with.
+const RESERVED_BYTE: u8 = 0xFE;
+
+/// Puts back the `0xFF` bytes replaced by [`FreeSpace::mask`].
+fn unmask(data: &mut [u8], masked: &[usize]) {
+    for &offset in masked {
+        data[offset] = 0xFF;
+    }
+}
+
+/// Size of the palettes of a tileset (16 palettes of 16 colors).
+pub const TILESET_PALETTES_SIZE: usize = 16 * 16 * 2;
+/// Size of a 4bpp 8x8 tile.
+const TILE_SIZE: usize = 32;
+/// Number of tiles shared by the primary and secondary tilesets.
+const TILES_COUNT: usize = 1024;
+
+/// Returns the size of the tiles of the tileset at the given offset,
+/// which is the space the game reserves for them if they are not compressed.
+fn tileset_tiles_size(rom: &Rom, offset: usize, tiles: usize) -> Option<usize> {
+    if read_u8(rom, offset)? != 0 {
+        return lz77_size(rom, tiles);
+    }
+
+    let primary_tiles = match rom.rom_type {
+        RomType::FireRed | RomType::LeafGreen => 640,
+        RomType::Ruby | RomType::Sapphire | RomType::Emerald => 512,
+    };
+    let is_secondary = read_u8(rom, offset + 1)? != 0;
+    Some(match is_secondary {
+        false => primary_tiles * TILE_SIZE,
+        true => (TILES_COUNT - primary_tiles) * TILE_SIZE,
+    })
+}
+
+/// Returns the tiles, palettes and metatiles of the tileset at the
+/// given offset, which is what changes how its metatiles look.
+pub fn tileset_graphics(rom: &Rom, offset: usize) -> [Option<&[u8]>; 3] {
+    let metatiles_count = rom
+        .refs
+        .tilesets_table
+        .as_ref()
+        .and_then(|table| table.get(&offset))
+        .map(|x| x.0)
+        .unwrap_or(0);
+
+    let slice = |pointer_offset: usize, size: Option<usize>| {
+        let start = read_pointer(rom, offset + pointer_offset)?;
+        // Uncompressed tiles can stop before the space reserved for them at the end of the ROM
+        rom.data.get(start..(start + size?).min(rom.data.len()))
+    };
+    let tiles_size =
+        read_pointer(rom, offset + 4).and_then(|tiles| tileset_tiles_size(rom, offset, tiles));
+    [
+        slice(4, tiles_size),
+        slice(8, Some(TILESET_PALETTES_SIZE)),
+        slice(12, Some(metatiles_count * 16)),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn free_space(written: Vec<Range<usize>>) -> FreeSpace {
+        FreeSpace { start: 0, written }
+    }
+
+    #[test]
+    fn free_space_skips_the_end_of_the_data() {
+        let mut data = vec![0xFF; 64];
+        data[0] = 1;
+        // The padding after the data is kept, and the start aligned
+        assert_eq!(free_space(vec![]).find(&data, 8), Some(20));
+        assert_eq!(free_space(vec![]).find(&data, 44), Some(20));
+        assert_eq!(free_space(vec![]).find(&data, 45), None);
+    }
+
+    #[test]
+    fn free_space_skips_the_written_data() {
+        let data = vec![0xFF; 128];
+        // Written data that only has 0xFF bytes is still taken
+        let space = free_space(vec![16..48]);
+        assert_eq!(space.find(&data, 8), Some(64));
+        assert_eq!(space.find(&data, 64), Some(64));
+        assert_eq!(space.find(&data, 65), None);
+    }
+
+    /// The first run of `size` free bytes, like `poly3lib` looks for them.
+    fn first_free_run(data: &[u8], size: usize) -> Option<usize> {
+        data.windows(size)
+            .position(|window| window.iter().all(|&byte| byte == 0xFF))
+    }
+
+    #[test]
+    fn reserved_data_ending_with_free_bytes_is_not_taken() {
+        // A copied tileset whose metatiles end with 0xFF bytes
+        let mut data = vec![0xFF; 128];
+        data[16..40].fill(1);
+        let space = free_space(vec![16..48]);
+
+        let masked = space.mask(&mut data);
+        assert_eq!(masked, (40..48).collect::<Vec<_>>());
+        // Another allocator can only take the space before or after it
+        assert_eq!(first_free_run(&data, 16), Some(0));
+        assert_eq!(first_free_run(&data, 17), Some(48));
+
+        unmask(&mut data, &masked);
+        assert!(data[40..].iter().all(|&byte| byte == 0xFF));
+        assert!(data[16..40].iter().all(|&byte| byte == 1));
+    }
+}
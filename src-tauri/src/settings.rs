@@ -0,0 +1,384 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tauri::{AppHandle, Manager};
+
+use crate::{
+    error::AppError,
+    handlers::map_list::MapId,
+    state::{AppResult, AppState},
+};
+
+/// The current version of the settings format, increased every time
+/// a change needs the old settings to be migrated.
+pub const SETTINGS_VERSION: u32 = 2;
+
+/// The functions that upgrade the settings to the next version,
+/// indexed by the version they upgrade from.
+const MIGRATIONS: [fn(&mut Value) -> AppResult<()>; SETTINGS_VERSION as usize] =
+    [migrate_from_v0, migrate_from_v1];
+
+/// The event sent to every window with the new settings after they are set.
+pub const SETTINGS_CHANGED: &str = "settings-changed";
+
+/// The name of the settings file in the app's config folder.
+const SETTINGS_FILE: &str = "settings.json";
+/// How many ROMs are kept in the recent ROMs list.
+const MAX_RECENT_ROMS: usize = 10;
+
+/// The settings of the user, shared by all the ROMs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppSettings {
+    /// The version of the format the settings were saved with.
+    pub version: u32,
+    /// The last opened ROMs, the most recent first.
+    pub recent_roms: Vec<RecentRom>,
+    /// The tabs that were open the last time each ROM was used, by ROM path.
+    pub sessions: HashMap<String, RomSession>,
+    pub backups: BackupPolicy,
+    pub export_formats: ExportFormats,
+    /// Shortcuts that replace the default ones, by binding id.
+    pub keybindings: HashMap<String, String>,
+    pub editor: EditorDefaults,
+    /// The port of the local server the icons are loaded from.
+    pub iconify_port: u16,
+}
+
+impl Default for AppSettings {
+    fn default() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            recent_roms: vec![],
+            sessions: HashMap::new(),
+            backups: BackupPolicy::default(),
+            export_formats: ExportFormats::default(),
+            keybindings: HashMap::new(),
+            editor: EditorDefaults::default(),
+            iconify_port: 3000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecentRom {
+    pub path: String,
+    pub rom_type: String,
+    /// The size of the ROM in bytes
+    pub size: usize,
+    /// When the ROM was last opened, in seconds since the Unix epoch
+    pub last_opened: u64,
+}
+
+/// The tabs that were open for a ROM.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RomSession {
+    pub tabs: Vec<SessionTab>,
+    /// The position of the selected tab
+    pub active: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionTab {
+    /// The name of the view, like "Map Editor"
+    pub view: String,
+    /// The map of the map editor tabs
+    #[serde(default)]
+    pub map: Option<MapId>,
+    /// The selected sub-tab, like "layout"
+    #[serde(default)]
+    pub tab: Option<String>,
+}
+
+/// Whether copies of the ROM are kept before saving it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackupPolicy {
+    pub enabled: bool,
+    /// How many copies are kept before the oldest is deleted.
+    pub max_backups: u32,
+}
+
+impl Default for BackupPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_backups: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExportFormats {
+    /// The format the layouts are exported to by default.
+    pub layout: LayoutExportFormat,
+}
+
+/// The formats a layout can be exported to, named after their extension.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LayoutExportFormat {
+    /// AdvanceMap map with its border
+    #[default]
+    Map,
+    /// AdvanceMap blocks
+    Blk,
+    /// Tiled XML map
+    Tmx,
+    /// Tiled JSON map
+    Tmj,
+}
+
+impl LayoutExportFormat {
+    const ALL: [Self; 4] = [Self::Map, Self::Blk, Self::Tmx, Self::Tmj];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Map => "map",
+            Self::Blk => "blk",
+            Self::Tmx => "tmx",
+            Self::Tmj => "tmj",
+        }
+    }
+
+    /// Returns the format of a file by its extension, if it's one of them.
+    pub fn of_path(path: &str) -> Option<Self> {
+        let extension = Path::new(path).extension()?;
+        Self::ALL
+            .into_iter()
+            .find(|format| extension.eq_ignore_ascii_case(format.extension()))
+    }
+}
+
+/// The options every new editor starts with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EditorDefaults {
+    /// Whether the tileset animations play when a map is opened.
+    pub play_animations: bool,
+    /// Whether the map previews are also saved next to the ROM.
+    pub preview_disk_cache: bool,
+}
+
+impl Default for EditorDefaults {
+    fn default() -> Self {
+        Self {
+            play_animations: true,
+            preview_disk_cache: false,
+        }
+    }
+}
+
+impl AppSettings {
+    /// Moves the ROM to the top of the recent ROMs list.
+    pub fn add_recent_rom(&mut self, rom: RecentRom) {
+        self.recent_roms.retain(|recent| recent.path != rom.path);
+        self.recent_roms.insert(0, rom);
+        self.recent_roms.truncate(MAX_RECENT_ROMS);
+    }
+
+    /// Loads the settings from the app's config folder, using the default
+    /// ones if there are none yet or if they could not be read.
+    ///
+    /// Settings that could not be read are kept as `settings.json.bak`,
+    /// so that they are not lost when the defaults are saved.
+    pub fn init(handle: &AppHandle) -> (Self, Option<PathBuf>) {
+        let Some(path) = settings_path(handle) else {
+            println!("Could not find the config folder, the settings won't be saved");
+            return (Self::default(), None);
+        };
+        if !path.exists() {
+            return (Self::default(), Some(path));
+        }
+
+        match Self::load(&path) {
+            Ok(settings) => (settings, Some(path)),
+            Err(err) => {
+                println!("Could not load the settings, using the defaults: {}", err);
+                if let Err(err) = std::fs::copy(&path, path.with_extension("json.bak")) {
+                    println!("Could not back up the settings: {}", err);
+                }
+                (Self::default(), Some(path))
+            }
+        }
+    }
+
+    /// Loads the settings, migrating them to the current version if they are older.
+    ///
+    /// Before the settings are migrated, a copy of the old ones is
+    /// saved next to them as `settings.json.v<version>.bak`.
+    fn load(path: &Path) -> AppResult<Self> {
+        let path_str = path.display().to_string();
+        let file = std::fs::File::open(path).map_err(|e| {
+            AppError::file(&path_str, format!("Could not open settings file: {}", e))
+        })?;
+        let mut value: Value = serde_json::from_reader(file).map_err(|e| {
+            AppError::file(&path_str, format!("Could not read settings file: {}", e))
+        })?;
+
+        let version = settings_version(&value)?;
+        if version < SETTINGS_VERSION {
+            let backup_path = path.with_extension(format!("json.v{}.bak", version));
+            std::fs::copy(path, &backup_path).map_err(|e| {
+                AppError::file(
+                    backup_path.display().to_string(),
+                    format!("Could not back up the settings file: {}", e),
+                )
+            })?;
+            println!(
+                "Migrating settings from version {} to {}, the old ones were saved to {}",
+                version,
+                SETTINGS_VERSION,
+                backup_path.display()
+            );
+
+            migrate(&mut value)?;
+        }
+
+        let mut settings: AppSettings = serde_json::from_value(value).map_err(|e| {
+            AppError::file(&path_str, format!("Could not read settings file: {}", e))
+        })?;
+        settings.version = SETTINGS_VERSION;
+        Ok(settings)
+    }
+
+    pub fn save(&self, path: &Path) -> AppResult<()> {
+        if let Some(folder) = path.parent() {
+            std::fs::create_dir_all(folder).map_err(|e| {
+                AppError::file(
+                    folder.display().to_string(),
+                    format!("Could not create the config folder: {}", e),
+                )
+            })?;
+        }
+
+        // Write to another file first, so that a failed write can't lose the settings
+        let temp_path = path.with_extension("json.tmp");
+        let text = serde_json::to_string_pretty(self).map_err(|e| {
+            AppError::file(
+                path.display().to_string(),
+                format!("Could not serialize the settings: {}", e),
+            )
+        })?;
+        std::fs::write(&temp_path, text).map_err(|e| {
+            AppError::file(
+                temp_path.display().to_string(),
+                format!("Could not write to settings file: {}", e),
+            )
+        })?;
+        std::fs::rename(&temp_path, path).map_err(|e| {
+            AppError::file(
+                path.display().to_string(),
+                format!("Could not write to settings file: {}", e),
+            )
+        })
+    }
+}
+
+fn settings_path(handle: &AppHandle) -> Option<PathBuf> {
+    Some(handle.path_resolver().app_config_dir()?.join(SETTINGS_FILE))
+}
+
+/// Returns the version of the settings, where settings without one are version 0.
+fn settings_version(value: &Value) -> AppResult<u32> {
+    let version = value.get("version").and_then(Value::as_u64).unwrap_or(0) as u32;
+
+    if version > SETTINGS_VERSION {
+        return Err(AppError::invalid_input(format!(
+            "The settings were saved by a newer version of Polythree (settings version {}, supported up to {})",
+            version, SETTINGS_VERSION
+        )));
+    }
+    Ok(version)
+}
+
+/// Upgrades the settings to the current version.
+fn migrate(value: &mut Value) -> AppResult<()> {
+    for version in settings_version(value)?..SETTINGS_VERSION {
+        MIGRATIONS[version as usize](value)?;
+        value["version"] = Value::from(version + 1);
+    }
+    Ok(())
+}
+
+/// Version 0 settings have the same fields as version 1, which only adds the version.
+fn migrate_from_v0(value: &mut Value) -> AppResult<()> {
+    if !value.is_object() {
+        return Err(AppError::invalid_input(
+            "The settings file is not a JSON object",
+        ));
+    }
+    Ok(())
+}
+
+/// Version 2 stores the type, size and opening time of the recent ROMs
+/// instead of only their path, which are left empty for the old ones.
+fn migrate_from_v1(value: &mut Value) -> AppResult<()> {
+    if let Some(Value::Array(recent_roms)) = value.get_mut("recent_roms") {
+        recent_roms.retain(Value::is_string);
+        for rom in recent_roms.iter_mut() {
+            *rom = json!({
+                "path": rom.take(),
+                "rom_type": "",
+                "size": 0,
+                "last_opened": 0,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Edits the settings and saves them.
+pub fn update_settings(state: &AppState, callback: impl FnOnce(&mut AppSettings)) -> AppResult<()> {
+    let mut settings = state
+        .settings
+        .lock()
+        .map_err(|_| AppError::lock("settings"))?;
+
+    let mut edited = settings.clone();
+    callback(&mut edited);
+
+    let path = state
+        .settings_path
+        .lock()
+        .map_err(|_| AppError::lock("settings path"))?;
+    if let Some(path) = path.as_ref() {
+        edited.save(path)?;
+    }
+    *settings = edited;
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_settings(state: AppState) -> AppResult<AppSettings> {
+    let settings = state
+        .settings
+        .lock()
+        .map_err(|_| AppError::lock("settings"))?;
+    Ok(settings.clone())
+}
+
+/// Replaces the settings, applying them to the open ROMs and sending
+/// them to the windows so that they apply the new keybindings.
+#[tauri::command]
+pub fn set_settings(state: AppState, handle: AppHandle, settings: AppSettings) -> AppResult<()> {
+    let settings = AppSettings {
+        version: SETTINGS_VERSION,
+        ..settings
+    };
+    update_settings(&state, |current| *current = settings.clone())?;
+
+    for rom in state.roms()? {
+        rom.set_backup_policy(settings.backups.clone())?;
+    }
+    if let Err(err) = handle.emit_all(SETTINGS_CHANGED, &settings) {
+        println!("Could not send event: {}", err);
+    }
+    Ok(())
+}
@@ -0,0 +1,75 @@
+//! A hash that gives the same result on every run, platform and Rust
+//! version, unlike `DefaultHasher`, for the keys saved to disk or
+//! compared between ROMs.
+
+/// The 64-bit FNV-1a hash (<http://www.isthe.com/chongo/tech/comp/fnv/>).
+pub struct StableHasher(u64);
+
+const FNV_OFFSET_BASIS: u64 = 0xCBF29CE484222325;
+const FNV_PRIME: u64 = 0x100000001B3;
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(FNV_OFFSET_BASIS)
+    }
+}
+
+impl StableHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) -> &mut Self {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(FNV_PRIME);
+        }
+        self
+    }
+
+    pub fn write_u64(&mut self, value: u64) -> &mut Self {
+        self.write(&value.to_le_bytes())
+    }
+
+    /// Writes the length before the bytes, so that consecutive
+    /// slices can't be split differently for the same hash.
+    pub fn write_slice(&mut self, bytes: &[u8]) -> &mut Self {
+        self.write_u64(bytes.len() as u64).write(bytes)
+    }
+
+    /// Writes whether the value is there before writing it.
+    pub fn write_option<T>(
+        &mut self,
+        value: Option<T>,
+        write: impl FnOnce(&mut Self, T) -> &mut Self,
+    ) -> &mut Self {
+        match value {
+            Some(value) => write(self.write(&[1]), value),
+            None => self.write(&[0]),
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_reference_values() {
+        assert_eq!(StableHasher::new().finish(), 0xCBF29CE484222325);
+        assert_eq!(StableHasher::new().write(b"a").finish(), 0xAF63DC4C8601EC8C);
+        assert_eq!(
+            StableHasher::new().write(b"foobar").finish(),
+            0x85944171F73967E8
+        );
+    }
+
+    #[test]
+    fn slices_are_delimited() {
+        let split = |a: &[u8], b: &[u8]| StableHasher::new().write_slice(a).write_slice(b).finish();
+        assert_ne!(split(b"ab", b"c"), split(b"a", b"bc"));
+    }
+}
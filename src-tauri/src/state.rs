@@ -1,86 +1,238 @@
-use std::sync::Mutex;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex, RwLock, RwLockReadGuard,
+    },
+};
 
 use poly3lib::rom::Rom;
 
-use crate::config::RomConfig;
+use crate::{
+    backups::back_up_rom,
+    config::RomConfig,
+    error::{AppError, ErrorKind},
+    preview_cache::{MapLayouts, PreviewCache},
+    preview_jobs::PreviewJobs,
+    settings::{AppSettings, BackupPolicy},
+};
 
 pub trait AppStateFunctions {
-    /// Set the ROM and path when opened.
-    fn set_rom(&self, path: String, rom: Rom, config: RomConfig);
-    /// Clear the ROM and path when closed.
-    fn clear_rom(&self);
-    /// Runs the function if a ROM is open, otherwise returns an error.
+    /// Runs the function with the ROM, at the same time as the other reads.
     ///
-    /// The function is passed a mutable reference to the ROM.
-    /// **The mutability should only be used to the extent of loading
-    /// new references** or making non-breaking changes.
+    /// Only waits for the ROM to be saved, or for the reads that go through
+    /// [`AppStateFunctions::with_rom`].
+    fn read_rom<T>(&self, callback: impl FnOnce(&Rom) -> AppResult<T>) -> AppResult<T>;
+
+    /// Runs the function with the ROM, blocking every other access to it.
+    ///
+    /// This is for reading the ROM through the `poly3lib` functions that take
+    /// a mutable ROM to load their references, like `map_headers` and
+    /// `map_layouts`. The references are loaded when the ROM is opened, so the
+    /// function should only read what it needs and leave the rest, like
+    /// rendering, to [`AppStateFunctions::read_rom`].
     ///
-    /// If you want to make potentially breaking changes, you should
-    /// use [`AppStateFunctions::save_rom`] instead.
+    /// **The mutability should only be used to the extent of loading
+    /// new references.** If you want to change the ROM, you should
+    /// use [`AppStateFunctions::update_rom`] instead.
     fn with_rom<T>(&self, callback: impl FnOnce(&mut Rom) -> AppResult<T>) -> AppResult<T>;
 
-    /// Runs the function if a ROM is open, otherwise returns an error.
+    /// Runs the function with the ROM.
     ///
     /// After the function is run, the ROM is saved to disk to the
-    /// same path it was opened from.
+    /// same path it was opened from. The first time it's saved, the
+    /// file is backed up first if the user's backup policy says so.
     ///
     /// If an error occurs while running the function, the ROM is
-    /// reverted to its original state.
+    /// reverted to its original state. Otherwise, the layouts used
+    /// by the map previews are read again.
     fn update_rom<T>(&self, callback: impl FnOnce(&mut Rom) -> AppResult<T>) -> AppResult<T>;
 }
 
+/// Identifies an open ROM. Every command that uses a ROM takes the
+/// handle it was given when opened.
+pub type RomHandle = u32;
+
 pub struct RomData {
     /// The path to the ROM.
     path: String,
     /// The rom object itself
     rom: Rom,
+    /// The path to the ROM's config.
+    config_path: String,
+    /// When to back up the ROM, from the user's settings
+    backups: BackupPolicy,
+    /// Whether the ROM was backed up since it was opened
+    backed_up: bool,
+}
+
+/// An open ROM with its config.
+pub struct RomState {
+    /// The handle the ROM was opened with
+    pub handle: RomHandle,
+    /// The ROM and its paths
+    data: RwLock<RomData>,
+    /// The ROM's configuration
+    pub(crate) config: Mutex<RomConfig>,
+    /// Rendered map previews
+    pub(crate) previews: Mutex<PreviewCache>,
+    /// The maps' layouts, for rendering previews. Always locked after the ROM
+    /// data, and only written under its write lock, so that they agree with it.
+    pub(crate) layouts: RwLock<MapLayouts>,
 }
 
 pub struct PolythreeState {
-    /// Info about the open ROM, if any.
-    rom: Mutex<Option<RomData>>,
-    /// Open Rom configuration
-    pub(crate) config: Mutex<Option<RomConfig>>,
+    /// The open ROMs, by their handle
+    roms: RwLock<HashMap<RomHandle, Arc<RomState>>>,
+    /// The handle of the next opened ROM
+    next_handle: AtomicU32,
+    /// Map previews waiting to be rendered
+    pub(crate) preview_jobs: PreviewJobs,
+    /// The settings of the user, loaded when the app starts
+    pub(crate) settings: Mutex<AppSettings>,
+    /// Where the settings are saved, if the config folder was found
+    pub(crate) settings_path: Mutex<Option<PathBuf>>,
 }
 
 impl PolythreeState {
     pub fn new() -> Self {
         Self {
-            rom: Mutex::new(None),
-            config: Mutex::new(None),
+            roms: RwLock::new(HashMap::new()),
+            next_handle: AtomicU32::new(1),
+            preview_jobs: PreviewJobs::default(),
+            settings: Mutex::new(AppSettings::default()),
+            settings_path: Mutex::new(None),
+        }
+    }
+
+    /// Returns the handle of the ROM if the file at the given path is already open.
+    pub fn open_handle(&self, path: &str) -> AppResult<Option<RomHandle>> {
+        open_handle(&*self.read_roms()?, path)
+    }
+
+    /// Adds an opened ROM with its config, returning its handle.
+    ///
+    /// Fails if the same file was opened in the meantime, since
+    /// two states of one file would overwrite each other's saves.
+    pub fn open_rom(
+        &self,
+        path: String,
+        mut rom: Rom,
+        config: RomConfig,
+        config_path: String,
+    ) -> AppResult<RomHandle> {
+        // Read before locking, since it takes a while
+        let layouts = MapLayouts::load(&mut rom);
+        let mut roms = self.roms.write().map_err(|_| AppError::lock("open ROMs"))?;
+        if let Some(open) = open_handle(&roms, &path)? {
+            return Err(already_open(open));
+        }
+
+        let handle = self.next_handle.fetch_add(1, Ordering::Relaxed);
+        let backups = self
+            .settings
+            .lock()
+            .map_err(|_| AppError::lock("settings"))?
+            .backups
+            .clone();
+        let rom_state = RomState {
+            handle,
+            data: RwLock::new(RomData {
+                path,
+                rom,
+                config_path,
+                backups,
+                backed_up: false,
+            }),
+            config: Mutex::new(config),
+            previews: Mutex::new(PreviewCache::default()),
+            layouts: RwLock::new(layouts),
+        };
+
+        roms.insert(handle, Arc::new(rom_state));
+        Ok(handle)
+    }
+
+    /// Closes the ROM, cancelling its map previews.
+    pub fn close_rom(&self, handle: RomHandle) -> AppResult<()> {
+        self.roms
+            .write()
+            .map_err(|_| AppError::lock("open ROMs"))?
+            .remove(&handle)
+            .ok_or_else(|| {
+                AppError::new(ErrorKind::RomNotOpen, format!("ROM {} is not open", handle))
+            })?;
+        self.preview_jobs.cancel(|job| job.state.handle == handle);
+        Ok(())
+    }
+
+    /// Returns the open ROM with the given handle.
+    pub fn rom(&self, handle: RomHandle) -> AppResult<Arc<RomState>> {
+        self.read_roms()?.get(&handle).cloned().ok_or_else(|| {
+            AppError::new(ErrorKind::RomNotOpen, format!("ROM {} is not open", handle))
+        })
+    }
+
+    /// Returns all the open ROMs, in the order they were opened.
+    pub fn roms(&self) -> AppResult<Vec<Arc<RomState>>> {
+        let mut roms: Vec<_> = self.read_roms()?.values().cloned().collect();
+        roms.sort_by_key(|rom| rom.handle);
+        Ok(roms)
+    }
+
+    fn read_roms(&self) -> AppResult<RwLockReadGuard<HashMap<RomHandle, Arc<RomState>>>> {
+        self.roms.read().map_err(|_| AppError::lock("open ROMs"))
+    }
+}
+
+/// Finds the open ROM with the same file as the path.
+fn open_handle(
+    roms: &HashMap<RomHandle, Arc<RomState>>,
+    path: &str,
+) -> AppResult<Option<RomHandle>> {
+    // Compare the files, not how their paths are written
+    let same_file = |other: &str| match (std::fs::canonicalize(path), std::fs::canonicalize(other))
+    {
+        (Ok(path), Ok(other)) => path == other,
+        _ => path == other,
+    };
+
+    for rom in roms.values() {
+        let rom_data = rom.data.read().map_err(|_| AppError::lock("map data"))?;
+        if same_file(&rom_data.path) {
+            return Ok(Some(rom.handle));
         }
     }
+    Ok(None)
 }
 
-pub type AppState<'a> = tauri::State<'a, PolythreeState>;
+pub fn already_open(handle: RomHandle) -> AppError {
+    AppError::invalid_input(format!("This ROM is already open as ROM {}", handle))
+}
 
-pub type AppResult<T> = Result<T, String>;
+pub type AppState<'a> = tauri::State<'a, PolythreeState>;
 
-impl AppStateFunctions for AppState<'_> {
-    fn set_rom(&self, path: String, rom: Rom, config: RomConfig) {
-        let mut rom_data = self.rom.lock().unwrap();
-        *rom_data = Some(RomData { path, rom });
+pub type AppResult<T> = Result<T, AppError>;
 
-        let mut config_data = self.config.lock().unwrap();
-        *config_data = Some(config);
+impl RomState {
+    /// Changes when the ROM is backed up, after the user changed their settings.
+    pub fn set_backup_policy(&self, policy: BackupPolicy) -> AppResult<()> {
+        let mut rom_data = self.data.write().map_err(|_| AppError::lock("map data"))?;
+        rom_data.backups = policy;
+        Ok(())
     }
+}
 
-    fn clear_rom(&self) {
-        let mut rom_data = self.rom.lock().unwrap();
-        *rom_data = None;
-        let mut config_data = self.config.lock().unwrap();
-        *config_data = None;
+impl AppStateFunctions for RomState {
+    fn read_rom<T>(&self, callback: impl FnOnce(&Rom) -> AppResult<T>) -> AppResult<T> {
+        let rom_data = self.data.read().map_err(|_| AppError::lock("map data"))?;
+        callback(&rom_data.rom)
     }
 
     fn with_rom<T>(&self, callback: impl FnOnce(&mut Rom) -> AppResult<T>) -> AppResult<T> {
         // Unlock the ROM data
-        let mut rom_data = self
-            .rom
-            .lock()
-            .map_err(|_| "Failed to unlock the map data")?;
-
-        // Get a mutable reference to the ROM data
-        let rom_data = rom_data.as_mut().ok_or("No ROM is open")?;
+        let mut rom_data = self.data.write().map_err(|_| AppError::lock("map data"))?;
 
         // Call the callback with the ROM
         callback(&mut rom_data.rom)
@@ -88,35 +240,50 @@ impl AppStateFunctions for AppState<'_> {
 
     fn update_rom<T>(&self, callback: impl FnOnce(&mut Rom) -> AppResult<T>) -> AppResult<T> {
         // Unlock the ROM data
-        let mut rom_data = self
-            .rom
-            .lock()
-            .map_err(|_| "Failed to unlock the map data")?;
-
-        let rom_data = rom_data.as_mut().ok_or("No ROM is open")?;
+        let mut rom_data = self.data.write().map_err(|_| AppError::lock("map data"))?;
 
         // Clone the ROM so that we can revert it if an error occurs
         let mut rom = rom_data.rom.clone();
 
         // Call the callback with the ROM and save the result
         let res = callback(&mut rom)?;
+        // Keep a copy of the ROM as it was opened before overwriting it
+        if rom_data.backups.enabled && rom_data.backups.max_backups > 0 && !rom_data.backed_up {
+            back_up_rom(&rom_data.path, &rom_data.backups)?;
+            rom_data.backed_up = true;
+        }
         // Save the modified ROM to disk
-        rom.save(&rom_data.path)
-            .map_err(|err| format!("Failed to save ROM: {}", err))?;
+        rom.save(&rom_data.path).map_err(|err| {
+            AppError::file(&rom_data.path, format!("Failed to save ROM: {}", err))
+        })?;
         // Then, since everything succeeded, update the one in the state
+        let layouts = MapLayouts::load(&mut rom);
         rom_data.rom = rom;
+        *self
+            .layouts
+            .write()
+            .map_err(|_| AppError::lock("map layouts"))? = layouts;
 
         Ok(res)
     }
 }
 
-pub fn get_rom_path(state: &AppState) -> AppResult<String> {
-    let rom_data = state
-        .rom
-        .lock()
-        .map_err(|_| "Failed to unlock the map data")?;
-
-    let rom_data = rom_data.as_ref().ok_or("No ROM is open")?;
+pub fn get_rom_path(state: &RomState) -> AppResult<String> {
+    let rom_data = state.data.read().map_err(|_| AppError::lock("map data"))?;
 
     Ok(rom_data.path.clone())
 }
+
+pub fn get_config_path(state: &RomState) -> AppResult<String> {
+    let rom_data = state.data.read().map_err(|_| AppError::lock("map data"))?;
+
+    Ok(rom_data.config_path.clone())
+}
+
+pub fn set_config_path(state: &RomState, config_path: String) -> AppResult<()> {
+    let mut rom_data = state.data.write().map_err(|_| AppError::lock("map data"))?;
+
+    rom_data.config_path = config_path;
+
+    Ok(())
+}
@@ -0,0 +1,141 @@
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+
+/// A transform applied to the colors of the palettes, used to preview
+/// flashbacks, night, cave darkness and weather effects.
+///
+/// Mirrors the `Tint` of the map canvas, so that previews and the editor agree.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PaletteTint {
+    /// Convert the colors to grayscale before blending
+    pub grayscale: bool,
+    /// The RGB color to blend with
+    pub blend_color: [u8; 3],
+    /// How much of the blend color to use, from 0 (none) to 16 (all)
+    pub blend_amount: u8,
+}
+
+impl PaletteTint {
+    /// Tint that blends the colors with the given one.
+    pub const fn blend(blend_color: [u8; 3], blend_amount: u8) -> Self {
+        Self {
+            grayscale: false,
+            blend_color,
+            blend_amount,
+        }
+    }
+
+    /// Tint that darkens the colors, for night and caves.
+    pub const fn darken(amount: u8) -> Self {
+        Self::blend([0, 0, 0], amount)
+    }
+
+    /// Returns the tint that approximates the given `header.weather` effect.
+    pub fn for_weather(weather: u8) -> Self {
+        match weather {
+            // Rainy, Thunderstorm, Overcast, Downpour, Abnormal
+            3 | 5 | 11 | 13 | 15 => Self::darken(3),
+            // Foggy (horizontal and diagonal)
+            6 | 9 => Self::blend([0xF8, 0xF8, 0xF8], 4),
+            // Volcanic Ash
+            7 => Self::blend([0x80, 0x80, 0x80], 3),
+            // Sandstorm
+            8 => Self::blend([0xC8, 0xA0, 0x60], 4),
+            // Underwater (with and without bubbles)
+            10 | 14 => Self::blend([0x20, 0x40, 0xA0], 4),
+            // Drought
+            12 => Self::blend([0xF8, 0xF8, 0xC0], 4),
+            _ => Self::default(),
+        }
+    }
+
+    /// Returns true if the tint leaves the colors unchanged.
+    pub fn is_identity(&self) -> bool {
+        !self.grayscale && self.blend_amount == 0
+    }
+
+    /// Returns the tinted RGB color.
+    pub fn apply(&self, [mut r, mut g, mut b]: [u8; 3]) -> [u8; 3] {
+        // Same weights as the game's grayscale tint
+        if self.grayscale {
+            let gray = ((r as u32 * 76 + g as u32 * 151 + b as u32 * 29) >> 8) as u8;
+            (r, g, b) = (gray, gray, gray);
+        }
+
+        // Same formula as the game's palette blending
+        let amount = self.blend_amount.min(16) as i32;
+        let blend =
+            |from: u8, to: u8| (from as i32 + (((to as i32 - from as i32) * amount) >> 4)) as u8;
+
+        [
+            blend(r, self.blend_color[0]),
+            blend(g, self.blend_color[1]),
+            blend(b, self.blend_color[2]),
+        ]
+    }
+
+    /// Applies the tint to every pixel of the image.
+    ///
+    /// Since every pixel comes from a palette color, tinting the pixels
+    /// gives the same result as tinting the palettes before rendering.
+    pub fn apply_to_image(&self, image: &mut RgbaImage) {
+        for pixel in image.pixels_mut() {
+            let [r, g, b, a] = pixel.0;
+            let [r, g, b] = self.apply([r, g, b]);
+            pixel.0 = [r, g, b, a];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tints and the colors they turn [0x40, 0x80, 0xC0] into.
+    ///
+    /// The `Tint` of the map canvas is tested against the same cases,
+    /// so that previews and the editor agree.
+    const CASES: [(bool, [u8; 3], u8, [u8; 3]); 7] = [
+        (false, [0xF8, 0x00, 0x10], 0, [0x40, 0x80, 0xC0]),
+        (false, [0xF8, 0x00, 0x10], 8, [0x9C, 0x40, 0x68]),
+        (false, [0xF8, 0x00, 0x10], 16, [0xF8, 0x00, 0x10]),
+        // Amounts over 16 use the whole blend color
+        (false, [0xF8, 0x00, 0x10], 20, [0xF8, 0x00, 0x10]),
+        (true, [0x00, 0x00, 0x00], 0, [0x74, 0x74, 0x74]),
+        (true, [0x00, 0x00, 0x00], 8, [0x3A, 0x3A, 0x3A]),
+        (true, [0xF8, 0xF8, 0xF8], 16, [0xF8, 0xF8, 0xF8]),
+    ];
+
+    #[test]
+    fn applies_the_tints() {
+        for (grayscale, blend_color, blend_amount, expected) in CASES {
+            let tint = PaletteTint {
+                grayscale,
+                blend_color,
+                blend_amount,
+            };
+            assert_eq!(tint.apply([0x40, 0x80, 0xC0]), expected, "{:?}", tint);
+        }
+    }
+
+    #[test]
+    fn grayscale_weights_the_channels() {
+        let tint = PaletteTint {
+            grayscale: true,
+            ..Default::default()
+        };
+
+        assert_eq!(tint.apply([0xFF, 0x00, 0x00]), [75; 3]);
+        assert_eq!(tint.apply([0x00, 0xFF, 0x00]), [150; 3]);
+        assert_eq!(tint.apply([0x00, 0x00, 0xFF]), [28; 3]);
+        assert_eq!(tint.apply([0xFF, 0xFF, 0xFF]), [0xFF; 3]);
+    }
+
+    #[test]
+    fn clear_weather_has_no_tint() {
+        assert!(PaletteTint::for_weather(0).is_identity());
+        assert!(PaletteTint::for_weather(2).is_identity());
+        assert_eq!(PaletteTint::for_weather(3), PaletteTint::darken(3));
+    }
+}
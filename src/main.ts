@@ -2,13 +2,39 @@ import "./styles.scss";
 import "./theme.css";
 import App from "./App.svelte";
 import { addAPIProvider, _api } from "iconify-icon";
+import { invoke } from "@tauri-apps/api";
+import { listen } from "@tauri-apps/api/event";
+import { settings, type AppSettings } from "./systems/global";
+import { overrideShortcuts } from "./systems/bindings";
 
-addAPIProvider("", {
-  resources: ["http://localhost:3000/api"],
+/** Applies the settings every time they are changed */
+listen<AppSettings>("settings-changed", (event) => {
+  settings.set(event.payload);
+  overrideShortcuts(event.payload.keybindings);
 });
 
-const app = new App({
-  target: document.getElementById("app")
+/** Loads the user's settings, which are needed before the app is shown */
+async function loadSettings(): Promise<AppSettings | null> {
+  try {
+    const loaded: AppSettings = await invoke("get_settings");
+    settings.set(loaded);
+    overrideShortcuts(loaded.keybindings);
+    return loaded;
+  }
+  catch (err) {
+    console.error("Could not load the settings", err);
+    return null;
+  }
+}
+
+const app = loadSettings().then(loaded => {
+  addAPIProvider("", {
+    resources: [`http://localhost:${loaded?.iconify_port ?? 3000}/api`],
+  });
+
+  return new App({
+    target: document.getElementById("app")
+  });
 });
 
 export default app;
@@ -37,12 +37,15 @@ type BindingFunction = (view: ViewContext | EditorContext | TabbedEditorContext<
 class KeyBinding {
     public name: string;
     public shortcutCode: string;
+    /** The shortcut the binding has when the user did not change it */
+    public readonly defaultShortcutCode: string;
     public binding: BindingFunction;
     public conditions: Conditions;
 
     constructor(name: string, shortcut: string, callback: BindingFunction, condition: string) {
         this.name = name;
         this.shortcutCode = shortcut;
+        this.defaultShortcutCode = shortcut;
         this.binding = callback;
         this.conditions = KeyBinding.parseConditions(condition);
     }
@@ -228,19 +231,36 @@ const keybindings: Record<string, KeyBinding> = {
 };
 
 /** The object containing all the existing shortcuts */
-const shortcutCodeToKeybindings: Record<string, KeyBinding[]> = {};
-
-// Compose the shortcut to id list
-for (const id in keybindings) {
-    // Get the binding
-    const binding: KeyBinding = keybindings[id];
-    const shortcut: string = binding.shortcutCode;
-
-    // Add it to the list under the shortcut
-    if (shortcutCodeToKeybindings[shortcut]) {
-        shortcutCodeToKeybindings[shortcut] = [binding, ...shortcutCodeToKeybindings[shortcut]];
-    } else
-        shortcutCodeToKeybindings[shortcut] = [binding];
+let shortcutCodeToKeybindings: Record<string, KeyBinding[]> = {};
+
+/** Composes the shortcut to id list */
+function composeShortcuts() {
+    shortcutCodeToKeybindings = {};
+    for (const id in keybindings) {
+        // Get the binding
+        const binding: KeyBinding = keybindings[id];
+        const shortcut: string = binding.shortcutCode;
+
+        // Add it to the list under the shortcut
+        if (shortcutCodeToKeybindings[shortcut]) {
+            shortcutCodeToKeybindings[shortcut] = [binding, ...shortcutCodeToKeybindings[shortcut]];
+        } else
+            shortcutCodeToKeybindings[shortcut] = [binding];
+    }
+}
+composeShortcuts();
+
+/** Replaces the shortcuts of the given bindings, like the ones in the user's settings.
+ *  The other bindings go back to their default shortcuts. */
+export function overrideShortcuts(overrides: Record<string, string>) {
+    for (const id in keybindings)
+        keybindings[id].shortcutCode = keybindings[id].defaultShortcutCode;
+    for (const id in overrides) {
+        if (id in keybindings)
+            keybindings[id].shortcutCode = overrides[id];
+        else console.error(`Binding ${id} does not exist`);
+    }
+    composeShortcuts();
 }
 
 /** Updates multiple bindings functions */
@@ -1,5 +1,6 @@
-import { invoke } from "@tauri-apps/api";
+import { invokeRom } from "src/systems/rom";
 import { spawnErrorDialog } from "src/components/dialog/ErrorDialog.svelte";
+import { errorMessage } from "src/systems/errors";
 import { derived, get, writable, type Readable, type Writable } from "svelte/store";
 
 interface MapSectionDump {
@@ -23,7 +24,7 @@ export async function getMapNamesStore(): Promise<Writable<MapNames>> {
         mapNames = writable(null);
 
         try {
-            const mapsecDump: MapSectionDump = await invoke("get_map_names");
+            const mapsecDump: MapSectionDump = await invokeRom("get_map_names");
 
             // Copy each name from the dump into the names array
             for (let i = 0; i < mapsecDump.names.length; i++)
@@ -34,7 +35,7 @@ export async function getMapNamesStore(): Promise<Writable<MapNames>> {
             // Set the name writable
             mapNames.set(names);
         } catch (err) {
-            await spawnErrorDialog(err + "\nThey will not be shown", "Could not retrieve map names")
+            await spawnErrorDialog(errorMessage(err) + "\nThey will not be shown", "Could not retrieve map names")
         }
     }
     return mapNames;
@@ -49,7 +50,7 @@ export async function setMapName(index: number, newName: string): Promise<boolea
     try {
         // Try to update the rom
         // TODO Uncomment this when the backend is implemented
-        // await invoke("set_map_name", { index, newName });
+        // await invokeRom("set_map_name", { index, newName });
         // If nothing fails update the client-side store
         (await getMapNamesStore()).update((names) => {
             names[index] = newName;
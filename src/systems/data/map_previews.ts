@@ -0,0 +1,106 @@
+import { invokeRom } from "src/systems/rom";
+import { rom } from "src/systems/global";
+import { listen } from "@tauri-apps/api/event";
+import { get } from "svelte/store";
+import type { MapId } from "src/views/MapList";
+
+/** How the colors of a preview are changed, like the `PaletteTint` of the backend */
+export interface PaletteTint {
+    /** Convert the colors to grayscale before blending */
+    grayscale: boolean;
+    /** The RGB color to blend with */
+    blend_color: [number, number, number];
+    /** How much of the blend color to use, from 0 (none) to 16 (all) */
+    blend_amount: number;
+}
+
+export interface MapPreviewOptions {
+    /** The largest side of the preview, in pixels, if it's scaled down */
+    size?: number;
+    tint?: PaletteTint;
+}
+
+interface MapPreviewResult {
+    /** The handle of the ROM the preview is of */
+    rom: number;
+    map: MapId;
+    size: number | null;
+    tint: PaletteTint | null;
+    preview: string | null;
+    error: string | null;
+}
+
+interface PendingPreview {
+    resolve: (preview: string) => void;
+    reject: (error: string) => void;
+}
+
+/** The requests waiting for a preview, by map and options */
+const pending: Map<string, PendingPreview[]> = new Map();
+/** Promise for the listener of the finished previews */
+let listening: Promise<unknown> = null;
+
+/** Identifies the previews of a map with the same size and tint */
+function previewKey({ group, index }: MapId, size: number | null, tint: PaletteTint | null): string {
+    const tintKey = tint ? `${+tint.grayscale},${tint.blend_color.join(",")},${tint.blend_amount}` : "";
+    return `${group}.${index}:${size ?? ""}:${tintKey}`;
+}
+
+/** Starts listening for the previews rendered in the background */
+function listenForPreviews(): Promise<unknown> {
+    if (listening === null) {
+        listening = listen<MapPreviewResult>("map-preview", ({ payload }) => {
+            // Ignore the previews of the other open ROMs
+            if (payload.rom !== get(rom)?.handle) return;
+
+            const key = previewKey(payload.map, payload.size, payload.tint);
+            const requests = pending.get(key) ?? [];
+            pending.delete(key);
+
+            for (const { resolve, reject } of requests) {
+                if (payload.error !== null) reject(payload.error);
+                else resolve(payload.preview);
+            }
+        });
+    }
+    return listening;
+}
+
+export interface MapPreviewRequest {
+    /** Resolves with the preview as a PNG data URL */
+    preview: Promise<string>;
+    /** Stops waiting for the preview, and stops rendering it if no one else is waiting */
+    cancel: () => void;
+}
+
+/** Asks the backend to render a map's preview in the background */
+export function requestMapPreview(map: MapId, { size, tint }: MapPreviewOptions = {}): MapPreviewRequest {
+    size = size ?? null;
+    tint = tint ?? null;
+    const key = previewKey(map, size, tint);
+    let request: PendingPreview;
+
+    const preview = new Promise<string>((resolve, reject) => {
+        request = { resolve, reject };
+        pending.set(key, [...(pending.get(key) ?? []), request]);
+    });
+
+    // Queue the preview once the listener is ready
+    listenForPreviews()
+        .then(() => invokeRom("queue_map_previews", { maps: [map], size, tint }))
+        .catch((err) => request.reject(err));
+
+    const cancel = () => {
+        const others = (pending.get(key) ?? []).filter((r) => r !== request);
+        if (others.length > 0) {
+            pending.set(key, others);
+            return;
+        }
+        // The backend cancels every preview of the map, which may still be waited for with other options
+        const mapPrefix = `${map.group}.${map.index}:`;
+        if (pending.delete(key) && ![...pending.keys()].some((other) => other.startsWith(mapPrefix)))
+            invokeRom("cancel_map_previews", { maps: [map] });
+    };
+
+    return { preview, cancel };
+}
@@ -0,0 +1,30 @@
+import { listen, type UnlistenFn } from "@tauri-apps/api/event";
+import { get } from "svelte/store";
+import { rom } from "src/systems/global";
+import type { MapId } from "src/views/MapList";
+
+/** Payloads of the events the backend sends after changing the ROM or its config */
+export interface RomEvents {
+    "map-created": MapId;
+    "map-deleted": MapId[];
+    "header-updated": MapId;
+    "layout-updated": number;
+    "config-changed": null;
+}
+
+/** How the events are sent, with the handle of the ROM they are about */
+interface RomEventPayload<T> {
+    rom: number;
+    data: T;
+}
+
+/** Calls the handler every time the backend sends the given event about the open ROM */
+export function listenRomEvent<E extends keyof RomEvents>(
+    event: E,
+    handler: (payload: RomEvents[E]) => void
+): Promise<UnlistenFn> {
+    return listen<RomEventPayload<RomEvents[E]>>(event, (e) => {
+        if (e.payload.rom === get(rom)?.handle)
+            handler(e.payload.data);
+    });
+}
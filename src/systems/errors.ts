@@ -0,0 +1,68 @@
+/** What kind of problem an error of the backend is */
+export type ErrorKind =
+    | "rom_not_open"
+    | "not_found"
+    | "invalid_input"
+    | "rom_data"
+    | "file"
+    | "out_of_space"
+    | "lock"
+    | "other";
+
+/** An error returned by a backend command */
+export interface AppError {
+    kind: ErrorKind;
+    message: string;
+    /** What the error is about, when it's known */
+    context: {
+        map?: { group: number, index: number };
+        layout?: number;
+        /** An offset in the ROM */
+        offset?: number;
+        path?: string;
+    };
+    /** Whether trying again after fixing the problem can work */
+    recoverable: boolean;
+}
+
+/** What the user can do about each kind of error */
+const recoveryHints: Record<ErrorKind, string | null> = {
+    rom_not_open: "Open the ROM again and retry.",
+    not_found: "It may have been deleted, refresh the view and retry.",
+    invalid_input: "Check the values you entered and retry.",
+    rom_data: "This part of the ROM could not be read, it may have been broken by another tool.",
+    file: "Check that the file exists and that Polythree can write next to it.",
+    out_of_space: "Free some space in the ROM, or expand it, and retry.",
+    lock: "Polythree is in a broken state, restart it to keep working.",
+    other: null,
+};
+
+export function isAppError(error: unknown): error is AppError {
+    return typeof error === "object" && error !== null
+        && "kind" in error && "message" in error;
+}
+
+/** Returns the message of an error, whether it comes from the backend or not */
+export function errorMessage(error: unknown): string {
+    if (isAppError(error))
+        return error.message;
+    if (error instanceof Error)
+        return error.message;
+    return String(error);
+}
+
+/** Returns what the user can do about an error of the backend */
+export function recoveryHint(error: AppError): string | null {
+    return recoveryHints[error.kind] ?? null;
+}
+
+/** Describes what the error is about, like "Map 3.1, offset $08123456" */
+export function describeErrorContext(error: AppError): string | null {
+    const { map, layout, offset, path } = error.context;
+    const parts = [];
+    if (map) parts.push(`Map ${map.group}.${map.index}`);
+    if (layout !== undefined) parts.push(`Layout ${layout}`);
+    if (offset !== undefined) parts.push(`Offset $${offset.toString(16).toUpperCase().padStart(7, "0")}`);
+    if (path) parts.push(path);
+    return parts.length ? parts.join(", ") : null;
+}
@@ -8,6 +8,8 @@ type BrushStore = Record<number, {
 }>;
 
 export interface Rom {
+    /** What the backend commands use to refer to the ROM */
+    handle: number;
     path: string;
     type: RomType;
     size: number;
@@ -15,6 +17,8 @@ export interface Rom {
 }
 
 export interface Config {
+    /** The version of the config format */
+    version: number;
     /** A map of layout numbers to layout name */
     layout_names: Record<number, string>;
     /** A map of tileset offsets to tileset name */
@@ -25,8 +29,49 @@ export interface Config {
     brushes: BrushStore;
 }
 
+export interface RecentRom {
+    path: string;
+    rom_type: RomType;
+    /** The size of the ROM in bytes */
+    size: number;
+    /** When the ROM was last opened, in seconds since the Unix epoch */
+    last_opened: number;
+}
+
+/** The settings of the user, shared by all the ROMs */
+export interface AppSettings {
+    /** The version of the settings format */
+    version: number;
+    /** The last opened ROMs, the most recent first */
+    recent_roms: RecentRom[];
+    backups: {
+        enabled: boolean;
+        /** How many copies are kept before the oldest is deleted */
+        max_backups: number;
+    };
+    export_formats: {
+        /** The format the layouts are exported to by default */
+        layout: "map" | "blk" | "tmx" | "tmj";
+    };
+    /** Shortcuts that replace the default ones, by binding id */
+    keybindings: Record<string, string>;
+    /** The options every new editor starts with */
+    editor: {
+        play_animations: boolean;
+        preview_disk_cache: boolean;
+    };
+    /** The port of the local server the icons are loaded from */
+    iconify_port: number;
+}
+
 /** The curretly open ROM */
 export const rom: Writable<Rom | null> = writable(null);
 
+/** A second ROM opened to compare the open one against, like the original game */
+export const referenceRom: Writable<Rom | null> = writable(null);
+
 /** The current config */
-export const config: Writable<Config> = writable(null);
\ No newline at end of file
+export const config: Writable<Config> = writable(null);
+
+/** The settings of the user, loaded when the app starts */
+export const settings: Writable<AppSettings> = writable(null);
@@ -1,26 +1,91 @@
 import { invoke } from "@tauri-apps/api";
+import type { InvokeArgs } from "@tauri-apps/api/tauri";
 import { get } from "svelte/store";
 import { open } from "@tauri-apps/api/dialog";
 import { spawnErrorDialog } from "src/components/dialog/ErrorDialog.svelte";
 import { spawnCloseViewsDialog } from "src/components/dialog/CloseViewsDialog.svelte";
+import { spawnConfigMismatchDialog, type ConfigMismatch } from "src/components/dialog/ConfigMismatchDialog.svelte";
 import { HomePageContext } from "src/views/HomePage";
 import { openViews } from "./views";
-import { config, rom, type Config } from "./global";
+import { config, referenceRom, rom, settings, type Config, type Rom } from "./global";
 import { lastClosedViews } from "./views";
 import { resetData } from "./data/common";
+import { restoreSession, saveSession } from "./session";
 
 type RomOpenResponse = {
+    handle: number,
     rom_type: RomType,
     rom_size: number,
     rom_size_fmt: string,
+    config_mismatch: ConfigMismatch | null,
 };
 
-export async function openRom() {
+/** Opens the ROM at the given path, or asks for one if no path is given */
+export async function openRom(path: string = null) {
     if (get(rom) !== null)
         return console.error("A rom is already open");
 
-    const filePath = await open({
-        title: "Open ROM",
+    const filePath = path ?? await askRomPath("Open ROM");
+
+    if (filePath === null)
+        return rom.set(null);
+
+    try {
+        // Initialize the ROM
+        const res = await invoke("init_rom", { path: filePath }) as RomOpenResponse;
+        rom.set(openedRom(res, filePath));
+
+        if (res.config_mismatch !== null)
+            await resolveConfigMismatch(res.config_mismatch);
+
+        // Save the map previews next to the ROM if the user wants to
+        if (get(settings)?.editor.preview_disk_cache)
+            await invokeRom("set_preview_disk_cache", { enabled: true });
+    } catch (err) {
+        await spawnErrorDialog(err, "Error while loading ROM");
+    }
+
+    // Load the configs too
+    await reloadConfig();
+
+    // Reopen the tabs of the last time the ROM was used
+    if (get(rom) !== null)
+        await restoreSession();
+}
+
+/** Opens a ROM next to the open one, to look at it or copy its data.
+ *  The reference ROM's config is left as it is. */
+export async function openReferenceRom(path: string = null) {
+    if (get(referenceRom) !== null)
+        return console.error("A reference rom is already open");
+
+    const filePath = path ?? await askRomPath("Open Reference ROM");
+    if (filePath === null) return;
+
+    try {
+        const res = await invoke("init_rom", { path: filePath }) as RomOpenResponse;
+        referenceRom.set(openedRom(res, filePath));
+    } catch (err) {
+        await spawnErrorDialog(err, "Error while loading the reference ROM");
+    }
+}
+
+export async function closeReferenceRom() {
+    const reference = get(referenceRom);
+    if (reference === null)
+        return console.error("No reference rom is open");
+
+    try {
+        await invoke("close_rom", { rom: reference.handle });
+    } catch (err) {
+        console.error("Could not close the reference ROM", err);
+    }
+    referenceRom.set(null);
+}
+
+async function askRomPath(title: string): Promise<string | null> {
+    return await open({
+        title,
         multiple: false,
         filters: [
             {
@@ -33,27 +98,22 @@ export async function openRom() {
             }
         ]
     }) as string;
+}
 
-    if (filePath === null)
-        return rom.set(null);
-
-    try {
-        // Initialize the ROM
-        const res = await invoke("init_rom", { path: filePath }) as RomOpenResponse;
-        rom.set({
-            size: res.rom_size,
-            sizePretty: res.rom_size_fmt,
-            type: res.rom_type,
-            path: filePath,
-        });
-    } catch (err) {
-        await spawnErrorDialog(err, "Error while loading ROM");
-    }
+function openedRom(res: RomOpenResponse, path: string): Rom {
+    return {
+        handle: res.handle,
+        size: res.rom_size,
+        sizePretty: res.rom_size_fmt,
+        type: res.rom_type,
+        path,
+    };
+}
 
+/** Loads the config of the open ROM into the config store, after it was opened or changed */
+export async function reloadConfig() {
     try {
-        // Load the configs too
-        const configs: Config = await invoke("get_config");
-        // Update the config store
+        const configs: Config = await invokeRom("get_config");
         config.set(configs);
     }
     catch (err) {
@@ -61,7 +121,36 @@ export async function openRom() {
     }
 }
 
+/** Asks the user what to do with a config made for another ROM */
+async function resolveConfigMismatch(mismatch: ConfigMismatch) {
+    while (true) {
+        const choice = await spawnConfigMismatchDialog(mismatch);
+
+        let resolution: any = choice;
+        if (choice === "Load") {
+            const path = await open({
+                title: "Open Config",
+                multiple: false,
+                filters: [{ name: "Config", extensions: ["json"] }],
+            }) as string;
+            // Ask again if no file was picked
+            if (path === null) continue;
+            resolution = { Load: { path } };
+        }
+
+        try {
+            await invokeRom("resolve_config_mismatch", { resolution });
+            return;
+        } catch (err) {
+            await spawnErrorDialog(err, "Error while fixing the config");
+        }
+    }
+}
+
 export async function closeRom() {
+    // Remember the tabs that are open before they are closed
+    await saveSession();
+
     // Close all tabs that require a rom
     const romTabs = get(openViews).filter(v => v.needsRom);
 
@@ -81,8 +170,10 @@ export async function closeRom() {
     if (get(rom) === null)
         return console.error("No rom is open");
 
-    // Close the rom
-    await invoke("close_rom");
+    // Close the rom, and the one it was compared with
+    await invokeRom("close_rom");
+    if (get(referenceRom) !== null)
+        await closeReferenceRom();
 
     // Set the rom
     rom.set(null);
@@ -93,6 +184,15 @@ export async function closeRom() {
     lastClosedViews.set([]);
 }
 
+/** Calls a command of the backend on the open ROM */
+export function invokeRom<T>(cmd: string, args: InvokeArgs = {}): Promise<T> {
+    return invoke(cmd, { ...args, rom: get(rom)?.handle });
+}
+
+/** Calls a command of the backend on the reference ROM */
+export function invokeReferenceRom<T>(cmd: string, args: InvokeArgs = {}): Promise<T> {
+    return invoke(cmd, { ...args, rom: get(referenceRom)?.handle });
+}
 
 export function getPtrOffset<T>(voidPointer: PointedData<T>): number {
     // In case it's unusable or invalid
@@ -0,0 +1,98 @@
+import { invokeRom } from "./rom";
+import { get } from "svelte/store";
+import { MapEditorContext, type MapEditorProperties } from "src/views/MapEditor";
+import { MapListContext } from "src/views/MapList";
+import { TabbedEditorContext } from "./contexts";
+import { rom } from "./global";
+import { activeView, openViews, type AnyContext } from "./views";
+
+/** The tabs that were open for a ROM, as the backend stores them */
+interface RomSession {
+    tabs: SessionTab[];
+    /** The position of the selected tab */
+    active: number | null;
+}
+
+interface SessionTab {
+    /** The name of the view, like "Map Editor" */
+    view: string;
+    /** The map of the map editor tabs */
+    map?: MapEditorProperties;
+    /** The selected sub-tab, like "layout" */
+    tab?: string;
+}
+
+/** How long to wait after the tabs change before saving them */
+const SAVE_DELAY = 1000;
+
+/** If the session is being restored, so that it's not saved halfway */
+let restoring = false;
+let saveTimeout: ReturnType<typeof setTimeout> = null;
+
+/** Returns the tabs of the views that can be reopened, or null for the other views */
+function sessionTab(view: AnyContext): SessionTab | null {
+    const tab = view instanceof TabbedEditorContext ? get(view.selectedTab) : undefined;
+
+    if (view instanceof MapEditorContext)
+        return { view: view.name, map: { ...view.identifier }, tab };
+    if (view instanceof MapListContext)
+        return { view: view.name };
+    return null;
+}
+
+/** Saves the tabs that are open for the current ROM */
+export async function saveSession() {
+    if (get(rom) === null || restoring) return;
+    clearTimeout(saveTimeout);
+
+    const views = get(openViews).filter(view => sessionTab(view) !== null);
+    const active = views.indexOf(get(activeView));
+    const session: RomSession = {
+        tabs: views.map(sessionTab),
+        active: active === -1 ? null : active,
+    };
+
+    try {
+        await invokeRom("save_session", { session });
+    }
+    catch (err) {
+        console.error("Could not save the open tabs", err);
+    }
+}
+
+/** Reopens the tabs that were open the last time the current ROM was used */
+export async function restoreSession() {
+    restoring = true;
+    try {
+        const session: RomSession = await invokeRom("restore_session");
+
+        const views = session.tabs.map(tab => {
+            switch (tab.view) {
+                case "Map Editor": {
+                    const view = new MapEditorContext(tab.map);
+                    if (tab.tab) view.selectedTab.set(tab.tab as any);
+                    return view.create();
+                }
+                case "Map List":
+                    return new MapListContext().create();
+            }
+            return null;
+        });
+        views[session.active]?.select();
+    }
+    catch (err) {
+        console.error("Could not restore the open tabs", err);
+    }
+    finally {
+        restoring = false;
+    }
+}
+
+// Save the session a little after the tabs change
+for (const store of [openViews, activeView]) {
+    store.subscribe(() => {
+        if (get(rom) === null || restoring) return;
+        clearTimeout(saveTimeout);
+        saveTimeout = setTimeout(saveSession, SAVE_DELAY);
+    });
+}
@@ -1,4 +1,7 @@
+import { invoke } from "@tauri-apps/api";
 import { ViewContext } from "src/systems/contexts";
+import type { RecentRom } from "src/systems/global";
+import { openRom } from "src/systems/rom";
 import HomePage from "src/views/HomePage.svelte";
 
 export class HomePageContext extends ViewContext {
@@ -7,8 +10,10 @@ export class HomePageContext extends ViewContext {
     public singularTab = true;
     public needsRom = false;
     public actions = {
-        "home_page/open_last_project": () => {
-            console.log("Opening last project");
+        "home_page/open_last_project": async () => {
+            const recentRoms: RecentRom[] = await invoke("get_recent_roms");
+            if (recentRoms.length !== 0)
+                await openRom(recentRoms[0].path);
         }
     }
 
@@ -10,6 +10,9 @@ import { PaletteModule } from "./MapEditor/modules/palette_module";
 import { MapModule, type MapHeaderData, type MapLayoutData, type TilesetsData } from "./MapEditor/modules/map_module";
 import { AnimationsModule } from "./MapEditor/modules/animations_module";
 import { ActionsModule } from "./MapEditor/modules/actions_module";
+import { listenRomEvent } from "src/systems/data/rom_events";
+import { reloadConfig } from "src/systems/rom";
+import type { UnlistenFn } from "@tauri-apps/api/event";
 
 export interface MapEditorProperties {
     group: number;
@@ -65,6 +68,9 @@ export class MapEditorContext extends TabbedEditorContext<MapEditorTabsIds> {
     public animations: AnimationsModule = new AnimationsModule(this);
     public actions: ActionsModule = new ActionsModule(this);
 
+    /** The listeners of the changes made to the ROM by the other views */
+    private romEvents: Promise<UnlistenFn>[] = [];
+
     // Tileset
     public tileset1Offset: number;
     public tileset2Offset: number;
@@ -123,6 +129,9 @@ export class MapEditorContext extends TabbedEditorContext<MapEditorTabsIds> {
             // Save the brushes
             await this.brushes.save();
         }
+        // Stop following the changes of the ROM
+        for (const unlisten of this.romEvents) unlisten.then(unlisten => unlisten());
+        this.romEvents = [];
         // Unlocks acquired layouts
         this.map.onClose();
         // Closes running timeouts
@@ -157,6 +166,32 @@ export class MapEditorContext extends TabbedEditorContext<MapEditorTabsIds> {
 
         // Load the animations when ready
         this.animations.load();
+        // Follow the changes made to this map elsewhere
+        this.listenRomEvents();
+    }
+
+    private listenRomEvents() {
+        const isThisMap = ({ group, index }: MapEditorProperties) =>
+            group === this.identifier.group && index === this.identifier.index;
+
+        this.romEvents = [
+            listenRomEvent("map-deleted", (maps) => {
+                if (maps.some(isThisMap)) this.close();
+            }),
+            // The editor already has the changes it makes itself
+            listenRomEvent("header-updated", (map) => {
+                if (isThisMap(map) && !this.map.isWriting) this.map.reloadHeader();
+            }),
+            listenRomEvent("layout-updated", (layout) => {
+                if (layout === this.layoutId && !this.map.isWriting) this.map.updateLayout(layout);
+            }),
+            // The levels are read from the config when the tilesets change,
+            // while the brushes show the ones changed elsewhere right away
+            listenRomEvent("config-changed", async () => {
+                await reloadConfig();
+                this.brushes.reload();
+            }),
+        ];
     }
 
     // ANCHOR Editor Methods
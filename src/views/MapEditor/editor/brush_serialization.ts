@@ -1,11 +1,14 @@
-import { invoke } from "@tauri-apps/api";
+import { invokeRom, reloadConfig } from "src/systems/rom";
 import { spawnErrorDialog } from "src/components/dialog/ErrorDialog.svelte";
+import { isAppError } from "src/systems/errors";
 import { config } from "src/systems/global";
 import { get } from "svelte/store";
 import type { SerializedBlocksData } from "./blocks_data";
 import { BrushMaterial, type BrushType } from "./brushes";
 
 export interface SerializedBrush {
+    /** The brush's id in the config, assigned by the backend when it is added */
+    id?: number;
     /** The brushe's type */
     type: BrushType;
     /** The brush name */
@@ -72,6 +75,7 @@ export async function loadBrushesForPrimaryTileset(tilesetOffset: number): Promi
     try {
         // Get the brushes for the primary tileset
         primaryTilesetBrushes = getPrimaryTilesetBrushes(tilesetOffset);
+        rememberStoredBrushes(tilesetOffset, null, primaryTilesetBrushes);
     }
     catch (err) {
         console.error("Could not load Brushes for this Tileset", tilesetOffset);
@@ -92,6 +96,7 @@ export async function loadBrushesForSecondaryTileset(primaryOffset: number, tile
     try {
         // Get the brushes for the secondary tileset
         secondaryTilesetBrushes = getSecondaryTilesetBrushes(primaryOffset, tilesetOffset);
+        rememberStoredBrushes(primaryOffset, tilesetOffset, secondaryTilesetBrushes);
     }
     catch (err) {
         console.error("Could not load Brushes for this Tileset", tilesetOffset);
@@ -105,27 +110,172 @@ export async function loadBrushesForSecondaryTileset(primaryOffset: number, tile
     }
 }
 
+function sameNumbers(a?: number[], b?: number[]): boolean {
+    if (!a || !b) return !a && !b;
+    return a.length === b.length && a.every((value, i) => value === b[i]);
+}
+
+/** Compares two serialized brushes field by field, since the ones read from the
+ *  config may have their fields in a different order or missing optional ones */
+function sameBrush(a: SerializedBrush, b: SerializedBrush): boolean {
+    const ninePatchA = a as SerializedNinePatchBrush, ninePatchB = b as SerializedNinePatchBrush;
+    return a.id === b.id
+        && a.type === b.type
+        && a.name === b.name
+        && !!a.pinned === !!b.pinned
+        && a.primary === b.primary
+        && (a.secondary ?? null) === (b.secondary ?? null)
+        && !a.blocks === !b.blocks
+        && (!a.blocks || (
+            a.blocks.width === b.blocks.width
+            && a.blocks.height === b.blocks.height
+            && sameNumbers(a.blocks.metatiles, b.blocks.metatiles)
+            && sameNumbers(a.blocks.permissions, b.blocks.permissions)
+        ))
+        && sameNumbers(ninePatchA.metatiles, ninePatchB.metatiles)
+        && sameNumbers(ninePatchA.permissions, ninePatchB.permissions);
+}
+
+/** The brushes of each list as they were last read from or written to the config,
+ *  by tilesets, to tell which brushes the editors added, edited or removed since then */
+const storedBrushes: Map<string, Map<number, SerializedBrush>> = new Map();
+/** The number of brush lists being saved, which are not synced with the config meanwhile */
+let savingLists = 0;
+
+function listKey(tileset1: number, tileset2: number | null): string {
+    return `${tileset1}:${tileset2 ?? ""}`;
+}
+
+/** Remembers the brushes of a list as they are stored in the config */
+function rememberStoredBrushes(tileset1: number, tileset2: number | null, brushes: SerializedBrush[]) {
+    storedBrushes.set(listKey(tileset1, tileset2), new Map(brushes.map(brush => [brush.id, brush])));
+}
+
+/** Returns the brushes of a list as they are now in the configs, without creating it */
+function getConfigBrushes(tileset1: number, tileset2: number | null): SerializedBrush[] {
+    const primary = get(config).brushes[tileset1];
+    if (tileset2 === null)
+        return primary?.brushes ?? [];
+    return primary?.secondary[tileset2] ?? [];
+}
+
+/** Runs a command on a brush, ignoring the error if the brush was deleted in the meantime */
+async function invokeOnBrush(command: string, args: Record<string, unknown>): Promise<boolean> {
+    try {
+        await invokeRom(command, args);
+        return true;
+    }
+    catch (e) {
+        if (isAppError(e) && e.kind === "not_found")
+            return false;
+        throw e;
+    }
+}
+
+/** Saves the changes this editor made to a list of brushes one brush at a time,
+ *  so that the brushes changed in the meantime by other editors are kept. */
+async function saveBrushList(tileset1: number, tileset2: number | null, brushes: BrushMaterial[]) {
+    const stored = storedBrushes.get(listKey(tileset1, tileset2)) ?? new Map();
+    const ids = new Set(brushes.map(brush => brush.id));
+
+    // Delete the brushes that were removed from this list
+    for (const id of stored.keys())
+        if (!ids.has(id))
+            await invokeOnBrush("delete_brush", { tileset1, tileset2, id });
+
+    const saved: SerializedBrush[] = [];
+    const addedIds: number[] = [];
+    for (const brush of brushes) {
+        const serialized = brush.serialize();
+        const old = brush.id === undefined ? undefined : stored.get(brush.id);
+
+        // Update the brushes that changed, unless they were deleted elsewhere
+        let exists = !!old;
+        if (old && !sameBrush(old, serialized))
+            exists = await invokeOnBrush("update_brush", { tileset1, tileset2, brush: serialized });
+
+        // Add the brushes that are new to this list
+        if (!exists) {
+            const added: SerializedBrush = await invokeRom("add_brush", {
+                tileset1, tileset2, brush: serialized
+            });
+            brush.id = serialized.id = added.id;
+            addedIds.push(added.id);
+        }
+
+        saved.push(serialized);
+    }
+
+    // The added brushes are at the end, so only reorder if the others were moved
+    const expected = [...stored.keys()].filter(id => ids.has(id)).concat(addedIds);
+    const order = saved.map(brush => brush.id);
+    if (!sameNumbers(expected, order))
+        await invokeRom("reorder_brushes", { tileset1, tileset2, ids: order });
+
+    rememberStoredBrushes(tileset1, tileset2, saved);
+}
+
 export async function saveBrushesForTilesets(
     tileset1: number, tileset2: number,
     primaryBrushes: BrushMaterial[], secondaryBrushes: BrushMaterial[]
 ) {
-    const tileset1Brushes = primaryBrushes.map(brush => brush.serialize());
-    const tileset2Brushes = secondaryBrushes.map(brush => brush.serialize());
-
-    // Update the brushes in the configs
-    config.update(config => {
-        config.brushes[tileset1].brushes = tileset1Brushes;
-        config.brushes[tileset1].secondary[tileset2] = tileset2Brushes;
-        return config;
-    })
-
+    savingLists++;
     try {
         // Save the brushes in the file
-        await invoke("update_brushes", {
-            tileset1, tileset2, tileset1Brushes, tileset2Brushes
-        });
+        await saveBrushList(tileset1, null, primaryBrushes);
+        await saveBrushList(tileset1, tileset2, secondaryBrushes);
     }
     catch (e) {
         await spawnErrorDialog(e, "Failed to save brushes");
     }
-}
\ No newline at end of file
+    finally {
+        savingLists--;
+    }
+
+    // Read the brushes as they are now, with the ones of the other editors
+    await reloadConfig();
+}
+
+/** Updates a list of brushes with the changes made to the config by other editors
+ *  or imports, while keeping the changes this editor has not saved yet.
+ *  Returns the brushes as they should be shown. */
+export function syncBrushList(tileset1: number, tileset2: number | null, brushes: BrushMaterial[]): BrushMaterial[] {
+    if (savingLists > 0) return brushes;
+
+    const stored = storedBrushes.get(listKey(tileset1, tileset2)) ?? new Map();
+    const fresh = getConfigBrushes(tileset1, tileset2);
+    const freshIds = new Set(fresh.map(brush => brush.id));
+    const local = new Map(brushes.filter(brush => brush.id !== undefined).map(brush => [brush.id, brush]));
+    const changedLocally = (brush: BrushMaterial) =>
+        !stored.has(brush.id) || !sameBrush(stored.get(brush.id), brush.serialize());
+
+    const synced: BrushMaterial[] = [];
+    for (const serialized of fresh) {
+        const brush = local.get(serialized.id);
+        // Skip the brushes this editor removed, add the ones added elsewhere
+        if (!brush) {
+            if (!stored.has(serialized.id))
+                synced.push(BrushMaterial.deserialize(serialized));
+        }
+        // Keep the brushes that did not change elsewhere, or that this editor changed
+        else if (changedLocally(brush) || sameBrush(serialized, stored.get(serialized.id)))
+            synced.push(brush);
+        else
+            synced.push(BrushMaterial.deserialize(serialized));
+    }
+
+    for (const brush of brushes) {
+        // Keep the brushes this editor added
+        if (brush.id === undefined || !stored.has(brush.id)) {
+            if (!freshIds.has(brush.id)) synced.push(brush);
+        }
+        // And the ones it changed, which were deleted elsewhere, as new ones
+        else if (!freshIds.has(brush.id) && changedLocally(brush)) {
+            brush.id = undefined;
+            synced.push(brush);
+        }
+    }
+
+    rememberStoredBrushes(tileset1, tileset2, fresh);
+    return synced.filter(brush => brush !== null);
+}
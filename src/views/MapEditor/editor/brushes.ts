@@ -24,6 +24,8 @@ export abstract class BrushMaterial extends PaintingMaterial {
     public blocks: BlocksData;
     /** Unique identifier for svelte each */
     public uid: number = BrushMaterial.LAST_UID++;
+    /** Identifier of the brush in the config, undefined until it is saved */
+    public id?: number;
     /** Canvas images for this brush type */
     public static canvasImages: MapCanvasImage[] = [];
 
@@ -137,6 +139,7 @@ export abstract class BrushMaterial extends PaintingMaterial {
     /** Serializes a brush into a storable object */
     public serialize(serializeBlocks: boolean = true): SerializedBrush {
         return {
+            id: this.id,
             blocks: serializeBlocks ? this.blocks.toSerialized() : null,
             type: this.type,
             name: this.name,
@@ -187,6 +190,7 @@ export class SimpleBrush extends BrushMaterial {
     public clone(): SimpleBrush {
         const brush = new SimpleBrush(this.primary, this.secondary);
         brush.blocks = this.blocks.clone();
+        brush.id = this.id;
         brush.name = this.name;
         brush.pinned = this.pinned;
         return brush;
@@ -208,6 +212,7 @@ export class SimpleBrush extends BrushMaterial {
         brush.blocks = BlocksData.fromSerialized(serialized.blocks);
         if (brush.width > SimpleBrush.MAX_WIDTH || brush.height > SimpleBrush.MAX_HEIGHT)
             return null;
+        brush.id = serialized.id;
         brush.name = serialized.name;
         brush.pinned = writable(serialized.pinned);
         return brush;
@@ -402,6 +407,7 @@ export class NinePatchBrush extends BrushMaterial {
     public clone(): NinePatchBrush {
         const brush = new NinePatchBrush(this.primary, this.secondary);
         brush.blocks = this.blocks.clone();
+        brush.id = this.id;
         brush.name = this.name;
         brush.pinned = this.pinned;
         return brush;
@@ -412,9 +418,10 @@ export class NinePatchBrush extends BrushMaterial {
         serialized.metatiles = [];
         serialized.permissions = [];
 
+        // Always the template's blocks, so that the backend gets the same count
         for (let y = 0; y < 8; y++) {
             for (let x = 0; x < 11; x++) {
-                if (this.blocks.getMetatile(x, y) === NULL_METATILE) continue;
+                if (NINEPATCH_DEFAULT_BLOCKS.getMetatile(x, y) === NULL_METATILE) continue;
                 serialized.metatiles.push(this.blocks.getMetatile(x, y));
                 serialized.permissions.push(this.blocks.getPermission(x, y));
             }
@@ -430,12 +437,13 @@ export class NinePatchBrush extends BrushMaterial {
         const permissions = serialized.permissions.reverse();
         for (let y = 0; y < 8; y++) {
             for (let x = 0; x < 11; x++) {
-                if (brush.blocks.getMetatile(x, y) === NULL_METATILE) continue;
+                if (NINEPATCH_DEFAULT_BLOCKS.getMetatile(x, y) === NULL_METATILE) continue;
                 brush.blocks.setMetatile(x, y, metatiles.pop())
                 brush.blocks.setPermission(x, y, permissions.pop())
             }
         }
 
+        brush.id = serialized.id;
         brush.name = serialized.name;
         brush.pinned = writable(serialized.pinned);
         return brush;
@@ -1,4 +1,5 @@
-import { invoke } from "@tauri-apps/api";
+import { invokeRom } from "src/systems/rom";
+import { settings } from "src/systems/global";
 import type { MapEditorContext } from "src/views/MapEditor";
 import { replace_tiles } from "src/wasm/map-canvas/pkg/map_canvas";
 import { type Writable, writable, type Unsubscriber, get } from "svelte/store";
@@ -28,7 +29,7 @@ export class AnimationsModule {
     /** Listener for the tileset animations. Updates when animations need to be updated */
     public changeStore: Writable<boolean> = writable(false);
     /** if the animations are being played */
-    public playing: Writable<boolean> = writable(true);
+    public playing: Writable<boolean> = writable(get(settings)?.editor.play_animations ?? true);
     /** The animation timeout */
     private timeout: NodeJS.Timeout;
     /** Function to unsubscribe from activeView */
@@ -86,7 +87,7 @@ export class AnimationsModule {
         this.list = null;
 
         // Load the animations
-        const animations: TilesetsAnimations = await invoke('get_tilesets_animations', {
+        const animations: TilesetsAnimations = await invokeRom('get_tilesets_animations', {
             tileset1: this.tileset1Offset,
             tileset2: this.tileset2Offset,
         });
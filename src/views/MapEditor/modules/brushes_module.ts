@@ -6,7 +6,8 @@ import { get, writable, type Writable } from "svelte/store";
 import {
     loadBrushesForPrimaryTileset,
     loadBrushesForSecondaryTileset,
-    saveBrushesForTilesets
+    saveBrushesForTilesets,
+    syncBrushList
 } from "../editor/brush_serialization";
 import { spawnErrorDialog } from "src/components/dialog/ErrorDialog.svelte";
 import type MapCanvas from "../editor/MapCanvas.svelte";
@@ -67,12 +68,25 @@ export class BrushesModule {
             // Close the brush currently being edited
             this.notifyClosedEditedBrush();
             await this.saveBrushesForTilesets();
+            this.reload();
         }
         catch (err) {
             await spawnErrorDialog(err, "Error while saving Brushes");
         }
     }
 
+    /** Updates the brushes with the ones added, edited or removed in the config
+     *  by other editors or imports, keeping the changes not saved yet */
+    public reload() {
+        // The brush editor keeps the index of the brush it is editing
+        if (get(this.editing) !== null) return;
+
+        this.primary.update(brushes => syncBrushList(this.tileset1Offset, null, brushes));
+        this.secondary.update(brushes =>
+            syncBrushList(this.tileset1Offset, this.tileset2Offset, brushes)
+        );
+    }
+
     // ANCHOR Other view updating
     /** Loops through all other MapEditors and pushes this brush into the editing ones */
     public notifyBrushEditingStarted(brush: BrushMaterial) {
@@ -1,15 +1,16 @@
-import { invoke } from "@tauri-apps/api";
 import { config } from "src/systems/global";
 import { spawnErrorDialog } from "src/components/dialog/ErrorDialog.svelte";
-import { getPtrOffset } from "src/systems/rom";
+import { getPtrOffset, invokeRom } from "src/systems/rom";
 import type { MapEditorContext } from "src/views/MapEditor";
-import initWasmFunctions, { load_tileset, render_blocks_data } from "src/wasm/map-canvas/pkg/map_canvas";
+import initWasmFunctions, { load_tileset, render_blocks_data, set_palette_tint } from "src/wasm/map-canvas/pkg/map_canvas";
+import { invoke } from "@tauri-apps/api";
 import { get, writable, type Writable } from "svelte/store";
 import { BlocksData, type ImportedBlocksData } from "../editor/blocks_data";
 import { spawnLayoutPickerDialog } from "../dialogs/LayoutPickerDialog.svelte";
 import { spawnTilesetPickerDialog } from "../dialogs/TilesetPickerDialog.svelte";
 import type MapCanvas from "../editor/MapCanvas.svelte";
 import { Change } from "src/systems/changes";
+import type { PaletteTint } from "src/systems/data/map_previews";
 
 export interface MapHeaderData {
     header: MapHeader,
@@ -42,6 +43,13 @@ interface ImportedTilesetsData {
     tile_limit: number;
     metatile_limit: number;
 }
+/** Tint of the flashback scenes */
+export const FLASHBACK_TINT: PaletteTint = { grayscale: true, blend_color: [0, 0, 0], blend_amount: 0 };
+/** Tint that darkens the map like at night or in a dark cave */
+export const NIGHT_TINT: PaletteTint = { grayscale: false, blend_color: [0, 0, 0], blend_amount: 6 };
+/** Tint that leaves the colors unchanged */
+const NO_TINT: PaletteTint = { grayscale: false, blend_color: [0, 0, 0], blend_amount: 0 };
+
 export interface TilesetsData {
     tiles: Uint8Array;
     metatiles: Uint16Array;
@@ -151,6 +159,11 @@ export class MapModule {
     public mainCanvas: MapCanvas;
     /** The borders data canvas */
     public bordersCanvas: MapCanvas;
+    /** The tint applied to the palettes of the tilesets, if any */
+    public paletteTint: Writable<PaletteTint> = writable(null);
+    /** The header and layout writes of this editor that are running,
+     * whose events come back to it before the command returns */
+    private pendingWrites: number = 0;
 
     // ANCHOR Getters & Setters
     public get identifier() { return this.context.identifier }
@@ -161,6 +174,8 @@ export class MapModule {
     public get tilesets() { return this.context.data.tilesets }
     public get $tilesets() { return get(this.tilesets) }
     public get loading() { return this.context.loading }
+    /** If the last header or layout event may come from this editor */
+    public get isWriting() { return this.pendingWrites > 0 }
 
     public get tileset1Offset() { return this.context.tileset1Offset }
     public set tileset1Offset(value: number) { this.context.tileset1Offset = value }
@@ -212,14 +227,15 @@ export class MapModule {
     /** Loads the header data */
     public async loadHeader(): Promise<MapHeaderData> {
         try {
-            return await invoke('get_map_header_data', {
+            return await invokeRom('get_map_header_data', {
                 group: this.identifier.group,
                 index: this.identifier.index,
             }) as MapHeaderData;
         }
         catch (e) {
-            // If the map header failed to load, close the editor
-            await spawnErrorDialog(e, "Failed to load map header");
+            // If the map header failed to load, close the editor unless the user retries
+            if (await spawnErrorDialog(e, "Failed to load map header", { retry: true }) === "retry")
+                return await this.loadHeader();
             return null;
         }
     }
@@ -251,10 +267,10 @@ export class MapModule {
             // Update the header
             headerData.header.map_layout_id = layoutId;
             headerData.header.map_layout = { offset: layoutOffset };
-            await invoke('update_map_header', {
+            await this.ownWrite(invokeRom('update_map_header', {
                 group: this.identifier.group, index: this.identifier.index,
                 header: headerData.header
-            });
+            }));
             // Save the new layout data
             this.layoutId = layoutId;
             this.layoutOffset = layoutOffset;
@@ -294,10 +310,10 @@ export class MapModule {
 
         // Update the rom with the new tileset ids
         try {
-            await invoke('update_layout_header', {
+            await this.ownWrite(invokeRom('update_layout_header', {
                 id: this.layoutId,
                 header: layoutData.header
-            });
+            }));
         }
         catch (e) {
             // If the map header failed to load, close the editor
@@ -311,7 +327,7 @@ export class MapModule {
         // Get the tilesets lengths
         try {
             [this.tileset1Length, this.tileset2Length] =
-                await invoke('get_tilesets_lengths', { tileset1, tileset2 }) as [number, number];
+                await invokeRom('get_tilesets_lengths', { tileset1, tileset2 }) as [number, number];
             this.tileset1Offset = tileset1;
             this.tileset2Offset = tileset2;
         }
@@ -327,6 +343,28 @@ export class MapModule {
         return this.loadTilesetsData(loadedTilesetsData);
     }
 
+    /** Waits for a write of this editor, so that the events it sends can be told apart */
+    private async ownWrite<T>(write: Promise<T>): Promise<T> {
+        this.pendingWrites++;
+        try {
+            return await write;
+        }
+        finally {
+            this.pendingWrites--;
+        }
+    }
+
+    /** Loads the header again after it was changed elsewhere, following its new layout */
+    public async reloadHeader(): Promise<void> {
+        const header = await this.loadHeader();
+        if (!header) return;
+
+        if (header.header.map_layout_id !== this.layoutId
+            && !await this.updateLayout(header.header.map_layout_id))
+            return;
+        this.header.set(header);
+    }
+
     // ANCHOR Secondary Methods
     /** Updates the layout to the given index. Returns true if all went well, false otherwise */
     public async updateLayout(newLayoutId: number): Promise<boolean> {
@@ -364,7 +402,7 @@ export class MapModule {
     public async setLayout(index: number, layoutData: MapLayoutData): Promise<boolean> {
         // Get the offset from the index
         try {
-            const offset: number = await invoke('get_layout_offset', { id: index });
+            const offset: number = await invokeRom('get_layout_offset', { id: index });
 
             // Create a clone of the layoutData
             layoutData = this.cloneLayoutData(layoutData);
@@ -417,7 +455,7 @@ export class MapModule {
         let loadedTilesetsData: ImportedTilesetsData;
         try {
             loadedTilesetsData =
-                await invoke('get_tilesets_rendering_data', { tileset1, tileset2 });
+                await invokeRom('get_tilesets_rendering_data', { tileset1, tileset2 });
         }
         catch (err) {
             await spawnErrorDialog(err, "Could not load tilesets");
@@ -431,7 +469,7 @@ export class MapModule {
         // Update the tilesetLength and offsets
         try {
             [this.tileset1Length, this.tileset2Length] =
-                await invoke('get_tilesets_lengths', { tileset1, tileset2 }) as [number, number];
+                await invokeRom('get_tilesets_lengths', { tileset1, tileset2 }) as [number, number];
             this.tileset1Offset = tileset1;
             this.tileset2Offset = tileset2;
         }
@@ -481,6 +519,24 @@ export class MapModule {
         this.botTiles.putImageData(this.botTilesData, 0, 0);
     }
 
+    /** Tints the palettes of the tilesets, or restores them if the tint is null */
+    public setPaletteTint(tint: PaletteTint) {
+        this.paletteTint.set(tint);
+        this.sendPaletteTint();
+        this.updateTilesetCache();
+    }
+    /** Tints the palettes like the weather effect set in the header */
+    public async setWeatherTint() {
+        const tint: PaletteTint = await invoke('get_weather_tint', { weather: this.$header.header.weather });
+        this.setPaletteTint(tint);
+    }
+    /** Sends the tint to the renderer, which redraws the chunks of the maps using these tilesets */
+    private sendPaletteTint() {
+        const tint = get(this.paletteTint) ?? NO_TINT;
+        set_palette_tint(this.tileset1Offset, this.tileset2Offset,
+            tint.grayscale, Uint8Array.from(tint.blend_color), tint.blend_amount);
+    }
+
     /** Renders the metatiles onto the given image datas */
     public renderMetatiles(bottomImageData: ImageData, topImageData: ImageData, blocksData: {
         metatiles: Uint16Array,
@@ -607,6 +663,7 @@ export class MapModule {
         // Initialize the renderer with the data
         load_tileset(this.tileset1Offset, this.tileset2Offset,
             metatiles, metatileLayers, tiles, palettes);
+        this.sendPaletteTint();
 
         // Render the tilesets onto the cache
         this.initTilesetCache(imported.metatiles.length);
@@ -684,8 +741,8 @@ export class MapModule {
     private async loadLayoutData(id: number): Promise<[index: number, offset: number, importedData: MapLayoutData] | string> {
         try {
             // Get the layout offset
-            const offset: number = await invoke('get_layout_offset', { id });
-            const importedLayoutData: ImportedMapLayoutData = await invoke('get_map_layout_data', { id });
+            const offset: number = await invokeRom('get_layout_offset', { id });
+            const importedLayoutData: ImportedMapLayoutData = await invokeRom('get_map_layout_data', { id });
             // Convert the imported data to a map layout data
             const layoutData: MapLayoutData = {
                 bits_per_block: importedLayoutData.bits_per_block,
@@ -706,7 +763,7 @@ export class MapModule {
             try {
                 return [
                     tileset1, tileset2,
-                    await invoke('get_tilesets_rendering_data', { tileset1, tileset2 })
+                    await invokeRom('get_tilesets_rendering_data', { tileset1, tileset2 })
                 ];
             }
             catch (message) {
@@ -1,4 +1,4 @@
-import { invoke } from "@tauri-apps/api";
+import { invokeRom } from "src/systems/rom";
 import { spawnErrorDialog } from "src/components/dialog/ErrorDialog.svelte";
 import { EditorContext } from "src/systems/contexts";
 import MapList from "src/views/MapList.svelte";
@@ -141,16 +141,10 @@ export class MapListContext extends EditorContext {
 
         // Load the map list from the backend
         try {
-            const res: MapHeaderDump[] = await invoke("get_map_list");
-
-            let mapCards: MapCardProps[] = [];
-            for (const map of res) {
-                mapCards.push(mapDumpToCardProps(map));
-            }
-
-            this.data.set(mapCards);
+            await this.reload();
         } catch (err) {
-            await spawnErrorDialog(err, "Could not retrieve map list");
+            if (await spawnErrorDialog(err, "Could not retrieve map list", { retry: true }) === "retry")
+                return await this.load();
 
             // Close the view on failure
             this.close();
@@ -160,6 +154,25 @@ export class MapListContext extends EditorContext {
         }
     }
 
+    /** Loads the map list again without showing the loading screen, returning the new cards */
+    public async reload(): Promise<MapCardProps[]> {
+        const res: MapHeaderDump[] = await invokeRom("get_map_list");
+
+        let mapCards: MapCardProps[] = [];
+        for (const map of res) {
+            mapCards.push(mapDumpToCardProps(map));
+        }
+
+        this.data.set(mapCards);
+        return mapCards;
+    }
+
+    /** Loads the card of a single map, without changing the others */
+    public async loadCard(id: MapId): Promise<MapCardProps> {
+        const res: MapHeaderDump = await invokeRom("get_map_header", { ...id });
+        return mapDumpToCardProps(res);
+    }
+
     constructor() {
         super(MapList, {});
         this.data = writable(null);
@@ -0,0 +1,370 @@
+use crate::{
+    data::{Color, TilesetData},
+    draw,
+};
+
+/// A map whose rendered pixels are kept inside wasm, split in square
+/// chunks of blocks so that only the ones touched by an edit are redrawn.
+pub struct ChunkedMap {
+    /// Key of the tilesets used to render this map
+    pub tilesets: (u32, u32),
+    /// The blocks width
+    pub blocks_width: usize,
+    /// The blocks height
+    pub blocks_height: usize,
+    /// The metatiles of every block in the map
+    pub blocks_data: Vec<u16>,
+    /// Size of the side of a chunk (in blocks)
+    pub chunk_size: usize,
+    /// The chunks, row by row
+    pub chunks: Vec<Chunk>,
+}
+
+pub struct Chunk {
+    /// Pixels of the bottom layer
+    pub bot: Vec<Color>,
+    /// Pixels of the top layer
+    pub top: Vec<Color>,
+    /// If the chunk has to be redrawn
+    pub dirty: bool,
+}
+
+impl ChunkedMap {
+    pub fn new(
+        tilesets: (u32, u32),
+        blocks_data: Vec<u16>,
+        blocks_width: usize,
+        blocks_height: usize,
+        chunk_size: usize,
+    ) -> Self {
+        let mut map = ChunkedMap {
+            tilesets,
+            blocks_width,
+            blocks_height,
+            blocks_data,
+            chunk_size,
+            chunks: vec![],
+        };
+
+        // Allocate all the chunks as dirty
+        let chunks_count = map.chunks_width() * map.chunks_height();
+        map.chunks = (0..chunks_count).map(|_| map.new_chunk()).collect();
+
+        map
+    }
+
+    /// Returns a new empty chunk, marked as dirty.
+    fn new_chunk(&self) -> Chunk {
+        let chunk_pixels = self.chunk_pixels_width() * self.chunk_pixels_width();
+        Chunk {
+            bot: vec![Color::default(); chunk_pixels],
+            top: vec![Color::default(); chunk_pixels],
+            dirty: true,
+        }
+    }
+
+    /// Changes the size of the map to the new blocks, which keep the
+    /// old ones in their top-left corner, like after resizing the map.
+    ///
+    /// The chunks that are fully inside both the old and the new map keep
+    /// their pixels, while the others are marked as dirty.
+    pub fn resize(&mut self, blocks_data: Vec<u16>, blocks_width: usize, blocks_height: usize) {
+        // Number of chunks in each direction that are not cut by either size
+        let kept_width = self.blocks_width.min(blocks_width) / self.chunk_size;
+        let kept_height = self.blocks_height.min(blocks_height) / self.chunk_size;
+        let old_chunks_width = self.chunks_width();
+        let width_changed = self.blocks_width != blocks_width;
+
+        let mut old_chunks: Vec<Option<Chunk>> = std::mem::take(&mut self.chunks)
+            .into_iter()
+            .map(Some)
+            .collect();
+
+        self.blocks_data = blocks_data;
+        self.blocks_width = blocks_width;
+        self.blocks_height = blocks_height;
+
+        let (chunks_width, chunks_height) = (self.chunks_width(), self.chunks_height());
+        self.chunks = Vec::with_capacity(chunks_width * chunks_height);
+        for cy in 0..chunks_height {
+            for cx in 0..chunks_width {
+                let chunk = if cx < kept_width && cy < kept_height {
+                    old_chunks[cy * old_chunks_width + cx].take()
+                } else {
+                    None
+                };
+                let chunk = chunk.unwrap_or_else(|| self.new_chunk());
+                self.chunks.push(chunk);
+            }
+        }
+
+        // Three-layered blocks draw the top layer of the block on their
+        // right, which may have appeared or disappeared with the resize
+        if width_changed && kept_width > 0 && kept_height > 0 {
+            let x = kept_width * self.chunk_size - 1;
+            self.invalidate_rect(x, 0, x + 1, kept_height * self.chunk_size);
+        }
+    }
+
+    /// Number of chunks in a row
+    pub fn chunks_width(&self) -> usize {
+        self.blocks_width.div_ceil(self.chunk_size)
+    }
+    /// Number of chunks in a column
+    pub fn chunks_height(&self) -> usize {
+        self.blocks_height.div_ceil(self.chunk_size)
+    }
+    /// Width (and height) of a chunk in pixels
+    pub fn chunk_pixels_width(&self) -> usize {
+        self.chunk_size * 16
+    }
+
+    /// Copies the given blocks in the rectangle starting at (`x`, `y`)
+    /// and marks the chunks that have to be redrawn as dirty.
+    pub fn update_blocks(&mut self, blocks: &[u16], x: usize, y: usize, width: usize) {
+        if width == 0 {
+            return;
+        }
+        let height = blocks.len() / width;
+
+        // Clamp the rectangle to the map
+        let end_x = (x + width).min(self.blocks_width);
+        let end_y = (y + height).min(self.blocks_height);
+        if x >= end_x || y >= end_y {
+            return;
+        }
+
+        for by in y..end_y {
+            let src = (by - y) * width;
+            let dst = by * self.blocks_width;
+            self.blocks_data[dst + x..dst + end_x].copy_from_slice(&blocks[src..src + end_x - x]);
+        }
+
+        // Three-layered blocks draw the top layer of the block on their
+        // right, so the column on the left of the edit has to be redrawn too
+        self.invalidate_rect(x.saturating_sub(1), y, end_x, end_y);
+    }
+
+    /// Marks the chunks containing the given blocks rectangle as dirty.
+    pub fn invalidate_rect(&mut self, start_x: usize, start_y: usize, end_x: usize, end_y: usize) {
+        let chunks_width = self.chunks_width();
+
+        for cy in start_y / self.chunk_size..=(end_y - 1) / self.chunk_size {
+            for cx in start_x / self.chunk_size..=(end_x - 1) / self.chunk_size {
+                self.chunks[cy * chunks_width + cx].dirty = true;
+            }
+        }
+    }
+
+    /// Marks every chunk as dirty.
+    pub fn invalidate_all(&mut self) {
+        for chunk in self.chunks.iter_mut() {
+            chunk.dirty = true;
+        }
+    }
+
+    /// Redraws all the dirty chunks, returning their indices.
+    pub fn render_dirty(&mut self, context: &TilesetData) -> Vec<u32> {
+        let chunks_width = self.chunks_width();
+        let pixels_width = self.chunk_pixels_width();
+        let mut redrawn = vec![];
+
+        for (chunk_index, chunk) in self.chunks.iter_mut().enumerate() {
+            if !chunk.dirty {
+                continue;
+            }
+
+            // Clear the chunk, since invalid blocks are not drawn
+            chunk.bot.fill(Color::default());
+            chunk.top.fill(Color::default());
+
+            let start_x = (chunk_index % chunks_width) * self.chunk_size;
+            let start_y = (chunk_index / chunks_width) * self.chunk_size;
+            let end_x = (start_x + self.chunk_size).min(self.blocks_width);
+            let end_y = (start_y + self.chunk_size).min(self.blocks_height);
+
+            for by in start_y..end_y {
+                for bx in start_x..end_x {
+                    draw::render_block(
+                        context,
+                        &mut chunk.bot,
+                        &mut chunk.top,
+                        &self.blocks_data,
+                        self.blocks_width,
+                        by * self.blocks_width + bx,
+                        (bx - start_x) * 16,
+                        (by - start_y) * 16,
+                        pixels_width,
+                    );
+                }
+            }
+
+            chunk.dirty = false;
+            redrawn.push(chunk_index as u32);
+        }
+
+        redrawn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Metatile;
+
+    /// A tileset with two metatiles: a normal one using the tile filled
+    /// with color 1 and a three-layered one using the tile filled with color 2.
+    fn tileset() -> TilesetData {
+        let color = |r| Color {
+            r,
+            g: 0,
+            b: 0,
+            a: 255,
+        };
+        let mut palette = [Color::default(); 16];
+        palette[1] = color(1);
+        palette[2] = color(2);
+
+        TilesetData {
+            metatiles: vec![
+                Metatile {
+                    bot: [1; 4],
+                    top: [1; 4],
+                },
+                Metatile {
+                    bot: [2; 4],
+                    top: [2; 4],
+                },
+            ],
+            layer_types: vec![0, 3],
+            palettes: vec![palette],
+            base_palettes: vec![palette],
+            tiles: vec![[[0; 8]; 8], [[1; 8]; 8], [[2; 8]; 8]],
+        }
+    }
+
+    fn dirty_chunks(map: &ChunkedMap) -> Vec<usize> {
+        map.chunks
+            .iter()
+            .enumerate()
+            .filter_map(|(index, chunk)| chunk.dirty.then_some(index))
+            .collect()
+    }
+
+    #[test]
+    fn new_chunks_are_dirty() {
+        let map = ChunkedMap::new((0, 0), vec![0; 5 * 3], 5, 3, 2);
+
+        assert_eq!(map.chunks_width(), 3);
+        assert_eq!(map.chunks_height(), 2);
+        assert_eq!(dirty_chunks(&map), vec![0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn rendering_cleans_the_chunks() {
+        let mut map = ChunkedMap::new((0, 0), vec![0; 5 * 3], 5, 3, 2);
+
+        assert_eq!(map.render_dirty(&tileset()), vec![0, 1, 2, 3, 4, 5]);
+        assert!(dirty_chunks(&map).is_empty());
+        assert!(map.render_dirty(&tileset()).is_empty());
+    }
+
+    #[test]
+    fn updates_dirty_only_the_touched_chunks() {
+        let mut map = ChunkedMap::new((0, 0), vec![0; 8 * 8], 8, 8, 4);
+        map.render_dirty(&tileset());
+
+        map.update_blocks(&[1, 1], 5, 1, 2);
+
+        assert_eq!(map.blocks_data[8 + 5..8 + 7], [1, 1]);
+        assert_eq!(dirty_chunks(&map), vec![1]);
+    }
+
+    #[test]
+    fn updates_dirty_the_chunk_on_the_left() {
+        let mut map = ChunkedMap::new((0, 0), vec![0; 8 * 8], 8, 8, 4);
+        map.render_dirty(&tileset());
+
+        map.update_blocks(&[1], 4, 5, 1);
+
+        assert_eq!(dirty_chunks(&map), vec![2, 3]);
+    }
+
+    #[test]
+    fn updates_are_clamped_to_the_map() {
+        let mut map = ChunkedMap::new((0, 0), vec![0; 4 * 4], 4, 4, 2);
+        map.render_dirty(&tileset());
+
+        map.update_blocks(&[1; 9], 3, 3, 3);
+        assert_eq!(map.blocks_data[15], 1);
+        assert_eq!(map.blocks_data.iter().filter(|&&b| b == 1).count(), 1);
+        assert_eq!(dirty_chunks(&map), vec![3]);
+
+        map.render_dirty(&tileset());
+        map.update_blocks(&[1; 4], 4, 0, 2);
+        assert!(dirty_chunks(&map).is_empty());
+    }
+
+    #[test]
+    fn resizing_keeps_the_chunks_inside_both_sizes() {
+        let mut map = ChunkedMap::new((0, 0), vec![0; 5 * 5], 5, 5, 2);
+        map.render_dirty(&tileset());
+
+        map.resize(vec![0; 7 * 3], 7, 3);
+
+        assert_eq!(map.chunks_width(), 4);
+        assert_eq!(map.chunks_height(), 2);
+        // The first two chunks are fully inside the 5x3 corner in common,
+        // but the second one has new blocks on its right
+        assert_eq!(dirty_chunks(&map), vec![1, 2, 3, 4, 5, 6, 7]);
+
+        map.render_dirty(&tileset());
+        map.resize(vec![0; 7 * 5], 7, 5);
+
+        // The width did not change, so only the cut chunks are redrawn
+        assert_eq!(dirty_chunks(&map), vec![3, 4, 5, 6, 7, 8, 9, 10, 11]);
+    }
+
+    #[test]
+    fn resizing_keeps_the_pixels_of_the_kept_chunks() {
+        let mut map = ChunkedMap::new((0, 0), vec![0; 4 * 4], 4, 4, 2);
+        map.render_dirty(&tileset());
+
+        map.resize(vec![0; 4 * 6], 4, 6);
+
+        assert_eq!(dirty_chunks(&map), vec![4, 5]);
+        assert_eq!(map.chunks[0].bot[0].r, 1);
+        assert_eq!(map.render_dirty(&tileset()), vec![4, 5]);
+    }
+
+    #[test]
+    fn three_layers_do_not_read_the_next_row() {
+        // The three-layered block ends the first row, so it must
+        // not take its top layer from the first block of the second row
+        let mut map = ChunkedMap::new((0, 0), vec![0, 1, 0, 0], 2, 2, 2);
+        map.render_dirty(&tileset());
+
+        let chunk = &map.chunks[0];
+        assert_eq!(chunk.top[16].a, 0);
+        assert_eq!(chunk.bot[16].r, 2);
+    }
+
+    #[test]
+    fn three_layers_take_the_top_of_the_next_block() {
+        let mut map = ChunkedMap::new((0, 0), vec![1, 0], 2, 1, 2);
+        map.render_dirty(&tileset());
+
+        let chunk = &map.chunks[0];
+        assert_eq!(chunk.top[0].r, 1);
+        assert_eq!(chunk.bot[0].r, 2);
+    }
+
+    #[test]
+    fn invalid_blocks_are_not_drawn() {
+        let mut map = ChunkedMap::new((0, 0), vec![2, 0xFFFF], 2, 1, 2);
+        map.render_dirty(&tileset());
+
+        let chunk = &map.chunks[0];
+        assert!(chunk.bot.iter().all(|color| color.a == 0));
+    }
+}
@@ -1,5 +1,7 @@
+use crate::tint::Tint;
+
 #[repr(C)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Default)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -7,17 +9,6 @@ pub struct Color {
     pub a: u8,
 }
 
-impl Default for Color {
-    fn default() -> Self {
-        Color {
-            r: 0,
-            g: 0,
-            b: 0,
-            a: 0,
-        }
-    }
-}
-
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct Metatile {
@@ -36,6 +27,19 @@ pub struct TilesetData {
     pub layer_types: Vec<u8>,
     /// The palette to use
     pub palettes: Vec<Palette>,
+    /// The palette as it was loaded, before applying the tint
+    pub base_palettes: Vec<Palette>,
     /// The tiles to use
     pub tiles: Vec<Tile>,
 }
+
+impl TilesetData {
+    /// Recomputes the palettes by applying the tint to the loaded ones.
+    pub fn set_tint(&mut self, tint: &Tint) {
+        for (palette, base) in self.palettes.iter_mut().zip(self.base_palettes.iter()) {
+            for (color, base) in palette.iter_mut().zip(base.iter()) {
+                *color = tint.apply(*base);
+            }
+        }
+    }
+}
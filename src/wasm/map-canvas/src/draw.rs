@@ -5,6 +5,7 @@ const COVERED: u8 = 1;
 const SPLIT: u8 = 2;
 const THREELAYERS: u8 = 3;
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn render(
     context: &TilesetData,
 
@@ -19,132 +20,164 @@ pub(crate) fn render(
     end_x: usize,
     end_y: usize,
 ) {
-    let metatiles_len = context.metatiles.len() as u16;
-
     // Loop through the blocks
     for by in start_y..end_y {
         for bx in start_x..end_x {
-            // Get the block
-            let index = (by * blocks_width + bx) as usize;
-            let metatile_id = match blocks_data[index] {
-                // Avoid drawing blocks you won't find in the metatiles table
-                // and the 0xFFFF block, which is transparent
-                id if id > metatiles_len => continue,
-                // Convert the indices you'll draw to usize for indexing
-                id => id as usize,
-            };
+            render_block(
+                context,
+                bot_layer_image_data,
+                top_layer_image_data,
+                blocks_data,
+                blocks_width,
+                by * blocks_width + bx,
+                bx * 16,
+                by * 16,
+                pixels_width,
+            );
+        }
+    }
+}
 
-            // Get the metatile from the corresponding array
-            let Metatile { bot, top } = &context.metatiles[metatile_id];
-
-            let offset_x = bx * 16;
-            let offset_y = by * 16;
-
-            macro_rules! clear_top_layer {
-                () => {
-                    draw_metatile_layer(
-                        context,
-                        top_layer_image_data,
-                        offset_x,
-                        offset_y,
-                        &[0; 4],
-                        pixels_width,
-                        false,
-                    );
-                };
-            }
+/// Renders the block at `index` in the blocks data to the given
+/// pixel offset of the two layers' image data.
+///
+/// `blocks_width` is needed by three-layered blocks, which take their
+/// top layer from the block on their right in the same row.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn render_block(
+    context: &TilesetData,
 
-            // Get the layer type
-            match context.layer_types[metatile_id] {
-                // TODO Change if you find the difference
-                NORMAL => {
-                    // Hero is covered by top layer
-                    draw_metatile_layer(
-                        context,
-                        bot_layer_image_data,
-                        offset_x,
-                        offset_y,
-                        bot,
-                        pixels_width,
-                        false,
-                    );
-                    draw_metatile_layer(
-                        context,
-                        top_layer_image_data,
-                        offset_x,
-                        offset_y,
-                        top,
-                        pixels_width,
-                        false,
-                    );
-                }
-                COVERED | SPLIT => {
-                    // Hero cover whole block
-                    draw_metatile_layer(
-                        context,
-                        bot_layer_image_data,
-                        offset_x,
-                        offset_y,
-                        bot,
-                        pixels_width,
-                        false,
-                    );
-                    draw_metatile_layer(
-                        context,
-                        bot_layer_image_data,
-                        offset_x,
-                        offset_y,
-                        top,
-                        pixels_width,
-                        true,
-                    );
+    bot_layer_image_data: &mut [Color],
+    top_layer_image_data: &mut [Color],
+    blocks_data: &[u16],
+    blocks_width: usize,
+    index: usize,
+    offset_x: usize,
+    offset_y: usize,
+    pixels_width: usize,
+) {
+    let metatiles_len = context.metatiles.len() as u16;
+
+    let metatile_id = match blocks_data[index] {
+        // Avoid drawing blocks you won't find in the metatiles table
+        // and the 0xFFFF block, which is transparent
+        id if id >= metatiles_len => return,
+        // Convert the indices you'll draw to usize for indexing
+        id => id as usize,
+    };
+
+    // Get the metatile from the corresponding array
+    let Metatile { bot, top } = &context.metatiles[metatile_id];
+
+    macro_rules! clear_top_layer {
+        () => {
+            draw_metatile_layer(
+                context,
+                top_layer_image_data,
+                offset_x,
+                offset_y,
+                &[0; 4],
+                pixels_width,
+                false,
+            );
+        };
+    }
+
+    // Get the layer type
+    match context.layer_types[metatile_id] {
+        // TODO Change if you find the difference
+        NORMAL => {
+            // Hero is covered by top layer
+            draw_metatile_layer(
+                context,
+                bot_layer_image_data,
+                offset_x,
+                offset_y,
+                bot,
+                pixels_width,
+                false,
+            );
+            draw_metatile_layer(
+                context,
+                top_layer_image_data,
+                offset_x,
+                offset_y,
+                top,
+                pixels_width,
+                false,
+            );
+        }
+        COVERED | SPLIT => {
+            // Hero cover whole block
+            draw_metatile_layer(
+                context,
+                bot_layer_image_data,
+                offset_x,
+                offset_y,
+                bot,
+                pixels_width,
+                false,
+            );
+            draw_metatile_layer(
+                context,
+                bot_layer_image_data,
+                offset_x,
+                offset_y,
+                top,
+                pixels_width,
+                true,
+            );
+            clear_top_layer!();
+        }
+        THREELAYERS => {
+            draw_metatile_layer(
+                context,
+                bot_layer_image_data,
+                offset_x,
+                offset_y,
+                bot,
+                pixels_width,
+                false,
+            );
+            draw_metatile_layer(
+                context,
+                bot_layer_image_data,
+                offset_x,
+                offset_y,
+                top,
+                pixels_width,
+                true,
+            );
+
+            // Get the next metatile, if this block is not the last of its row
+            let next_index = index + 1;
+            let next_block = if !next_index.is_multiple_of(blocks_width) {
+                blocks_data.get(next_index).copied()
+            } else {
+                None
+            };
+            let next_metatile = match next_block {
+                Some(id) if id < metatiles_len => id as usize,
+                _ => {
                     clear_top_layer!();
+                    return;
                 }
-                THREELAYERS => {
-                    draw_metatile_layer(
-                        context,
-                        bot_layer_image_data,
-                        offset_x,
-                        offset_y,
-                        bot,
-                        pixels_width,
-                        false,
-                    );
-                    draw_metatile_layer(
-                        context,
-                        bot_layer_image_data,
-                        offset_x,
-                        offset_y,
-                        top,
-                        pixels_width,
-                        true,
-                    );
-
-                    // Get the next metatile
-                    let next_metatile = match blocks_data[index + 1] {
-                        id if id > metatiles_len => {
-                            clear_top_layer!();
-                            continue;
-                        }
-                        id => id as usize,
-                    };
-                    let Metatile { bot: _, top } = &context.metatiles[next_metatile];
-
-                    draw_metatile_layer(
-                        context,
-                        top_layer_image_data,
-                        offset_x,
-                        offset_y,
-                        top,
-                        pixels_width,
-                        false,
-                    );
-                }
-
-                // This should not happen
-                _ => unreachable!("The backend relies on an enum that has values only in 0..=3"),
-            }
+            };
+            let Metatile { bot: _, top } = &context.metatiles[next_metatile];
+
+            draw_metatile_layer(
+                context,
+                top_layer_image_data,
+                offset_x,
+                offset_y,
+                top,
+                pixels_width,
+                false,
+            );
         }
+
+        // This should not happen
+        _ => unreachable!("The backend relies on an enum that has values only in 0..=3"),
     }
 }
 
@@ -168,7 +201,7 @@ fn draw_metatile_layer(
         let vflip = tile_info & 0x800 != 0;
 
         // Get the tile
-        if tile_id > context.tiles.len() {
+        if tile_id >= context.tiles.len() {
             continue;
         }
         let tile: &Tile = &context.tiles[tile_id];
@@ -2,17 +2,37 @@ extern crate console_error_panic_hook;
 
 use wasm_bindgen::prelude::*;
 
-use crate::data::{Color, Metatile, Palette, Tile, TilesetData};
-use std::{cell::OnceCell, collections::HashMap, mem::transmute};
+use crate::{
+    chunks::ChunkedMap,
+    data::{Color, Metatile, Palette, Tile, TilesetData},
+    overlay::OverlayStyle,
+    tint::Tint,
+};
+use std::{cell::RefCell, collections::HashMap, mem::transmute};
 
+mod chunks;
 mod data;
 mod draw;
+mod overlay;
+mod tint;
 
-// Global variable for the context
-static mut LOADED_TILESETS: OnceCell<HashMap<(u32, u32), TilesetData>> = OnceCell::new();
+thread_local! {
+    // Global variable for the context
+    static LOADED_TILESETS: RefCell<HashMap<(u32, u32), TilesetData>> = RefCell::new(HashMap::new());
+    // Global variable for the maps rendered in chunks
+    static CHUNKED_MAPS: RefCell<HashMap<u32, ChunkedMap>> = RefCell::new(HashMap::new());
+    // Global variable for the permissions overlay colors
+    static OVERLAY_STYLE: RefCell<OverlayStyle> = RefCell::new(OverlayStyle::default());
+}
 
 #[wasm_bindgen]
 /// Renders the blocks data to two image datas obtained by a canvas.
+///
+/// # Safety
+///
+/// The image datas are reinterpreted as colors, so they must be
+/// the RGBA data of a canvas' `ImageData`.
+#[allow(clippy::too_many_arguments)]
 pub unsafe fn render_blocks_data(
     bottom_layer_image_data: &mut [u8],
     top_layer_image_data: &mut [u8],
@@ -49,28 +69,32 @@ pub unsafe fn render_blocks_data(
         "blocks_data has wrong size"
     );
 
-    // Get the data for this context
-    let context = LOADED_TILESETS
-        .get()
-        .unwrap()
-        .get(&(primary_offset, secondary_offset))
-        .unwrap();
-
-    draw::render(
-        context,
-        bot_layer_image_data,
-        top_layer_image_data,
-        blocks_data,
-        blocks_width as usize,
-        blocks_width as usize * 16,
-        start_x as usize,
-        start_y as usize,
-        end_x as usize,
-        end_y as usize,
-    );
+    LOADED_TILESETS.with_borrow(|tilesets| {
+        // Get the data for this context
+        let context = tilesets.get(&(primary_offset, secondary_offset)).unwrap();
+
+        draw::render(
+            context,
+            bot_layer_image_data,
+            top_layer_image_data,
+            blocks_data,
+            blocks_width as usize,
+            blocks_width as usize * 16,
+            start_x as usize,
+            start_y as usize,
+            end_x as usize,
+            end_y as usize,
+        );
+    });
 }
 
 #[wasm_bindgen]
+/// Loads the tilesets with the given offsets, unless they are already loaded.
+///
+/// # Safety
+///
+/// The metatiles, tiles and palettes are reinterpreted as their structures,
+/// so they must be the raw data of the tilesets as read from the ROM.
 pub unsafe fn load_tileset(
     primary_offset: u32,
     secondary_offset: u32,
@@ -79,10 +103,9 @@ pub unsafe fn load_tileset(
     tiles: &[u8],
     palettes: &[u8],
 ) {
-    // Make sure that the hashmap exists and that the tileset is not already loaded
+    // Make sure that the tileset is not already loaded
     if LOADED_TILESETS
-        .get_or_init(|| HashMap::new())
-        .contains_key(&(primary_offset, secondary_offset))
+        .with_borrow(|tilesets| tilesets.contains_key(&(primary_offset, secondary_offset)))
     {
         return;
     }
@@ -107,37 +130,325 @@ pub unsafe fn load_tileset(
     let tileset_data = TilesetData {
         metatiles,
         layer_types,
+        base_palettes: palettes.clone(),
         palettes,
         tiles,
     };
 
     // Insert the context map
-    LOADED_TILESETS
-        .get_mut()
-        .unwrap()
-        .insert((primary_offset, secondary_offset), tileset_data);
+    LOADED_TILESETS.with_borrow_mut(|tilesets| {
+        tilesets.insert((primary_offset, secondary_offset), tileset_data)
+    });
 }
 
 #[wasm_bindgen]
+/// Replaces the tiles of a loaded tileset starting from `tiles_start`.
+///
+/// # Safety
+///
+/// The tiles are reinterpreted as 8x8 tiles of one byte per pixel,
+/// so their length must be a multiple of 64.
 pub unsafe fn replace_tiles(
     primary_offset: u32,
     secondary_offset: u32,
     tiles_start: u32,
     tiles: &[u8],
 ) {
-    // Get the data for this context
-    let context = LOADED_TILESETS
-        .get_mut()
-        .unwrap()
-        .get_mut(&(primary_offset, secondary_offset))
-        .unwrap();
-
     // Transmute the tiles into an slice of Tiles
     let tiles: &[Tile] = transmute(tiles);
 
     let tiles_start = tiles_start as usize;
     // For some reason the lenght does not update, so we have to recompute it
-    let tiles_length = tiles.len() as usize / 64;
-    // Replace the tiles
-    context.tiles[tiles_start..tiles_start + tiles_length].copy_from_slice(&tiles[0..tiles_length]);
+    let tiles_length = tiles.len() / 64;
+
+    LOADED_TILESETS.with_borrow_mut(|tilesets| {
+        // Get the data for this context
+        let context = tilesets
+            .get_mut(&(primary_offset, secondary_offset))
+            .unwrap();
+
+        // Replace the tiles
+        context.tiles[tiles_start..tiles_start + tiles_length]
+            .copy_from_slice(&tiles[0..tiles_length]);
+    });
+}
+
+#[wasm_bindgen]
+/// Tints the palettes of a loaded tileset without reloading its tiles.
+///
+/// The colors are first converted to grayscale (if `grayscale` is set)
+/// and then blended with the RGB `blend_color` by `blend_amount` / 16.
+/// Use black to darken and an all-zero amount to restore the original.
+///
+/// The chunked maps that use the tileset are marked as dirty.
+pub fn set_palette_tint(
+    primary_offset: u32,
+    secondary_offset: u32,
+    grayscale: bool,
+    blend_color: &[u8],
+    blend_amount: u8,
+) {
+    assert!(blend_color.len() == 3, "blend_color has wrong size");
+
+    LOADED_TILESETS.with_borrow_mut(|tilesets| {
+        // Get the data for this context
+        let context = tilesets
+            .get_mut(&(primary_offset, secondary_offset))
+            .unwrap();
+
+        context.set_tint(&Tint {
+            grayscale,
+            blend_color: Color {
+                r: blend_color[0],
+                g: blend_color[1],
+                b: blend_color[2],
+                a: 255,
+            },
+            blend_amount,
+        });
+    });
+
+    // The chunks drawn with these tilesets have the old colors
+    CHUNKED_MAPS.with_borrow_mut(|maps| {
+        maps.values_mut()
+            .filter(|map| map.tilesets == (primary_offset, secondary_offset))
+            .for_each(ChunkedMap::invalidate_all)
+    });
+}
+
+// ANCHOR Permissions overlay
+#[wasm_bindgen]
+/// Renders the permissions (collision and elevation) of the blocks
+/// to a third image data, to draw on top of the other two layers.
+///
+/// If `glyphs` is set, each block also has its level printed on it.
+///
+/// # Safety
+///
+/// The image data is reinterpreted as colors, so it must be
+/// the RGBA data of a canvas' `ImageData`.
+#[allow(clippy::too_many_arguments)]
+pub unsafe fn render_permissions_data(
+    overlay_image_data: &mut [u8],
+    permissions_data: &[u8],
+    blocks_width: u32,
+    blocks_height: u32,
+
+    start_x: u32,
+    start_y: u32,
+    end_x: u32,
+    end_y: u32,
+
+    glyphs: bool,
+) {
+    // Make sure the image data is the correct size
+    let expected_image_data_size = blocks_width * 16 * blocks_height * 16 * 4;
+    assert!(
+        overlay_image_data.len() == expected_image_data_size as usize,
+        "overlay_image_data has wrong size"
+    );
+
+    // Transmute the data so that it matches the correct type
+    let overlay_image_data: &mut [Color] = transmute(overlay_image_data);
+
+    // Make sure the permissions data is the correct size
+    assert!(
+        permissions_data.len() == (blocks_width * blocks_height) as usize,
+        "permissions_data has wrong size"
+    );
+
+    OVERLAY_STYLE.with_borrow(|style| {
+        overlay::render_permissions(
+            style,
+            overlay_image_data,
+            permissions_data,
+            blocks_width as usize,
+            blocks_width as usize * 16,
+            start_x as usize,
+            start_y as usize,
+            end_x as usize,
+            end_y as usize,
+            glyphs,
+        )
+    });
+}
+
+#[wasm_bindgen]
+/// Sets the colors used by [`render_permissions_data`].
+///
+/// `colors` contains an RGBA color for each permission value, while
+/// `null_color` and `glyph_color` are a single RGBA color each.
+pub fn set_permission_colors(colors: &[u8], null_color: &[u8], glyph_color: &[u8]) {
+    assert!(colors.len().is_multiple_of(4), "colors has wrong size");
+    assert!(null_color.len() == 4, "null_color has wrong size");
+    assert!(glyph_color.len() == 4, "glyph_color has wrong size");
+
+    let to_color = |rgba: &[u8]| Color {
+        r: rgba[0],
+        g: rgba[1],
+        b: rgba[2],
+        a: rgba[3],
+    };
+
+    OVERLAY_STYLE.set(OverlayStyle {
+        colors: colors.chunks(4).map(to_color).collect(),
+        null_color: to_color(null_color),
+        glyph_color: to_color(glyph_color),
+    });
+}
+
+// ANCHOR Chunked rendering
+#[wasm_bindgen]
+/// Creates a map identified by `handle` whose pixels are kept in wasm
+/// in chunks of `chunk_size` by `chunk_size` blocks.
+///
+/// All the chunks start dirty, so call [`render_dirty_chunks`] to draw them.
+pub fn create_chunked_map(
+    handle: u32,
+    primary_offset: u32,
+    secondary_offset: u32,
+    blocks_data: &[u16],
+    blocks_width: u32,
+    blocks_height: u32,
+    chunk_size: u32,
+) {
+    console_error_panic_hook::set_once();
+
+    // Make sure the blocks data is the correct size
+    assert!(
+        blocks_data.len() == (blocks_width * blocks_height) as usize,
+        "blocks_data has wrong size"
+    );
+    assert!(chunk_size > 0, "chunk_size must be positive");
+
+    let map = ChunkedMap::new(
+        (primary_offset, secondary_offset),
+        blocks_data.to_vec(),
+        blocks_width as usize,
+        blocks_height as usize,
+        chunk_size as usize,
+    );
+
+    CHUNKED_MAPS.with_borrow_mut(|maps| maps.insert(handle, map));
+}
+
+#[wasm_bindgen]
+/// Changes the size of a chunked map after the map was resized,
+/// keeping the pixels of the chunks whose blocks did not change.
+pub fn resize_chunked_map(handle: u32, blocks_data: &[u16], blocks_width: u32, blocks_height: u32) {
+    // Make sure the blocks data is the correct size
+    assert!(
+        blocks_data.len() == (blocks_width * blocks_height) as usize,
+        "blocks_data has wrong size"
+    );
+
+    with_chunked_map(handle, |map| {
+        map.resize(
+            blocks_data.to_vec(),
+            blocks_width as usize,
+            blocks_height as usize,
+        )
+    });
+}
+
+#[wasm_bindgen]
+/// Frees the memory used by a chunked map.
+pub fn drop_chunked_map(handle: u32) {
+    CHUNKED_MAPS.with_borrow_mut(|maps| maps.remove(&handle));
+}
+
+#[wasm_bindgen]
+/// Returns the number of chunks in a row and in a column of a chunked map.
+pub fn get_chunks_size(handle: u32) -> Vec<u32> {
+    with_chunked_map(handle, |map| {
+        vec![map.chunks_width() as u32, map.chunks_height() as u32]
+    })
+}
+
+#[wasm_bindgen]
+/// Writes the `blocks_width`-wide rectangle of blocks at (`start_x`, `start_y`)
+/// in the chunked map and marks the chunks it touches as dirty.
+pub fn update_chunked_blocks(
+    handle: u32,
+    blocks_data: &[u16],
+    blocks_width: u32,
+    start_x: u32,
+    start_y: u32,
+) {
+    assert!(
+        blocks_width > 0 && blocks_data.len().is_multiple_of(blocks_width as usize),
+        "blocks_data has wrong size"
+    );
+
+    with_chunked_map(handle, |map| {
+        map.update_blocks(
+            blocks_data,
+            start_x as usize,
+            start_y as usize,
+            blocks_width as usize,
+        )
+    });
+}
+
+#[wasm_bindgen]
+/// Marks all the chunks of a chunked map as dirty (e.g. after the tiles changed).
+pub fn invalidate_chunked_map(handle: u32) {
+    with_chunked_map(handle, ChunkedMap::invalidate_all);
+}
+
+#[wasm_bindgen]
+/// Redraws the dirty chunks of a chunked map and returns their indices,
+/// so that only their bitmaps have to be fetched with [`get_chunk_data`].
+pub fn render_dirty_chunks(handle: u32) -> Vec<u32> {
+    with_chunked_map(handle, |map| {
+        LOADED_TILESETS.with_borrow(|tilesets| {
+            // Get the data for this context
+            let context = tilesets.get(&map.tilesets).unwrap();
+
+            map.render_dirty(context)
+        })
+    })
+}
+
+#[wasm_bindgen]
+/// Copies the pixels of a chunk to two image datas of side `chunk_size * 16`.
+///
+/// # Safety
+///
+/// The image datas are reinterpreted as colors, so they must be
+/// the RGBA data of a canvas' `ImageData`.
+pub unsafe fn get_chunk_data(
+    handle: u32,
+    chunk_index: u32,
+    bottom_layer_image_data: &mut [u8],
+    top_layer_image_data: &mut [u8],
+) {
+    // Transmute the data so that it matches the correct type
+    let bot_layer_image_data: &mut [Color] = transmute(bottom_layer_image_data);
+    let top_layer_image_data: &mut [Color] = transmute(top_layer_image_data);
+
+    with_chunked_map(handle, |map| {
+        // Make sure the image data are the correct size
+        let expected_image_data_size = map.chunk_pixels_width() * map.chunk_pixels_width() * 4;
+        assert!(
+            bot_layer_image_data.len() == expected_image_data_size,
+            "bottom_layer_image_data has wrong size"
+        );
+        assert!(
+            top_layer_image_data.len() == expected_image_data_size,
+            "top_layer_image_data has wrong size"
+        );
+
+        let chunk = &map.chunks[chunk_index as usize];
+        // For the same reason as in `replace_tiles`, the length is in bytes
+        let pixels = expected_image_data_size / 4;
+        bot_layer_image_data[..pixels].copy_from_slice(&chunk.bot);
+        top_layer_image_data[..pixels].copy_from_slice(&chunk.top);
+    });
+}
+
+/// Runs the function with the chunked map of the given handle.
+fn with_chunked_map<T>(handle: u32, callback: impl FnOnce(&mut ChunkedMap) -> T) -> T {
+    CHUNKED_MAPS
+        .with_borrow_mut(|maps| callback(maps.get_mut(&handle).expect("chunked map not found")))
 }
@@ -0,0 +1,297 @@
+use crate::data::Color;
+
+/// Permission value used for blocks without permission
+const NULL_PERMISSION: u8 = 0xFF;
+
+/// Alpha of the default colors
+const DEFAULT_ALPHA: u8 = 0x54;
+
+/// 3x5 bitmaps of the digits, one row per byte (bit 2 is the leftmost pixel)
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// Colors used to draw the permissions overlay, set by the editor.
+pub struct OverlayStyle {
+    /// The color of each permission, indexed by its value
+    pub colors: Vec<Color>,
+    /// The color of the blocks without a permission
+    pub null_color: Color,
+    /// The color of the level numbers
+    pub glyph_color: Color,
+}
+
+impl Default for OverlayStyle {
+    fn default() -> Self {
+        OverlayStyle {
+            colors: vec![],
+            null_color: Color {
+                r: 0x88,
+                g: 0x88,
+                b: 0x88,
+                a: DEFAULT_ALPHA,
+            },
+            glyph_color: Color {
+                r: 0xFF,
+                g: 0xFF,
+                b: 0xFF,
+                a: 0xFF,
+            },
+        }
+    }
+}
+
+/// Draws the permissions in the given range of blocks to the overlay image data.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn render_permissions(
+    style: &OverlayStyle,
+
+    overlay_image_data: &mut [Color],
+    permissions: &[u8],
+    blocks_width: usize,
+    pixels_width: usize,
+
+    start_x: usize,
+    start_y: usize,
+    end_x: usize,
+    end_y: usize,
+
+    glyphs: bool,
+) {
+    for by in start_y..end_y {
+        for bx in start_x..end_x {
+            let permission = permissions[by * blocks_width + bx];
+            let offset_x = bx * 16;
+            let offset_y = by * 16;
+
+            // Get the background color
+            let color = match permission {
+                NULL_PERMISSION => style.null_color,
+                perm => style.colors.get(perm as usize).copied().unwrap_or(Color {
+                    r: 0xFF,
+                    g: 0xFF,
+                    b: 0xFF,
+                    a: DEFAULT_ALPHA,
+                }),
+            };
+            for y in 0..16 {
+                let row = (offset_y + y) * pixels_width + offset_x;
+                overlay_image_data[row..row + 16].fill(color);
+            }
+
+            // Null permissions have no level to print
+            if !glyphs || permission == NULL_PERMISSION {
+                continue;
+            }
+            draw_level(
+                style.glyph_color,
+                overlay_image_data,
+                offset_x,
+                offset_y,
+                pixels_width,
+                permission >> 1,
+                permission & 1 != 0,
+            );
+        }
+    }
+}
+
+/// Draws the level number centered in the block, surrounded by a box
+/// if the block is an obstacle.
+fn draw_level(
+    color: Color,
+    buffer: &mut [Color],
+    offset_x: usize,
+    offset_y: usize,
+    pixels_width: usize,
+    level: u8,
+    obstacle: bool,
+) {
+    let digits: Vec<usize> = level
+        .to_string()
+        .bytes()
+        .map(|digit| (digit - b'0') as usize)
+        .collect();
+
+    // Use bigger digits if they fit in the block
+    let text_width = digits.len() * 4 - 1;
+    let scale = if text_width * 2 <= 10 { 2 } else { 1 };
+    let start_x = offset_x + (16 - text_width * scale) / 2;
+    let start_y = offset_y + (16 - 5 * scale) / 2;
+
+    let mut put_pixel = |x: usize, y: usize| buffer[y * pixels_width + x] = color;
+
+    for (i, digit) in digits.iter().enumerate() {
+        for (row_index, row) in DIGITS[*digit].iter().enumerate() {
+            for column in 0..3 {
+                if row & (0b100 >> column) == 0 {
+                    continue;
+                }
+                for sy in 0..scale {
+                    for sx in 0..scale {
+                        put_pixel(
+                            start_x + (i * 4 + column) * scale + sx,
+                            start_y + row_index * scale + sy,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    // Draw the box around the block's border
+    if obstacle {
+        for i in 1..15 {
+            put_pixel(offset_x + i, offset_y + 1);
+            put_pixel(offset_x + i, offset_y + 14);
+            put_pixel(offset_x + 1, offset_y + i);
+            put_pixel(offset_x + 14, offset_y + i);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn color(r: u8) -> Color {
+        Color {
+            r,
+            g: 0,
+            b: 0,
+            a: 0xFF,
+        }
+    }
+
+    fn style() -> OverlayStyle {
+        OverlayStyle {
+            colors: (0..32).map(color).collect(),
+            null_color: color(0x88),
+            glyph_color: color(0xFF),
+        }
+    }
+
+    /// Renders a single block and returns its 16x16 pixels
+    fn render_block(permission: u8, glyphs: bool) -> Vec<Color> {
+        let mut pixels = vec![Color::default(); 16 * 16];
+        render_permissions(
+            &style(),
+            &mut pixels,
+            &[permission],
+            1,
+            16,
+            0,
+            0,
+            1,
+            1,
+            glyphs,
+        );
+        pixels
+    }
+
+    /// Returns the rows of the block as strings, with `#` for the glyph pixels
+    fn glyph_rows(pixels: &[Color]) -> Vec<String> {
+        pixels
+            .chunks(16)
+            .map(|row| {
+                row.iter()
+                    .map(|c| if c.r == 0xFF { '#' } else { '.' })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn blocks_are_filled_with_their_permission_color() {
+        for permission in 0..4 {
+            let pixels = render_block(permission, false);
+            assert!(pixels.iter().all(|c| c.r == permission));
+        }
+    }
+
+    #[test]
+    fn permissions_outside_the_table_are_white() {
+        let pixels = render_block(32, false);
+        assert!(pixels
+            .iter()
+            .all(|c| (c.r, c.g, c.b, c.a) == (0xFF, 0xFF, 0xFF, DEFAULT_ALPHA)));
+    }
+
+    #[test]
+    fn null_permissions_have_no_glyph() {
+        let pixels = render_block(NULL_PERMISSION, true);
+        assert!(pixels.iter().all(|c| c.r == 0x88));
+    }
+
+    #[test]
+    fn only_the_given_range_is_drawn() {
+        let mut pixels = vec![Color::default(); 32 * 16];
+        render_permissions(&style(), &mut pixels, &[1, 2], 2, 32, 1, 0, 2, 1, false);
+
+        for row in pixels.chunks(32) {
+            assert!(row[..16].iter().all(|c| c.a == 0));
+            assert!(row[16..].iter().all(|c| c.r == 2));
+        }
+    }
+
+    #[test]
+    fn single_digits_are_doubled_and_centered() {
+        // Level 1, not an obstacle
+        let rows = glyph_rows(&render_block(2, true));
+
+        #[rustfmt::skip]
+        let expected = [
+            "................",
+            "................",
+            "................",
+            ".......##.......",
+            ".......##.......",
+            ".....####.......",
+            ".....####.......",
+            ".......##.......",
+            ".......##.......",
+            ".......##.......",
+            ".......##.......",
+            ".....######.....",
+            ".....######.....",
+            "................",
+            "................",
+            "................",
+        ];
+        assert_eq!(rows, expected);
+    }
+
+    #[test]
+    fn obstacles_are_boxed() {
+        // Level 0, obstacle
+        let rows = glyph_rows(&render_block(1, true));
+
+        assert_eq!(rows[0], "................");
+        assert_eq!(rows[1], ".##############.");
+        assert_eq!(rows[2], ".#............#.");
+        assert_eq!(rows[14], ".##############.");
+        assert_eq!(rows[15], "................");
+    }
+
+    #[test]
+    fn two_digits_are_not_scaled() {
+        // Level 10, not an obstacle
+        let mut pixels = vec![Color::default(); 16 * 16];
+        render_permissions(&style(), &mut pixels, &[20], 1, 16, 0, 0, 1, 1, true);
+        let rows = glyph_rows(&pixels);
+
+        assert_eq!(rows[5], ".....#..###.....");
+        assert_eq!(rows[6], "....##..#.#.....");
+        assert_eq!(rows[9], "....###.###.....");
+        assert_eq!(rows[10], "................");
+    }
+}
@@ -0,0 +1,91 @@
+use crate::data::Color;
+
+/// A transform applied to the colors of the palettes, used to preview
+/// flashbacks, night, cave darkness and weather effects.
+#[derive(Copy, Clone, Default)]
+pub struct Tint {
+    /// Convert the colors to grayscale before blending
+    pub grayscale: bool,
+    /// The color to blend with
+    pub blend_color: Color,
+    /// How much of the blend color to use, from 0 (none) to 16 (all)
+    pub blend_amount: u8,
+}
+
+impl Tint {
+    /// Returns the tinted color.
+    pub fn apply(&self, color: Color) -> Color {
+        let Color {
+            mut r,
+            mut g,
+            mut b,
+            a,
+        } = color;
+
+        // Same weights as the game's grayscale tint
+        if self.grayscale {
+            let gray = ((r as u32 * 76 + g as u32 * 151 + b as u32 * 29) >> 8) as u8;
+            (r, g, b) = (gray, gray, gray);
+        }
+
+        // Same formula as the game's palette blending
+        let amount = self.blend_amount.min(16) as i32;
+        let blend =
+            |from: u8, to: u8| (from as i32 + (((to as i32 - from as i32) * amount) >> 4)) as u8;
+
+        Color {
+            r: blend(r, self.blend_color.r),
+            g: blend(g, self.blend_color.g),
+            b: blend(b, self.blend_color.b),
+            a,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn color([r, g, b]: [u8; 3]) -> Color {
+        Color { r, g, b, a: 255 }
+    }
+
+    /// Tints and the colors they turn [0x40, 0x80, 0xC0] into.
+    ///
+    /// The `PaletteTint` of the backend is tested against the same cases,
+    /// so that previews and the editor agree.
+    const CASES: [(bool, [u8; 3], u8, [u8; 3]); 7] = [
+        (false, [0xF8, 0x00, 0x10], 0, [0x40, 0x80, 0xC0]),
+        (false, [0xF8, 0x00, 0x10], 8, [0x9C, 0x40, 0x68]),
+        (false, [0xF8, 0x00, 0x10], 16, [0xF8, 0x00, 0x10]),
+        // Amounts over 16 use the whole blend color
+        (false, [0xF8, 0x00, 0x10], 20, [0xF8, 0x00, 0x10]),
+        (true, [0x00, 0x00, 0x00], 0, [0x74, 0x74, 0x74]),
+        (true, [0x00, 0x00, 0x00], 8, [0x3A, 0x3A, 0x3A]),
+        (true, [0xF8, 0xF8, 0xF8], 16, [0xF8, 0xF8, 0xF8]),
+    ];
+
+    #[test]
+    fn applies_the_tints() {
+        for (grayscale, blend_color, blend_amount, expected) in CASES {
+            let tint = Tint {
+                grayscale,
+                blend_color: color(blend_color),
+                blend_amount,
+            };
+            let Color { r, g, b, .. } = tint.apply(color([0x40, 0x80, 0xC0]));
+            assert_eq!([r, g, b], expected, "{:?}", (grayscale, blend_amount));
+        }
+    }
+
+    #[test]
+    fn keeps_the_alpha() {
+        let tint = Tint {
+            grayscale: true,
+            blend_color: color([0xF8, 0xF8, 0xF8]),
+            blend_amount: 16,
+        };
+
+        assert_eq!(tint.apply(Color::default()).a, 0);
+    }
+}